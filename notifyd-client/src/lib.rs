@@ -0,0 +1,198 @@
+use std::fmt;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+pub mod models;
+
+pub use models::{HistoryEntry, Notification, Outcome, Priority, QueueEntry, Segment, Speed, SpeechParams, Status};
+
+/****************
+ * DEFINE ERROR *
+ ****************/
+
+#[derive(Debug)]
+pub enum Error
+{
+    /* The daemon could not be reached, or its answer could not be read */
+    Http(reqwest::Error),
+    /* The daemon refused the request, with its reason and error */
+    Daemon { status : u16, reason : String, err : String }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{}", e),
+            Error::Daemon { status, reason, err } if err.is_empty() => write!(f, "HTTP {} : {}", status, reason),
+            Error::Daemon { status, reason, err } => write!(f, "HTTP {} : {} : {}", status, reason, err)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Daemon { .. } => None
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e : reqwest::Error) -> Error
+    {
+        Error::Http(e)
+    }
+}
+
+/**********
+ * CLIENT *
+ **********/
+
+#[derive(Clone)]
+pub struct Client
+{
+    base : String,
+    token : Option<String>,
+    http : reqwest::Client
+}
+
+impl Client
+{
+    /* Client of the daemon at the given URL, e.g. "http://pi.local:8090" or "https://pi.local/kitchen" for an instance */
+    pub fn new(base : &str) -> Client
+    {
+        Client {
+            base : base.trim_end_matches('/').to_string(),
+            token : None,
+            http : reqwest::Client::new()
+        }
+    }
+
+    /* Bearer token sent along, required once the daemon has one */
+    pub fn with_token(self : Self, token : &str) -> Client
+    {
+        Client {
+            token : Some(token.to_string()),
+            ..self
+        }
+    }
+
+    fn request(self : &Self, method : reqwest::Method, path : &str) -> reqwest::RequestBuilder
+    {
+        let builder = self.http.request(method, format!("{}{}", self.base, path));
+
+        match &self.token {
+            Some(t) => builder.bearer_auth(t),
+            None => builder
+        }
+    }
+
+    /* Refusals carry the reason and error of the daemon as JSON */
+    async fn send(self : &Self, builder : reqwest::RequestBuilder) -> Result<reqwest::Response, Error>
+    {
+        let response = builder.send().await?;
+        let status = response.status();
+
+        if status.is_success()
+        {
+            return Ok(response);
+        }
+
+        let text = response.text().await?;
+
+        Err(match serde_json::from_str::<Outcome>(&text) {
+            Ok(o) => Error::Daemon { status : status.as_u16(), reason : o.reason, err : o.err },
+            Err(_) => Error::Daemon { status : status.as_u16(), reason : text.trim().to_string(), err : String::new() }
+        })
+    }
+
+    async fn get<T : DeserializeOwned>(self : &Self, path : &str, query : &[(&str, String)]) -> Result<T, Error>
+    {
+        Ok(self.send(self.request(reqwest::Method::GET, path).query(query)).await?.json().await?)
+    }
+
+    async fn post<B : Serialize, T : DeserializeOwned>(self : &Self, path : &str, body : &B) -> Result<T, Error>
+    {
+        Ok(self.send(self.request(reqwest::Method::POST, path).json(body)).await?.json().await?)
+    }
+
+    /* Queue the notification, or with "wait" deliver it, on its target */
+    pub async fn notify(self : &Self, notification : &Notification) -> Result<Outcome, Error>
+    {
+        self.post("/notify", notification).await
+    }
+
+    /* Queue the notification under a configured topic, whose defaults and target apply */
+    pub async fn notify_topic(self : &Self, topic : &str, notification : &Notification) -> Result<Outcome, Error>
+    {
+        self.post(&format!("/notify/t/{}", topic), notification).await
+    }
+
+    /* Speak on the speaker of the host running the daemon */
+    pub async fn speak(self : &Self, text : &str, params : &SpeechParams) -> Result<Outcome, Error>
+    {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            text : &'a str,
+            #[serde(flatten)]
+            params : &'a SpeechParams
+        }
+
+        self.post("/action/speak", &Json { text : text, params : params }).await
+    }
+
+    /* Synthesize without playing, returning WAV audio */
+    pub async fn synthesize(self : &Self, text : &str, params : &SpeechParams) -> Result<Vec<u8>, Error>
+    {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            text : &'a str,
+            #[serde(flatten)]
+            params : &'a SpeechParams
+        }
+
+        let builder = self.request(reqwest::Method::POST, "/action/synthesize")
+                          .header(reqwest::header::ACCEPT, "audio/wav")
+                          .json(&Json { text : text, params : params });
+
+        Ok(self.send(builder).await?.bytes().await?.to_vec())
+    }
+
+    /* Cast to a device given by UUID, name or alias */
+    pub async fn cast(self : &Self, uid : &str, text : &str, params : &SpeechParams) -> Result<Outcome, Error>
+    {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            text : &'a str,
+            uid : &'a str,
+            #[serde(flatten)]
+            params : &'a SpeechParams
+        }
+
+        self.post("/action/cast", &Json { text : text, uid : uid, params : params }).await
+    }
+
+    pub async fn status(self : &Self) -> Result<Status, Error>
+    {
+        self.get("/status", &[]).await
+    }
+
+    /* Recorded notifications, oldest first: the last "limit" ones, recorded from "since" (RFC 3339 or unix time) on */
+    pub async fn history(self : &Self, limit : Option<usize>, since : Option<&str>) -> Result<Vec<HistoryEntry>, Error>
+    {
+        let mut query = Vec::new();
+
+        if let Some(l) = limit
+        {
+            query.push(("limit", l.to_string()));
+        }
+
+        if let Some(s) = since
+        {
+            query.push(("since", s.to_string()));
+        }
+
+        self.get("/history", &query).await
+    }
+}
@@ -0,0 +1,212 @@
+use serde::{Serialize, Deserialize};
+
+/**********
+ * MODELS *
+ **********/
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority
+{
+    Low,
+    Normal,
+    High,
+    Urgent
+}
+
+/* Named speech rates */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Speed
+{
+    Slow,
+    Normal,
+    Fast,
+    Urgent
+}
+
+/* Part of a text spoken in its own language or voice */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Segment
+{
+    #[serde(default)]
+    pub text : String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voice : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phonemes : Option<String>
+}
+
+/* Optional per-request synthesis settings, the daemon defaults applying to those left out */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SpeechParams
+{
+    /* Topic whose configured defaults apply to this request */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed : Option<Speed>,
+    /* Speed-up factor from 0.25 to 4, overriding the speed preset */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate : Option<f32>,
+    /* Relative to the natural pitch of the voice, from 0.5 to 2 */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pitch : Option<f32>,
+    /* Volume factor from 0 to 4, 0.5 halving the amplitude */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gain : Option<f32>,
+    /* Level of the speaker or device while this plays, from 0.0 to 1.0 */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume : Option<f32>,
+    /* espeak voice variant such as "f3", "m7", "whisper" or "croak" */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang : Option<String>,
+    /* Either an engine specific voice name or an "engine:voice" spec */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voice : Option<String>,
+    /* Phoneme mnemonics spoken instead of the text, for engines supporting it */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phonemes : Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments : Vec<Segment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority : Option<Priority>,
+    /* Said before the text, e.g. "From Grafana:" */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix : Option<String>,
+    /* Pending notifications with the same key are replaced by this one */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replace_key : Option<String>,
+    /* Notifications with the same key arriving within the deduplication window are merged into the first one */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dedup_key : Option<String>,
+    /* Answer once the notification was delivered instead of once queued */
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wait : bool,
+    /* Times the notification is played in a row, from 1 to 10 */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat : Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_gap_ms : Option<u64>,
+    /* Stop the notification being played and go before the pending ones */
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupt : bool
+}
+
+/* Body of /notify */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Notification
+{
+    #[serde(default)]
+    pub text : String,
+    /* A device, a group or "local", the default target of the daemon otherwise */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target : Option<String>,
+    /* Base64 encoded WAV, MP3, OGG or FLAC played instead of the text */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_b64 : Option<String>,
+    /* Send later, after a delay or at an RFC 3339 timestamp */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay_seconds : Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at : Option<String>,
+    /* Idempotency key: a retry carrying it gets the first answer back instead of being spoken twice */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id : Option<String>,
+    #[serde(flatten)]
+    pub params : SpeechParams
+}
+
+impl Notification
+{
+    pub fn new(text : &str) -> Notification
+    {
+        Notification {
+            text : text.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/* Answer of the speech endpoints, the fields present depending on how far the request went */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Outcome
+{
+    pub success : bool,
+    #[serde(default)]
+    pub reason : String,
+    #[serde(default)]
+    pub err : String,
+    /* Job queued, whose progress /jobs/<id> reports */
+    pub id : Option<u64>,
+    pub status_url : Option<String>,
+    /* Where the job stands, when answering a retry */
+    pub status : Option<String>,
+    /* Notification sent later */
+    pub schedule_id : Option<u64>,
+    pub due : Option<String>,
+    /* Synthesis metadata, once delivered */
+    pub audio_url : Option<String>,
+    pub duration_ms : Option<u64>,
+    pub synthesis_ms : Option<u64>,
+    pub engine : Option<String>,
+    pub voice : Option<String>,
+    pub target : Option<String>
+}
+
+/* Item of the playback queue */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueueEntry
+{
+    pub id : u64,
+    pub position : usize,
+    pub priority : Priority,
+    pub target : String,
+    pub text : String,
+    /* Estimated time before it is spoken */
+    pub eta_ms : u64,
+    pub replace_key : Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Status
+{
+    pub version : String,
+    pub instance : Option<String>,
+    pub uptime_secs : u64,
+    pub playing : Option<QueueEntry>,
+    pub pending : Vec<QueueEntry>,
+    pub engine : String,
+    pub voice : Option<String>,
+    pub lang : String,
+    pub target : String,
+    pub paused : bool,
+    pub do_not_disturb : bool
+}
+
+/* Notification recorded by the daemon */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry
+{
+    /* RFC 3339 */
+    pub time : String,
+    pub id : Option<u64>,
+    pub text : String,
+    pub topic : Option<String>,
+    pub target : String,
+    pub priority : Priority,
+    /* "done", "failed", "replaced", "interrupted", "stopped", "deduplicated", "deferred", "suppressed", "digested", "digest", "dropped" or "expired" */
+    pub status : String,
+    /* Notifications summarized by a digest */
+    #[serde(default)]
+    pub items : Vec<String>,
+    pub err : Option<String>,
+    /* From the submission to the end of the playback */
+    pub latency_ms : Option<u64>,
+    /* Where the archived audio can be downloaded */
+    pub audio_url : Option<String>
+}
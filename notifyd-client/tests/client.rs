@@ -0,0 +1,75 @@
+use std::io::Write;
+use std::sync::Arc;
+use notifyd_client::{Client, Error, Notification, Priority, SpeechParams};
+use notifyd_core::config::Config;
+use notifyd_core::notifyd::Notifyd;
+
+/* A daemon serving on a free port, requiring the given token */
+async fn daemon(token : &str) -> String
+{
+    let dir = tempdir::TempDir::new("notifyd-test").unwrap();
+    let path = dir.path().join("notifyd.toml");
+
+    std::fs::File::create(&path).unwrap()
+        .write_all(format!("database = \":memory:\"\n[auth]\nadmin_token = \"{}\"\n", token).as_bytes()).unwrap();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config = Config::load(&path).unwrap();
+    let notifyd = Notifyd::new(port as u32, String::from("Use Local Speaker"), None, config).unwrap();
+
+    std::thread::spawn(move || {
+        Notifyd::run(Arc::new(notifyd));
+    });
+
+    let base = format!("http://127.0.0.1:{}", port);
+
+    for _ in 0..50
+    {
+        if reqwest::get(format!("{}/readyz", base)).await.is_ok()
+        {
+            break;
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    base
+}
+
+#[tokio::test]
+async fn client_submits_and_reads_back()
+{
+    let base = daemon("s3cret").await;
+
+    match Client::new(&base).status().await {
+        Err(Error::Daemon { status, .. }) => assert_eq!(status, 401),
+        _ => panic!("The status was read without a token")
+    }
+
+    let client = Client::new(&base).with_token("s3cret");
+
+    let status = client.status().await.unwrap();
+    assert_eq!(status.target, "Use Local Speaker");
+
+    let queued = client.notify(&Notification {
+        target : Some(String::from("local")),
+        params : SpeechParams { priority : Some(Priority::High), ..Default::default() },
+        ..Notification::new("Dinner is ready")
+    }).await.unwrap();
+
+    assert!(queued.success);
+    assert!(queued.id.is_some());
+
+    match client.notify(&Notification {
+        params : SpeechParams { volume : Some(3.0), ..Default::default() },
+        ..Notification::new("Too loud")
+    }).await {
+        Err(Error::Daemon { status, err, .. }) => {
+            assert_eq!(status, 400);
+            assert!(err.contains("volume"));
+        },
+        _ => panic!("An out of range volume was accepted")
+    }
+
+    assert!(client.history(Some(10), None).await.unwrap().len() <= 10);
+}
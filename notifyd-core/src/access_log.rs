@@ -0,0 +1,114 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use rouille::{Response, Request};
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use std::io::Write;
+
+/**************
+ * ACCESS LOG *
+ **************/
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat
+{
+    Json,
+    Common
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AccessLogConfig
+{
+    pub(crate) format : AccessLogFormat,
+    /* File to append to, "-" for stdout */
+    pub(crate) path : String
+}
+
+impl Default for AccessLogConfig
+{
+    fn default() -> Self
+    {
+        AccessLogConfig {
+            format : AccessLogFormat::Json,
+            path : String::from("-")
+        }
+    }
+}
+
+pub struct AccessLog
+{
+    pub(crate) format : AccessLogFormat,
+    pub(crate) out : Mutex<Box<dyn Write + Send>>
+}
+
+impl AccessLog
+{
+    pub(crate) fn new(config : &AccessLogConfig) -> Result<AccessLog, Box<dyn std::error::Error>>
+    {
+        let out : Box<dyn Write + Send> = if config.path == "-"
+        {
+            Box::new(std::io::stdout())
+        }
+        else
+        {
+            Box::new(std::fs::OpenOptions::new().create(true).append(true).open(&config.path)?)
+        };
+
+        Ok(AccessLog {
+            format : config.format,
+            out : Mutex::new(out)
+        })
+    }
+
+    /* The source is the client, which differs from the peer of relayed connections */
+    pub(crate) fn log(self : &Self, request : &Request, source : &SocketAddr, response : &Response, elapsed : Duration)
+    {
+        let header = |name : &str| -> Option<String> {
+            response.headers.iter()
+                            .find(|(h, _)| h.eq_ignore_ascii_case(name))
+                            .map(|(_, v)| v.to_string())
+        };
+
+        /* Gotify clients may put their token in the URL */
+        let path = match request.get_param("token") {
+            Some(t) if !t.is_empty() => request.raw_url().replace(&t, "REDACTED"),
+            _ => request.raw_url().to_string()
+        };
+
+        let job = header("X-Job-Id");
+        let identity = header("X-Auth-Identity");
+        let latency_ms = elapsed.as_secs_f64() * 1000.0;
+
+        let line = match self.format {
+            AccessLogFormat::Json => {
+                serde_json::json!({
+                    "time" : chrono::Local::now().to_rfc3339(),
+                    "method" : request.method(),
+                    "path" : path,
+                    "source" : source.to_string(),
+                    "identity" : identity,
+                    "status" : response.status_code,
+                    "latency_ms" : latency_ms,
+                    "job" : job
+                }).to_string()
+            },
+            AccessLogFormat::Common => {
+                format!("{} - {} [{}] \"{} {}\" {} - {:.1}ms {}",
+                        source.ip(),
+                        identity.unwrap_or(String::from("-")),
+                        chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+                        request.method(),
+                        path,
+                        response.status_code,
+                        latency_ms,
+                        job.unwrap_or(String::from("-")))
+            }
+        };
+
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
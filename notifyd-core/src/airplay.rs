@@ -0,0 +1,65 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use which::which;
+use crate::audio::run_player;
+use crate::error::NotifydError;
+
+/***********
+ * AIRPLAY *
+ ***********/
+
+/* Kind of the registry devices played over AirPlay */
+pub const AIRPLAY_KIND : &str = "airplay";
+
+/* Time given to a sender beyond the length of the notification before it is killed: connecting and
+   pairing with the speaker take a few seconds, and AirPlay buffers about two more of audio */
+pub const AIRPLAY_MARGIN : Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AirPlayConfig
+{
+    /* RAOP sender, "{host}", "{file}" and "{volume}" (0 to 100) being substituted */
+    pub(crate) command : Vec<String>,
+    /* Level used when the notification gives none, from 0.0 to 1.0 */
+    pub(crate) volume : f32
+}
+
+impl Default for AirPlayConfig
+{
+    fn default() -> Self
+    {
+        AirPlayConfig {
+            command : vec![String::from("raop_play"), String::from("-v"), String::from("{volume}"), String::from("{host}"), String::from("{file}")],
+            volume : 0.5
+        }
+    }
+}
+
+impl AirPlayConfig
+{
+    pub(crate) fn check(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        match self.command.first() {
+            None => Err(NotifydError::new("No AirPlay sender command is configured")),
+            Some(c) => {
+                which(c).map_err(|_| NotifydError::new(format!("AirPlay sender {} is not installed", c).as_str()))?;
+                Ok(())
+            }
+        }
+    }
+
+    /* Stream the file to the speaker until done, stopping or suspending the sender from the flags */
+    pub(crate) fn play(self : &Self, host : &str, file : &str, volume : Option<f32>, length : Duration, interrupt : &AtomicBool, paused : &AtomicBool) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.check()?;
+
+        let volume = (volume.unwrap_or(self.volume) * 100.0).round().clamp(0.0, 100.0).to_string();
+        let cmd : Vec<String> = self.command.iter()
+                                    .map(|a| a.replace("{host}", host).replace("{file}", file).replace("{volume}", &volume))
+                                    .collect();
+
+        run_player(&cmd, length + AIRPLAY_MARGIN, interrupt, paused)
+    }
+}
@@ -0,0 +1,227 @@
+use serde::{Serialize, Deserialize};
+use crate::cloud::curl;
+use crate::error::NotifydError;
+
+/*****************
+ * ANNOUNCEMENTS *
+ *****************/
+
+/* Language part of a locale, "fr-FR" giving "fr" */
+pub fn lang_prefix(lang : &str) -> String
+{
+    lang.split(['-', '_']).next().unwrap_or("en").to_lowercase()
+}
+
+pub fn time_sentence(lang : &str, now : &chrono::DateTime<chrono::Local>) -> String
+{
+    use chrono::Timelike;
+
+    let (h, m) = (now.hour(), now.minute());
+
+    match lang_prefix(lang).as_str() {
+        "fr" => {
+            if m == 0 { format!("Il est {} heures.", h) } else { format!("Il est {} heures {}.", h, m) }
+        },
+        "de" => {
+            if m == 0 { format!("Es ist {} Uhr.", h) } else { format!("Es ist {} Uhr {}.", h, m) }
+        },
+        "es" => {
+            let hours = if h % 12 == 1 { String::from("Es la una") } else { format!("Son las {}", if h % 12 == 0 { 12 } else { h % 12 }) };
+            if m == 0 { format!("{} en punto.", hours) } else { format!("{} y {}.", hours, m) }
+        },
+        _ => format!("It is {}.", now.format("%-I:%M %p"))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Condition
+{
+    Clear,
+    PartlyCloudy,
+    Cloudy,
+    Fog,
+    Drizzle,
+    Rain,
+    Showers,
+    Snow,
+    Thunderstorm
+}
+
+impl Condition
+{
+    /* WMO weather interpretation codes, as returned by open-meteo */
+    pub(crate) fn from_wmo(code : i64) -> Condition
+    {
+        match code {
+            0 => Condition::Clear,
+            1 | 2 => Condition::PartlyCloudy,
+            3 => Condition::Cloudy,
+            45 | 48 => Condition::Fog,
+            51..=57 => Condition::Drizzle,
+            61..=67 => Condition::Rain,
+            71..=77 | 85 | 86 => Condition::Snow,
+            80..=82 => Condition::Showers,
+            95..=99 => Condition::Thunderstorm,
+            _ => Condition::Cloudy
+        }
+    }
+
+    /* OpenWeatherMap condition ids */
+    pub(crate) fn from_owm(id : i64) -> Condition
+    {
+        match id {
+            200..=299 => Condition::Thunderstorm,
+            300..=399 => Condition::Drizzle,
+            500..=504 => Condition::Rain,
+            511..=599 => Condition::Showers,
+            600..=699 => Condition::Snow,
+            700..=799 => Condition::Fog,
+            800 => Condition::Clear,
+            801 | 802 => Condition::PartlyCloudy,
+            _ => Condition::Cloudy
+        }
+    }
+
+    pub(crate) fn describe(self : &Self, lang : &str) -> &'static str
+    {
+        let names = match lang_prefix(lang).as_str() {
+            "fr" => ["ciel dégagé", "quelques nuages", "ciel couvert", "brouillard", "bruine", "pluie", "averses", "neige", "orages"],
+            "de" => ["klarer Himmel", "leicht bewölkt", "bewölkt", "Nebel", "Nieselregen", "Regen", "Schauer", "Schnee", "Gewitter"],
+            "es" => ["cielo despejado", "algunas nubes", "cielo cubierto", "niebla", "llovizna", "lluvia", "chubascos", "nieve", "tormentas"],
+            _ => ["clear skies", "a few clouds", "cloudy skies", "fog", "drizzle", "rain", "showers", "snow", "thunderstorms"]
+        };
+
+        names[*self as usize]
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WeatherProvider
+{
+    OpenMeteo,
+    Openweathermap
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WeatherConfig
+{
+    pub(crate) provider : WeatherProvider,
+    /* Required by openweathermap only */
+    pub(crate) api_key : Option<String>,
+    pub(crate) latitude : Option<f64>,
+    pub(crate) longitude : Option<f64>,
+    /* Name of the place, as spoken */
+    pub(crate) location : Option<String>,
+    /* "metric" or "imperial" */
+    pub(crate) units : String
+}
+
+impl Default for WeatherConfig
+{
+    fn default() -> Self
+    {
+        WeatherConfig {
+            provider : WeatherProvider::OpenMeteo,
+            api_key : None,
+            latitude : None,
+            longitude : None,
+            location : None,
+            units : String::from("metric")
+        }
+    }
+}
+
+pub struct WeatherReport
+{
+    pub(crate) temp : f64,
+    pub(crate) min : f64,
+    pub(crate) max : f64,
+    pub(crate) condition : Condition
+}
+
+impl WeatherConfig
+{
+    /* The URL may carry the API key, it goes to curl over stdin and stays out of the errors */
+    pub(crate) fn fetch_json(url : &str) -> Result<serde_json::Value, Box<dyn std::error::Error>>
+    {
+        let (status, body) = curl(url, &[], None).map_err(|_| NotifydError::new("Weather provider request failed"))?;
+
+        if !(200..300).contains(&status)
+        {
+            return Err(NotifydError::new(format!("Weather provider request failed (HTTP {})", status).as_str()));
+        }
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub(crate) fn fetch(self : &Self) -> Result<WeatherReport, Box<dyn std::error::Error>>
+    {
+        let (lat, lon) = match (self.latitude, self.longitude) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => return Err(NotifydError::new("No latitude and longitude configured in the [weather] section"))
+        };
+
+        let imperial = self.units == "imperial";
+        let num = |v : &serde_json::Value| -> Result<f64, Box<dyn std::error::Error>> {
+            v.as_f64().ok_or_else(|| NotifydError::new("Unexpected answer from the weather provider"))
+        };
+
+        match self.provider {
+            WeatherProvider::OpenMeteo => {
+                let url = format!("https://api.open-meteo.com/v1/forecast?latitude={}&longitude={}&current=temperature_2m,weather_code&daily=temperature_2m_max,temperature_2m_min&timezone=auto&forecast_days=1{}",
+                                  lat, lon, if imperial { "&temperature_unit=fahrenheit" } else { "" });
+                let v = WeatherConfig::fetch_json(&url)?;
+
+                Ok(WeatherReport {
+                    temp : num(&v["current"]["temperature_2m"])?,
+                    min : num(&v["daily"]["temperature_2m_min"][0])?,
+                    max : num(&v["daily"]["temperature_2m_max"][0])?,
+                    condition : Condition::from_wmo(v["current"]["weather_code"].as_i64().unwrap_or(3))
+                })
+            },
+            WeatherProvider::Openweathermap => {
+                let key = match &self.api_key {
+                    Some(k) => k,
+                    None => return Err(NotifydError::new("openweathermap requires an api_key in the [weather] section"))
+                };
+                let url = format!("https://api.openweathermap.org/data/2.5/weather?lat={}&lon={}&appid={}&units={}",
+                                  lat, lon, key, if imperial { "imperial" } else { "metric" });
+                let v = WeatherConfig::fetch_json(&url)?;
+
+                Ok(WeatherReport {
+                    temp : num(&v["main"]["temp"])?,
+                    min : num(&v["main"]["temp_min"])?,
+                    max : num(&v["main"]["temp_max"])?,
+                    condition : Condition::from_owm(v["weather"][0]["id"].as_i64().unwrap_or(804))
+                })
+            }
+        }
+    }
+}
+
+pub fn weather_sentence(lang : &str, location : Option<&String>, r : &WeatherReport) -> String
+{
+    let (temp, min, max) = (r.temp.round() as i64, r.min.round() as i64, r.max.round() as i64);
+    let cond = r.condition.describe(lang);
+
+    match lang_prefix(lang).as_str() {
+        "fr" => {
+            let start = match location { Some(l) => format!("À {}, il fait", l), None => String::from("Il fait") };
+            format!("{} {} degrés, {}. Aujourd'hui, entre {} et {} degrés.", start, temp, cond, min, max)
+        },
+        "de" => {
+            let start = match location { Some(l) => format!("In {} sind es", l), None => String::from("Es sind") };
+            format!("{} {} Grad, {}. Heute zwischen {} und {} Grad.", start, temp, cond, min, max)
+        },
+        "es" => {
+            let start = match location { Some(l) => format!("En {} hace", l), None => String::from("Hace") };
+            format!("{} {} grados, {}. Hoy, entre {} y {} grados.", start, temp, cond, min, max)
+        },
+        _ => {
+            let start = match location { Some(l) => format!("In {}, it is", l), None => String::from("It is") };
+            format!("{} {} degrees with {}. Today, between {} and {} degrees.", start, temp, cond, min, max)
+        }
+    }
+}
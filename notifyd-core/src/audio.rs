@@ -0,0 +1,844 @@
+use which::which;
+use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "soloud")]
+use soloud::*;
+use std::time::{Instant, Duration};
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tempdir::TempDir;
+use crate::cloud::{curl, curl_option};
+use crate::error::{NotifydError, PlaybackInterrupted, PlaybackStalled};
+use crate::now_in_usecs;
+use crate::queue::Priority;
+use crate::tts::TtsSentence;
+
+/*****************
+ * AUDIO HELPERS *
+ *****************/
+
+/* Extension of the audio format found in the first bytes of a file */
+pub fn sniff_audio(content : &[u8]) -> Option<&'static str>
+{
+    if content.len() >= 12 && &content[0..4] == b"RIFF" && &content[8..12] == b"WAVE"
+    {
+        return Some("wav");
+    }
+
+    if content.starts_with(b"OggS")
+    {
+        return Some("ogg");
+    }
+
+    if content.starts_with(b"fLaC")
+    {
+        return Some("flac");
+    }
+
+    /* Either an ID3 tag or an MPEG frame sync */
+    if content.starts_with(b"ID3") || (content.len() >= 2 && content[0] == 0xFF && (content[1] & 0xE0) == 0xE0)
+    {
+        return Some("mp3");
+    }
+
+    None
+}
+
+pub fn audio_mime(path : &Path) -> &'static str
+{
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("opus") => "audio/ogg; codecs=opus",
+        Some("flac") => "audio/flac",
+        _ => "audio/wav"
+    }
+}
+
+/* Content and format of the audio behind an http(s) URL, refused when the server
+   says it is something else or when it exceeds max_bytes */
+pub(crate) fn fetch_audio(url : &str, max_bytes : usize) -> Result<(Vec<u8>, &'static str), Box<dyn std::error::Error>>
+{
+    /* Dropped, and removed, once the headers are read */
+    let scratch = TempDir::new("notifydurl")?;
+    let headers = scratch.path().join("headers");
+
+    let options = vec![
+        String::from("location"),
+        curl_option("max-redirs", "5"),
+        curl_option("proto", "=http,https"),
+        curl_option("proto-redir", "=http,https"),
+        curl_option("max-filesize", max_bytes.to_string().as_str()),
+        curl_option("dump-header", headers.to_string_lossy().as_ref())
+    ];
+
+    let (code, content) = curl(url, &options, None)?;
+
+    if !(200..300).contains(&code)
+    {
+        return Err(NotifydError::new(format!("{} answered HTTP {}", url, code).as_str()));
+    }
+
+    if content.len() > max_bytes
+    {
+        return Err(NotifydError::new(format!("{} exceeds {} bytes", url, max_bytes).as_str()));
+    }
+
+    /* The last one, redirections dumping their headers too */
+    let dumped = std::fs::read_to_string(&headers)?;
+    let content_type = dumped.lines()
+                             .rev()
+                             .filter_map(|l| l.split_once(':'))
+                             .find(|(k, _)| k.trim().eq_ignore_ascii_case("content-type"))
+                             .map(|(_, v)| v.trim().to_lowercase());
+
+    if let Some(t) = content_type
+    {
+        let mime = t.split(';').next().unwrap_or_default().trim().to_string();
+
+        if !mime.starts_with("audio/") && mime != "application/ogg" && mime != "application/octet-stream"
+        {
+            return Err(NotifydError::new(format!("{} is {}, not audio", url, mime).as_str()));
+        }
+    }
+
+    match sniff_audio(&content) {
+        Some(ext) => Ok((content, ext)),
+        None => Err(NotifydError::new(format!("{} is not WAV, MP3, OGG or FLAC", url).as_str()))
+    }
+}
+
+/* What a "Range" request header asks of a file of total bytes, a single range being honoured */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ByteRange
+{
+    Full,
+    /* First and last byte, both included */
+    Partial(u64, u64),
+    Unsatisfiable
+}
+
+impl ByteRange
+{
+    pub fn parse(header : Option<&str>, total : u64) -> ByteRange
+    {
+        /* Other units, several ranges and broken headers get the whole file */
+        let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+            Some(s) if !s.contains(',') => s.trim(),
+            _ => return ByteRange::Full
+        };
+
+        let (first, last) = match spec.split_once('-') {
+            Some(p) => p,
+            None => return ByteRange::Full
+        };
+
+        match (first.parse::<u64>(), last.parse::<u64>()) {
+            /* "-500" are the last 500 bytes */
+            (Err(_), Ok(n)) if first.is_empty() => {
+                if n == 0 || total == 0
+                {
+                    ByteRange::Unsatisfiable
+                }
+                else
+                {
+                    ByteRange::Partial(total - n.min(total), total - 1)
+                }
+            },
+            (Ok(start), _) if start >= total => ByteRange::Unsatisfiable,
+            (Ok(start), Err(_)) if last.is_empty() => ByteRange::Partial(start, total - 1),
+            (Ok(start), Ok(end)) if start <= end => ByteRange::Partial(start, end.min(total - 1)),
+            _ => ByteRange::Full
+        }
+    }
+}
+
+/* Decoded 16-bit PCM audio, enough to stitch WAV files together */
+#[derive(Clone)]
+pub struct PcmAudio
+{
+    pub(crate) rate : u32,
+    pub(crate) channels : u16,
+    pub(crate) samples : Vec<i16>
+}
+
+impl PcmAudio
+{
+    /* WAV files are read as they are, other formats go through ffmpeg */
+    pub(crate) fn decode(path : &Path, tmpdir : &Path) -> Result<PcmAudio, Box<dyn std::error::Error>>
+    {
+        let mut content = Vec::new();
+        File::open(path)?.read_to_end(&mut content)?;
+
+        match sniff_audio(&content) {
+            Some("wav") => PcmAudio::load(&path.to_string_lossy()),
+            Some(_) => {
+                let ffmpeg = match which("ffmpeg") {
+                    Ok(p) => p,
+                    Err(_) => return Err(NotifydError::new(format!("Cannot locate ffmpeg in path to decode {}", path.to_string_lossy()).as_str()))
+                };
+
+                let out = tmpdir.join(format!("decode-{}.wav", now_in_usecs()));
+
+                let ret = Command::new(ffmpeg)
+                            .args(["-y", "-loglevel", "error", "-i"])
+                            .arg(path)
+                            .args(["-c:a", "pcm_s16le"])
+                            .arg(&out)
+                            .output()?;
+
+                if !ret.status.success()
+                {
+                    return Err(NotifydError::new(String::from_utf8_lossy(&ret.stderr).trim()));
+                }
+
+                let audio = PcmAudio::load(&out.to_string_lossy());
+                let _ = std::fs::remove_file(&out);
+                audio
+            },
+            None => Err(NotifydError::new(format!("{} is not WAV, MP3, OGG or FLAC", path.to_string_lossy()).as_str()))
+        }
+    }
+
+    pub(crate) fn load(path : &str) -> Result<PcmAudio, Box<dyn std::error::Error>>
+    {
+        let mut content = Vec::new();
+        File::open(path)?.read_to_end(&mut content)?;
+
+        let le16 = |off : usize| -> u16 { u16::from_le_bytes([content[off], content[off + 1]]) };
+        let le32 = |off : usize| -> u32 {
+            u32::from_le_bytes([content[off], content[off + 1], content[off + 2], content[off + 3]])
+        };
+
+        if content.len() < 12 || &content[0..4] != b"RIFF" || &content[8..12] != b"WAVE"
+        {
+            return Err(NotifydError::new(format!("{} is not a WAV file", path).as_str()));
+        }
+
+        let mut fmt : Option<(u32, u16)> = None;
+        let mut off = 12;
+
+        while off + 8 <= content.len()
+        {
+            let id = &content[off..off + 4];
+            let size = le32(off + 4) as usize;
+            let body = off + 8;
+
+            if id == b"fmt " && body + 16 <= content.len()
+            {
+                if le16(body) != 1 || le16(body + 14) != 16
+                {
+                    return Err(NotifydError::new(format!("{} is not 16-bit PCM", path).as_str()));
+                }
+                fmt = Some((le32(body + 4), le16(body + 2)));
+            }
+            else if id == b"data"
+            {
+                let (rate, channels) = match fmt {
+                    Some(f) => f,
+                    None => break
+                };
+                let avail = content.len() - body;
+                let size = if size == 0 || size > avail { avail } else { size };
+
+                let samples = content[body..body + size - (size & 1)]
+                                .chunks(2)
+                                .map(|c| i16::from_le_bytes([c[0], c[1]]))
+                                .collect();
+
+                return Ok(PcmAudio{
+                    rate : rate,
+                    channels : channels,
+                    samples : samples
+                });
+            }
+
+            off = body + size + (size & 1);
+        }
+
+        Err(NotifydError::new(format!("Could not find audio data in {}", path).as_str()))
+    }
+
+    /* Convert to another rate / channel count (linear interpolation, channels averaged) */
+    pub(crate) fn convert(self : &Self, rate : u32, channels : u16) -> PcmAudio
+    {
+        if rate == self.rate && channels == self.channels
+        {
+            return self.clone();
+        }
+
+        let src_ch = self.channels.max(1) as usize;
+        let mono : Vec<f32> = self.samples
+                                  .chunks(src_ch)
+                                  .map(|f| f.iter().map(|v| *v as f32).sum::<f32>() / f.len() as f32)
+                                  .collect();
+
+        let out_frames = (mono.len() as u64 * rate as u64 / self.rate.max(1) as u64) as usize;
+        let mut samples = Vec::with_capacity(out_frames * channels as usize);
+
+        for i in 0..out_frames
+        {
+            let pos = i as f64 * self.rate as f64 / rate as f64;
+            let idx = pos as usize;
+            let frac = (pos - idx as f64) as f32;
+            let a = mono.get(idx).copied().unwrap_or(0.0);
+            let b = mono.get(idx + 1).copied().unwrap_or(a);
+            let v = (a + (b - a) * frac) as i16;
+
+            for _ in 0..channels
+            {
+                samples.push(v);
+            }
+        }
+
+        PcmAudio {
+            rate : rate,
+            channels : channels,
+            samples : samples
+        }
+    }
+
+    pub(crate) fn tone(rate : u32, frequency : f32, ms : u64) -> PcmAudio
+    {
+        let frames = (rate as u64 * ms / 1000) as usize;
+        /* Short linear ramps avoid clicks at both ends */
+        let ramp = (rate as usize / 200).max(1).min(frames / 2 + 1);
+
+        let samples = (0..frames).map(|i| {
+            let gain = (i.min(frames - 1 - i) as f32 / ramp as f32).min(1.0);
+            let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / rate as f32;
+            (phase.sin() * gain * i16::MAX as f32 * 0.5) as i16
+        }).collect();
+
+        PcmAudio {
+            rate : rate,
+            channels : 1,
+            samples : samples
+        }
+    }
+
+    pub(crate) fn silence(rate : u32, ms : u64) -> PcmAudio
+    {
+        PcmAudio {
+            rate : rate,
+            channels : 1,
+            samples : vec![0; (rate as u64 * ms / 1000) as usize]
+        }
+    }
+
+    pub(crate) fn append(self : &mut Self, other : &PcmAudio)
+    {
+        let converted = other.convert(self.rate, self.channels);
+        self.samples.extend_from_slice(&converted.samples);
+    }
+
+    /* Multiply the amplitude, clipping what overflows */
+    pub(crate) fn scale(self : &mut Self, gain : f32)
+    {
+        for v in self.samples.iter_mut()
+        {
+            *v = (*v as f32 * gain).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+
+    /* Pass u32::MAX as length for streams of unknown size */
+    pub(crate) fn wav_header(rate : u32, channels : u16, data_len : u32) -> Vec<u8>
+    {
+        let block_align = channels * 2;
+
+        let mut out = Vec::with_capacity(44);
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&data_len.saturating_add(36).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&rate.to_le_bytes());
+        out.extend_from_slice(&(rate * block_align as u32).to_le_bytes());
+        out.extend_from_slice(&block_align.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn pcm_bytes(self : &Self) -> Vec<u8>
+    {
+        self.samples.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    pub(crate) fn save(self : &Self, path : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut out = PcmAudio::wav_header(self.rate, self.channels, (self.samples.len() * 2) as u32);
+        out.extend(self.pcm_bytes());
+
+        File::create(path)?.write_all(&out)?;
+        Ok(())
+    }
+}
+
+/*****************
+ * BEEP FALLBACK *
+ *****************/
+
+pub const BEEP_RATE : u32 = 22050;
+
+/* Tone patterns made of '.' (short), '-' (long) and ' ' (pause), or "morse:<code>" */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct BeepPatterns
+{
+    pub(crate) low : String,
+    pub(crate) normal : String,
+    pub(crate) high : String,
+    pub(crate) urgent : String
+}
+
+impl Default for BeepPatterns
+{
+    fn default() -> Self
+    {
+        BeepPatterns {
+            low : String::from("."),
+            normal : String::from(".."),
+            high : String::from("..."),
+            urgent : String::from("morse:SOS")
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct BeepConfig
+{
+    /* Beep when no speech could be synthesized */
+    pub(crate) enabled : bool,
+    /* Tone frequency in Hz */
+    pub(crate) frequency : f32,
+    /* Length of a short tone, long tones and pauses last three units */
+    pub(crate) unit_ms : u64,
+    pub(crate) patterns : BeepPatterns
+}
+
+impl Default for BeepConfig
+{
+    fn default() -> Self
+    {
+        BeepConfig {
+            enabled : true,
+            frequency : 880.0,
+            unit_ms : 120,
+            patterns : BeepPatterns::default()
+        }
+    }
+}
+
+impl BeepConfig
+{
+    pub(crate) fn morse(code : &str) -> String
+    {
+        code.to_uppercase().chars().map(|c| {
+            match c {
+                'A' => ".-", 'B' => "-...", 'C' => "-.-.", 'D' => "-..", 'E' => ".",
+                'F' => "..-.", 'G' => "--.", 'H' => "....", 'I' => "..", 'J' => ".---",
+                'K' => "-.-", 'L' => ".-..", 'M' => "--", 'N' => "-.", 'O' => "---",
+                'P' => ".--.", 'Q' => "--.-", 'R' => ".-.", 'S' => "...", 'T' => "-",
+                'U' => "..-", 'V' => "...-", 'W' => ".--", 'X' => "-..-", 'Y' => "-.--",
+                'Z' => "--..", '0' => "-----", '1' => ".----", '2' => "..---", '3' => "...--",
+                '4' => "....-", '5' => ".....", '6' => "-....", '7' => "--...", '8' => "---..",
+                '9' => "----.",
+                _ => ""
+            }
+        }).filter(|m| !m.is_empty()).collect::<Vec<&str>>().join(" ")
+    }
+
+    pub(crate) fn pattern(self : &Self, priority : Priority) -> String
+    {
+        let desc = match priority {
+            Priority::Low => &self.patterns.low,
+            Priority::Normal => &self.patterns.normal,
+            Priority::High => &self.patterns.high,
+            Priority::Urgent => &self.patterns.urgent
+        };
+
+        match desc.strip_prefix("morse:") {
+            Some(code) => BeepConfig::morse(code),
+            None => desc.clone()
+        }
+    }
+
+    pub(crate) fn render(self : &Self, pattern : &str) -> PcmAudio
+    {
+        let mut out = PcmAudio::silence(BEEP_RATE, self.unit_ms);
+
+        for c in pattern.chars()
+        {
+            match c {
+                '.' => out.append(&PcmAudio::tone(BEEP_RATE, self.frequency, self.unit_ms)),
+                '-' => out.append(&PcmAudio::tone(BEEP_RATE, self.frequency, 3 * self.unit_ms)),
+                ' ' => out.append(&PcmAudio::silence(BEEP_RATE, 2 * self.unit_ms)),
+                _ => continue
+            }
+            out.append(&PcmAudio::silence(BEEP_RATE, self.unit_ms));
+        }
+
+        out
+    }
+}
+
+/**********
+ * CHIMES *
+ **********/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ChimeConfig
+{
+    /* Attention sound played before every announcement, none when absent */
+    pub(crate) default : Option<PathBuf>,
+    /* Per priority sounds, e.g. urgent = "/usr/share/sounds/alert.ogg" */
+    pub(crate) priorities : HashMap<Priority, PathBuf>,
+    /* Silence between the chime and the speech */
+    pub(crate) gap_ms : u64
+}
+
+impl Default for ChimeConfig
+{
+    fn default() -> Self
+    {
+        ChimeConfig {
+            default : None,
+            priorities : HashMap::new(),
+            gap_ms : 300
+        }
+    }
+}
+
+impl ChimeConfig
+{
+    /* The chime of a topic wins over the one of the priority, an empty path meaning none */
+    pub(crate) fn select(self : &Self, topic : Option<&PathBuf>, priority : Priority) -> Option<PathBuf>
+    {
+        topic.or(self.priorities.get(&priority))
+             .or(self.default.as_ref())
+             .filter(|p| !p.as_os_str().is_empty())
+             .cloned()
+    }
+}
+
+/* Chimes are decoded once for each configuration */
+pub struct Chimes
+{
+    pub(crate) config : ChimeConfig,
+    pub(crate) decoded : Mutex<HashMap<PathBuf, PcmAudio>>
+}
+
+impl Chimes
+{
+    pub(crate) fn new(config : &ChimeConfig) -> Chimes
+    {
+        Chimes {
+            config : config.clone(),
+            decoded : Mutex::new(HashMap::new())
+        }
+    }
+
+    /* Write the chime, a short silence and the speech to out */
+    pub(crate) fn prepend(self : &Self, chime : &Path, speech : &str, out : &str, tmpdir : &Path) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let cached = self.decoded.lock().unwrap().get(chime).cloned();
+
+        let chime_audio = match cached {
+            Some(a) => a,
+            None => {
+                let a = PcmAudio::decode(chime, tmpdir)?;
+                self.decoded.lock().unwrap().insert(chime.to_path_buf(), a.clone());
+                a
+            }
+        };
+
+        let speech = PcmAudio::load(speech)?;
+
+        let mut audio = chime_audio.convert(speech.rate, speech.channels);
+        audio.append(&PcmAudio::silence(speech.rate, self.config.gap_ms));
+        audio.append(&speech);
+        audio.save(out)
+    }
+}
+
+/**********
+ * VOLUME *
+ **********/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct VolumeConfig
+{
+    /* Milliseconds over which the volume changes, so that the speakers do not pop */
+    pub(crate) ramp_ms : u64
+}
+
+impl Default for VolumeConfig
+{
+    fn default() -> Self
+    {
+        VolumeConfig {
+            ramp_ms : 150
+        }
+    }
+}
+
+/* Global level of the local speaker, which playback follows even when changed midway */
+pub struct OutputLevel
+{
+    /* Level, and the ramp to reach it */
+    pub(crate) target : Mutex<(f32, Duration)>
+}
+
+impl OutputLevel
+{
+    pub(crate) fn new() -> OutputLevel
+    {
+        OutputLevel {
+            target : Mutex::new((1.0, Duration::ZERO))
+        }
+    }
+
+    pub(crate) fn get(self : &Self) -> (f32, Duration)
+    {
+        *self.target.lock().unwrap()
+    }
+
+    pub(crate) fn set(self : &Self, level : f32, ramp : Duration)
+    {
+        *self.target.lock().unwrap() = (level, ramp);
+    }
+}
+
+/****************
+ * AUDIO OUTPUT *
+ ****************/
+
+/* What acts on a playback from aside, and the level it plays at */
+pub(crate) struct PlaybackControl<'a>
+{
+    pub(crate) interrupt : &'a AtomicBool,
+    pub(crate) paused : &'a AtomicBool,
+    pub(crate) level : &'a OutputLevel,
+    /* Level of this sentence within the output, faded in over ramp */
+    pub(crate) volume : Option<f32>,
+    pub(crate) ramp : Duration
+}
+
+/* Follows pause requests, keeping the time spent paused out of the stall detection */
+pub(crate) struct PauseClock
+{
+    pub(crate) since : Option<Instant>,
+    pub(crate) total : Duration
+}
+
+impl PauseClock
+{
+    pub(crate) fn new() -> PauseClock
+    {
+        PauseClock {
+            since : None,
+            total : Duration::ZERO
+        }
+    }
+
+    /* Whether the pause state changed with this request */
+    pub(crate) fn update(self : &mut Self, paused : bool) -> bool
+    {
+        match (self.since, paused) {
+            (None, true) => {
+                self.since = Some(Instant::now());
+                true
+            },
+            (Some(s), false) => {
+                self.total += s.elapsed();
+                self.since = None;
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub(crate) fn total(self : &Self) -> Duration
+    {
+        self.total + self.since.map(|s| s.elapsed()).unwrap_or_default()
+    }
+}
+
+/* Local playback, through Soloud or an external player for small boards */
+pub enum AudioOutput
+{
+    #[cfg(feature = "soloud")]
+    Soloud(Soloud),
+    /* Command line to which the file to play is appended */
+    Player(Vec<String>)
+}
+
+impl AudioOutput
+{
+    pub(crate) fn open(player : &Option<Vec<String>>) -> Result<AudioOutput, Box<dyn std::error::Error>>
+    {
+        match player {
+            Some(cmd) => {
+                match cmd.first() {
+                    Some(bin) => {
+                        which(bin)?;
+                        Ok(AudioOutput::Player(cmd.clone()))
+                    },
+                    None => Err(NotifydError::new("The player command is empty"))
+                }
+            },
+            None => AudioOutput::open_default()
+        }
+    }
+
+    #[cfg(feature = "soloud")]
+    pub(crate) fn open_default() -> Result<AudioOutput, Box<dyn std::error::Error>>
+    {
+        Ok(AudioOutput::Soloud(Soloud::default()?))
+    }
+
+    #[cfg(not(feature = "soloud"))]
+    pub(crate) fn open_default() -> Result<AudioOutput, Box<dyn std::error::Error>>
+    {
+        AudioOutput::open(&Some(vec![String::from("aplay"), String::from("-q")]))
+    }
+
+    /* Whether the global level can be set, external players having no volume control */
+    pub(crate) fn has_volume(player : &Option<Vec<String>>) -> bool
+    {
+        cfg!(feature = "soloud") && player.is_none()
+    }
+
+    /* Play until done, or until interrupted, holding while paused */
+    pub(crate) fn play(self : &mut Self, sentence : &TtsSentence, control : &PlaybackControl) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let (interrupt, paused) = (control.interrupt, control.paused);
+
+        /* Preempted while synthesizing */
+        if interrupt.load(Ordering::SeqCst)
+        {
+            return Err(Box::new(PlaybackInterrupted));
+        }
+
+        match self {
+            #[cfg(feature = "soloud")]
+            AudioOutput::Soloud(sl) => {
+                let mut wav = audio::Wav::default();
+                wav.load(&std::path::Path::new(&sentence.path))?;
+
+                /* Leave plenty of room before declaring the backend wedged */
+                let limit = Duration::from_secs_f64(wav.length() * 2.0) + Duration::from_secs(5);
+                let start = Instant::now();
+                let mut pause = PauseClock::new();
+
+                let mut level = control.level.get().0;
+                sl.set_global_volume(level);
+
+                /* Faded in from silence rather than starting at its level */
+                match control.volume {
+                    Some(v) => {
+                        let h = sl.play_ex(&wav, 0.0, 0.0, false, Handle::PRIMARY);
+                        sl.fade_volume(h, v, control.ramp.as_secs_f64());
+                    },
+                    None => {
+                        sl.play(&wav);
+                    }
+                }
+
+                while sl.voice_count() > 0 {
+                    let (wanted, ramp) = control.level.get();
+                    if wanted != level
+                    {
+                        sl.fade_global_volume(wanted, ramp.as_secs_f64());
+                        level = wanted;
+                    }
+
+                    if interrupt.load(Ordering::SeqCst)
+                    {
+                        sl.stop_all();
+                        return Err(Box::new(PlaybackInterrupted));
+                    }
+
+                    let hold = paused.load(Ordering::SeqCst);
+                    if pause.update(hold)
+                    {
+                        sl.set_pause_all(hold);
+                    }
+
+                    if start.elapsed().saturating_sub(pause.total()) > limit
+                    {
+                        sl.stop_all();
+                        return Err(Box::new(PlaybackStalled));
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+                Ok(())
+            },
+            AudioOutput::Player(cmd) => {
+                let length = Duration::from_millis(sentence.duration_ms().unwrap_or(300_000));
+                let mut args = cmd.clone();
+                args.push(sentence.path.clone());
+
+                run_player(&args, length * 2 + Duration::from_secs(5), interrupt, paused)
+            }
+        }
+    }
+}
+
+/* Run a command playing audio until it exits, killing it when interrupted or past limit and suspending it while paused */
+pub(crate) fn run_player(cmd : &[String], limit : Duration, interrupt : &AtomicBool, paused : &AtomicBool) -> Result<(), Box<dyn std::error::Error>>
+{
+    let start = Instant::now();
+    let mut pause = PauseClock::new();
+
+    let mut child = Command::new(&cmd[0])
+                        .args(&cmd[1..])
+                        .stdout(Stdio::null())
+                        .spawn()?;
+
+    loop {
+        if let Some(status) = child.try_wait()?
+        {
+            if !status.success()
+            {
+                return Err(NotifydError::new(format!("{} exited with {}", cmd[0], status).as_str()));
+            }
+            return Ok(());
+        }
+
+        if interrupt.load(Ordering::SeqCst)
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Box::new(PlaybackInterrupted));
+        }
+
+        /* Players know nothing of pausing, suspend them instead */
+        let hold = paused.load(Ordering::SeqCst);
+        if pause.update(hold)
+        {
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(child.id() as libc::pid_t, if hold { libc::SIGSTOP } else { libc::SIGCONT });
+            }
+        }
+
+        if start.elapsed().saturating_sub(pause.total()) > limit
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Box::new(PlaybackStalled));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,251 @@
+use std::cell::RefCell;
+use md5::compute as md5;
+use serde::{Serialize, Deserialize};
+use base64::Engine;
+use ring::rand::SecureRandom;
+use crate::now_in_usecs;
+use crate::store::Store;
+
+/******************
+ * ACCESS CONTROL *
+ ******************/
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope
+{
+    /* Submit notifications and announcements */
+    Notify,
+    /* Look at queues, history, voices and devices */
+    Read,
+    /* Everything, including /admin */
+    Admin
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AuthConfig
+{
+    /* Token with every scope, allowed to manage the client tokens */
+    pub(crate) admin_token : Option<String>
+}
+
+thread_local! {
+    /* Label of the token of the request this thread is handling */
+    static REQUEST_IDENTITY : RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/* Handlers reach the identity of their request through request_identity() while f runs */
+pub fn with_identity<T>(identity : Option<String>, f : impl FnOnce() -> T) -> T
+{
+    let previous = REQUEST_IDENTITY.replace(identity);
+    let ret = f();
+    REQUEST_IDENTITY.set(previous);
+    ret
+}
+
+pub fn request_identity() -> Option<String>
+{
+    REQUEST_IDENTITY.with_borrow(|i| i.clone())
+}
+
+/* Revocable token given to one client device */
+#[derive(Serialize, Clone)]
+pub struct ClientToken
+{
+    pub(crate) id : i64,
+    pub(crate) label : String,
+    pub(crate) scopes : Vec<Scope>,
+    pub(crate) created : i64,
+    pub(crate) last_used : Option<i64>
+}
+
+impl ClientToken
+{
+    pub(crate) fn from_row(row : &rusqlite::Row) -> rusqlite::Result<ClientToken>
+    {
+        let scopes : String = row.get(2)?;
+
+        Ok(ClientToken {
+            id : row.get(0)?,
+            label : row.get(1)?,
+            scopes : serde_json::from_str(&scopes).unwrap_or_default(),
+            created : row.get(3)?,
+            last_used : row.get(4)?
+        })
+    }
+
+    pub(crate) fn allows(self : &Self, scope : Scope) -> bool
+    {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+
+    pub(crate) fn hash(secret : &str) -> String
+    {
+        ring::digest::digest(&ring::digest::SHA256, secret.as_bytes()).as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /* Tokens created before SHA-256 was used are stored as MD5, rehashed on their next use */
+    pub(crate) fn legacy_hash(secret : &str) -> String
+    {
+        format!("{:x}", md5(secret.as_bytes()))
+    }
+
+    pub(crate) fn generate_secret() -> String
+    {
+        let mut bytes = [0u8; 32];
+        ring::rand::SystemRandom::new().fill(&mut bytes).expect("Failed to draw a token secret");
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+    }
+}
+
+/* Comparing secrets takes the same time wherever they differ */
+pub fn same_secret(a : &str, b : &str) -> bool
+{
+    ring::constant_time::verify_slices_are_equal(a.as_bytes(), b.as_bytes()).is_ok()
+}
+
+impl Store
+{
+    pub(crate) fn tokens(self : &Self) -> Result<Vec<ClientToken>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, label, scopes, created, last_used FROM tokens ORDER BY id")?;
+        let rows = stmt.query_map([], ClientToken::from_row)?;
+
+        let mut ret = Vec::new();
+        for t in rows
+        {
+            ret.push(t?);
+        }
+
+        Ok(ret)
+    }
+
+    pub(crate) fn token(self : &Self, id : i64) -> Result<Option<ClientToken>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, label, scopes, created, last_used FROM tokens WHERE id = ?1")?;
+        let mut rows = stmt.query_map([id], ClientToken::from_row)?;
+
+        match rows.next() {
+            Some(t) => Ok(Some(t?)),
+            None => Ok(None)
+        }
+    }
+
+    pub(crate) fn has_tokens(self : &Self) -> bool
+    {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM tokens", [], |row| row.get::<_, i64>(0)).map(|n| n > 0).unwrap_or(false)
+    }
+
+    /* Returns the token along with its secret, which is only stored hashed */
+    pub(crate) fn create_token(self : &Self, label : &str, scopes : &[Scope]) -> Result<(ClientToken, String), Box<dyn std::error::Error>>
+    {
+        let secret = ClientToken::generate_secret();
+        let now = (now_in_usecs() / 1_000_000) as i64;
+
+        let id = {
+            let conn = self.conn.lock().unwrap();
+            conn.execute("INSERT INTO tokens (label, hash, scopes, created) VALUES (?1, ?2, ?3, ?4)",
+                         rusqlite::params![label, ClientToken::hash(&secret), serde_json::to_string(scopes)?, now])?;
+            conn.last_insert_rowid()
+        };
+
+        Ok((ClientToken {
+            id : id,
+            label : label.to_string(),
+            scopes : scopes.to_vec(),
+            created : now,
+            last_used : None
+        }, secret))
+    }
+
+    pub(crate) fn update_token(self : &Self, token : &ClientToken) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.conn.lock().unwrap().execute("UPDATE tokens SET label = ?1, scopes = ?2 WHERE id = ?3",
+                                          rusqlite::params![token.label, serde_json::to_string(&token.scopes)?, token.id])?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_token(self : &Self, id : i64) -> Result<bool, Box<dyn std::error::Error>>
+    {
+        Ok(self.conn.lock().unwrap().execute("DELETE FROM tokens WHERE id = ?1", [id])? > 0)
+    }
+
+    /* The token matching this secret, marked as used */
+    pub(crate) fn authenticate(self : &Self, secret : &str) -> Option<ClientToken>
+    {
+        let conn = self.conn.lock().unwrap();
+        let now = (now_in_usecs() / 1_000_000) as i64;
+        let hash = ClientToken::hash(secret);
+        let legacy = ClientToken::legacy_hash(secret);
+
+        /* Every hash is compared rather than looked up, in constant time */
+        let mut stmt = conn.prepare("SELECT id, hash FROM tokens").ok()?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))).ok()?;
+
+        let mut found = None;
+        for (id, stored) in rows.flatten()
+        {
+            if same_secret(&stored, &hash)
+            {
+                found = Some(id);
+            }
+            else if same_secret(&stored, &legacy)
+            {
+                let _ = conn.execute("UPDATE tokens SET hash = ?1 WHERE id = ?2", rusqlite::params![hash, id]);
+                found = Some(id);
+            }
+        }
+
+        let mut token = conn.query_row("SELECT id, label, scopes, created, last_used FROM tokens WHERE id = ?1",
+                                       [found?], ClientToken::from_row).ok()?;
+
+        let _ = conn.execute("UPDATE tokens SET last_used = ?1 WHERE id = ?2", rusqlite::params![now, token.id]);
+        token.last_used = Some(now);
+        Some(token)
+    }
+}
+
+/***************
+ * SIGNED URLS *
+ ***************/
+
+/* Cast devices fetch /static/ without a token, the URLs they get are signed
+   with a key drawn at startup and expire */
+pub struct UrlSigner
+{
+    pub(crate) key : ring::hmac::Key
+}
+
+impl UrlSigner
+{
+    pub(crate) fn new() -> UrlSigner
+    {
+        UrlSigner {
+            key : ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &ring::rand::SystemRandom::new())
+                                 .expect("Failed to draw a key to sign URLs")
+        }
+    }
+
+    pub(crate) fn message(file : &str, expires : i64) -> String
+    {
+        format!("{}:{}", file, expires)
+    }
+
+    pub(crate) fn sign(self : &Self, file : &str, expires : i64) -> String
+    {
+        let tag = ring::hmac::sign(&self.key, UrlSigner::message(file, expires).as_bytes());
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(tag.as_ref())
+    }
+
+    pub(crate) fn verify(self : &Self, file : &str, expires : i64, sig : &str) -> bool
+    {
+        match base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(sig) {
+            Ok(tag) => ring::hmac::verify(&self.key, UrlSigner::message(file, expires).as_bytes(), &tag).is_ok(),
+            Err(_) => false
+        }
+    }
+}
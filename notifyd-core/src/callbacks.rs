@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::cloud::{curl, curl_option, expect_success};
+use crate::error::NotifydError;
+
+/*********************
+ * OUTBOUND WEBHOOKS *
+ *********************/
+
+/* Delay before retrying a callback, doubled on each attempt */
+pub const CALLBACK_BACKOFF : Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CallbackEvent
+{
+    Queued,
+    Played,
+    Failed
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CallbackConfig
+{
+    pub(crate) url : String,
+    pub(crate) events : Vec<CallbackEvent>,
+    /* Signs the body with HMAC-SHA256, sent as "sha256=<hex>" in X-Notifyd-Signature */
+    pub(crate) secret : Option<String>,
+    /* Sent along, e.g. Authorization = "Bearer ..." */
+    pub(crate) headers : HashMap<String, String>,
+    /* Attempts after the first failed one */
+    pub(crate) retries : u32
+}
+
+impl Default for CallbackConfig
+{
+    fn default() -> Self
+    {
+        CallbackConfig {
+            url : String::new(),
+            events : vec![CallbackEvent::Queued, CallbackEvent::Played, CallbackEvent::Failed],
+            secret : None,
+            headers : HashMap::new(),
+            retries : 2
+        }
+    }
+}
+
+impl CallbackConfig
+{
+    pub(crate) fn check(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://")
+        {
+            return Err(NotifydError::new(format!("Callback URL '{}' must be an http:// or https:// URL", self.url).as_str()));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn post(self : &Self, event : CallbackEvent, body : &[u8]) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut options = vec![
+            curl_option("header", "Content-Type: application/json"),
+            curl_option("header", format!("X-Notifyd-Event: {}", serde_json::to_value(event)?.as_str().unwrap_or_default()).as_str())
+        ];
+
+        if let Some(secret) = &self.secret
+        {
+            let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+            let tag : String = ring::hmac::sign(&key, body).as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+            options.push(curl_option("header", format!("X-Notifyd-Signature: sha256={}", tag).as_str()));
+        }
+
+        for (name, value) in &self.headers
+        {
+            options.push(curl_option("header", format!("{}: {}", name, value).as_str()));
+        }
+
+        let (code, content) = curl(&self.url, &options, Some(body))?;
+        expect_success("Callback", code, content)?;
+
+        Ok(())
+    }
+}
+
+/* Post the event to every callback listening for it, each in its own thread */
+pub(crate) fn send_callbacks(callbacks : &[CallbackConfig], event : CallbackEvent, payload : serde_json::Value)
+{
+    let body = payload.to_string();
+
+    for callback in callbacks.iter().filter(|c| c.events.contains(&event))
+    {
+        let callback = callback.clone();
+        let body = body.clone();
+
+        std::thread::spawn(move || {
+            let mut attempt = 0;
+
+            while let Err(e) = callback.post(event, body.as_bytes())
+            {
+                if attempt >= callback.retries
+                {
+                    warn!("Failed to call back {} for a {:?} job : {}", callback.url, event, e);
+                    return;
+                }
+
+                std::thread::sleep(CALLBACK_BACKOFF * 2u32.pow(attempt.min(10)));
+                attempt += 1;
+            }
+        });
+    }
+}
+
+/* RFC 3339 form of a time in microseconds since the epoch */
+pub(crate) fn timestamp(usecs : u128) -> String
+{
+    use chrono::TimeZone;
+
+    chrono::Local.timestamp_opt((usecs / 1_000_000) as i64, ((usecs % 1_000_000) * 1000) as u32)
+                 .single()
+                 .map(|t| t.to_rfc3339())
+                 .unwrap_or_default()
+}
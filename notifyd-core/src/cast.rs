@@ -0,0 +1,583 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned};
+use simple_dns::rdata::RData;
+use simple_dns::{CLASS, Name, Packet, QCLASS, QTYPE, Question, TYPE};
+use crate::error::NotifydError;
+
+/***************
+ * CAST DEVICE *
+ ***************/
+
+pub const CAST_SERVICE : &str = "_googlecast._tcp.local";
+
+/* A receiver as advertised over mDNS */
+#[derive(Clone, Debug)]
+pub struct CastDevice
+{
+    /* The "id" TXT record, what go-chromecast called the UUID */
+    pub uuid : String,
+    pub name : String,
+    pub model : String,
+    pub addr : SocketAddr
+}
+
+impl CastDevice
+{
+    pub fn is_group(self : &Self) -> bool
+    {
+        self.model == "Google Cast Group"
+    }
+
+    pub(crate) fn matches(self : &Self, uid : &str) -> bool
+    {
+        let canonical = |u : &str| u.replace('-', "").to_lowercase();
+        canonical(&self.uuid) == canonical(uid) || self.name == uid
+    }
+}
+
+/* What the answers to a query told so far, records of a service often coming in several packets */
+#[derive(Default)]
+pub(crate) struct Browse
+{
+    instances : Vec<String>,
+    srv : HashMap<String, (String, u16)>,
+    txt : HashMap<String, HashMap<String, Option<String>>>,
+    hosts : HashMap<String, Ipv4Addr>
+}
+
+impl Browse
+{
+    pub(crate) fn add(self : &mut Self, packet : &Packet)
+    {
+        for rr in packet.answers.iter().chain(packet.additional_records.iter())
+        {
+            let name = rr.name.to_string();
+
+            match &rr.rdata {
+                RData::PTR(ptr) => {
+                    let instance = ptr.0.to_string();
+                    if name == CAST_SERVICE && !self.instances.contains(&instance)
+                    {
+                        self.instances.push(instance);
+                    }
+                },
+                RData::SRV(srv) => {
+                    self.srv.insert(name, (srv.target.to_string(), srv.port));
+                },
+                RData::TXT(txt) => {
+                    self.txt.insert(name, txt.attributes());
+                },
+                RData::A(a) => {
+                    self.hosts.insert(name, Ipv4Addr::from(a.address));
+                },
+                _ => {}
+            }
+        }
+    }
+
+    pub(crate) fn devices(self : &Self) -> Vec<CastDevice>
+    {
+        let mut ret = Vec::new();
+
+        for instance in &self.instances
+        {
+            let (ip, port) = match self.srv.get(instance).and_then(|(host, port)| self.hosts.get(host).map(|ip| (*ip, *port))) {
+                Some(a) => a,
+                None => continue
+            };
+
+            let txt = self.txt.get(instance).cloned().unwrap_or_default();
+            let field = |k : &str| txt.get(k).cloned().flatten();
+
+            ret.push(CastDevice {
+                uuid : field("id").unwrap_or(instance.clone()),
+                name : field("fn").unwrap_or(instance.clone()),
+                model : field("md").unwrap_or_default(),
+                addr : SocketAddr::new(IpAddr::V4(ip), port)
+            });
+        }
+
+        ret
+    }
+}
+
+/* Ask the LAN for cast receivers, stopping early once `until` is satisfied */
+pub(crate) fn browse(timeout : Duration, until : &dyn Fn(&[CastDevice]) -> bool) -> Result<Vec<CastDevice>, Box<dyn std::error::Error>>
+{
+    let mut query = Packet::new_query(0);
+    query.questions.push(Question::new(Name::new_unchecked(CAST_SERVICE), QTYPE::TYPE(TYPE::PTR), QCLASS::CLASS(CLASS::IN), false));
+
+    /* Sent from an ephemeral port, responders answer us directly (legacy unicast) */
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.send_to(&query.build_bytes_vec()?, "224.0.0.251:5353")?;
+
+    let deadline = Instant::now() + timeout;
+    let mut browse = Browse::default();
+    let mut buf = [0u8; 9000];
+
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero())
+    {
+        socket.set_read_timeout(Some(left))?;
+
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(Box::new(e))
+        };
+
+        if let Ok(packet) = Packet::parse(&buf[..len])
+        {
+            browse.add(&packet);
+
+            if until(&browse.devices())
+            {
+                break;
+            }
+        }
+    }
+
+    Ok(browse.devices())
+}
+
+pub fn discover(timeout : Duration) -> Result<Vec<CastDevice>, Box<dyn std::error::Error>>
+{
+    browse(timeout, &|_| false)
+}
+
+/* Look a receiver up by UUID or friendly name */
+pub fn resolve(uid : &str, timeout : Duration) -> Result<Option<CastDevice>, Box<dyn std::error::Error>>
+{
+    let found = browse(timeout, &|devices| devices.iter().any(|d| d.matches(uid)))?;
+    Ok(found.into_iter().find(|d| d.matches(uid)))
+}
+
+/*****************
+ * CAST MESSAGES *
+ *****************/
+
+pub const NS_CONNECTION : &str = "urn:x-cast:com.google.cast.tp.connection";
+pub const NS_HEARTBEAT : &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+pub const NS_RECEIVER : &str = "urn:x-cast:com.google.cast.receiver";
+pub const NS_MEDIA : &str = "urn:x-cast:com.google.cast.media";
+
+/* The Default Media Receiver, able to play any audio URL */
+pub const MEDIA_RECEIVER_APP : &str = "CC1AD845";
+
+/* The CastMessage protobuf, only ever carrying JSON payloads here */
+#[derive(Debug, PartialEq)]
+pub struct CastMessage
+{
+    pub source : String,
+    pub destination : String,
+    pub namespace : String,
+    pub payload : String
+}
+
+impl CastMessage
+{
+    pub(crate) fn put_varint(out : &mut Vec<u8>, mut v : u64)
+    {
+        while v >= 0x80
+        {
+            out.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    pub(crate) fn get_varint(data : &[u8], pos : &mut usize) -> Result<u64, Box<dyn std::error::Error>>
+    {
+        let mut v = 0u64;
+
+        for shift in (0..64).step_by(7)
+        {
+            let b = match data.get(*pos) {
+                Some(b) => *b,
+                None => return Err(NotifydError::new("Truncated cast message"))
+            };
+            *pos += 1;
+            v |= ((b & 0x7f) as u64) << shift;
+
+            if b & 0x80 == 0
+            {
+                return Ok(v);
+            }
+        }
+
+        Err(NotifydError::new("Bad varint in cast message"))
+    }
+
+    pub(crate) fn put_string(out : &mut Vec<u8>, field : u64, s : &str)
+    {
+        CastMessage::put_varint(out, (field << 3) | 2);
+        CastMessage::put_varint(out, s.len() as u64);
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn encode(self : &Self) -> Vec<u8>
+    {
+        let mut out = Vec::new();
+
+        /* protocol_version = CASTV2_1_0 */
+        CastMessage::put_varint(&mut out, 1 << 3);
+        CastMessage::put_varint(&mut out, 0);
+        CastMessage::put_string(&mut out, 2, &self.source);
+        CastMessage::put_string(&mut out, 3, &self.destination);
+        CastMessage::put_string(&mut out, 4, &self.namespace);
+        /* payload_type = STRING */
+        CastMessage::put_varint(&mut out, 5 << 3);
+        CastMessage::put_varint(&mut out, 0);
+        CastMessage::put_string(&mut out, 6, &self.payload);
+
+        out
+    }
+
+    pub fn decode(data : &[u8]) -> Result<CastMessage, Box<dyn std::error::Error>>
+    {
+        let mut msg = CastMessage { source : String::new(), destination : String::new(), namespace : String::new(), payload : String::new() };
+        let mut pos = 0;
+
+        while pos < data.len()
+        {
+            let key = CastMessage::get_varint(data, &mut pos)?;
+
+            match key & 7 {
+                0 => {
+                    CastMessage::get_varint(data, &mut pos)?;
+                },
+                2 => {
+                    let len = CastMessage::get_varint(data, &mut pos)? as usize;
+                    let bytes = match data.get(pos..pos + len) {
+                        Some(b) => b,
+                        None => return Err(NotifydError::new("Truncated cast message"))
+                    };
+                    pos += len;
+
+                    let value = String::from_utf8_lossy(bytes).to_string();
+
+                    match key >> 3 {
+                        2 => msg.source = value,
+                        3 => msg.destination = value,
+                        4 => msg.namespace = value,
+                        6 => msg.payload = value,
+                        /* Binary payloads are not used by the receivers we talk to */
+                        _ => {}
+                    }
+                },
+                w => return Err(NotifydError::new(format!("Unexpected wire type {} in cast message", w).as_str()))
+            }
+        }
+
+        Ok(msg)
+    }
+}
+
+/***************
+ * CAST CLIENT *
+ ***************/
+
+/* Receivers present self-signed certificates, the channel is only encrypted */
+#[derive(Debug)]
+pub(crate) struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate
+{
+    fn verify_server_cert(&self, _end_entity : &CertificateDer<'_>, _intermediates : &[CertificateDer<'_>], _server_name : &ServerName<'_>,
+                          _ocsp_response : &[u8], _now : UnixTime) -> Result<ServerCertVerified, rustls::Error>
+    {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message : &[u8], cert : &CertificateDer<'_>, dss : &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error>
+    {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message : &[u8], cert : &CertificateDer<'_>, dss : &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error>
+    {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme>
+    {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+pub const SENDER_ID : &str = "sender-0";
+pub const RECEIVER_ID : &str = "receiver-0";
+
+/* How long a receiver may stay silent, it pings every 5 seconds */
+pub const CAST_READ_TIMEOUT : Duration = Duration::from_secs(15);
+
+pub struct CastClient
+{
+    pub(crate) stream : StreamOwned<ClientConnection, TcpStream>,
+    pub(crate) request_id : u64
+}
+
+impl CastClient
+{
+    pub fn connect(addr : &SocketAddr) -> Result<CastClient, Box<dyn std::error::Error>>
+    {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider.clone())
+                        .with_safe_default_protocol_versions()?
+                        .dangerous()
+                        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+                        .with_no_client_auth();
+
+        let tcp = TcpStream::connect_timeout(addr, Duration::from_secs(5))?;
+        tcp.set_read_timeout(Some(CAST_READ_TIMEOUT))?;
+
+        let conn = ClientConnection::new(Arc::new(config), ServerName::from(addr.ip()))?;
+
+        let mut client = CastClient {
+            stream : StreamOwned::new(conn, tcp),
+            request_id : 0
+        };
+
+        client.send(RECEIVER_ID, NS_CONNECTION, &serde_json::json!({ "type" : "CONNECT" }))?;
+
+        Ok(client)
+    }
+
+    pub fn send(self : &mut Self, destination : &str, namespace : &str, payload : &serde_json::Value) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let msg = CastMessage {
+            source : String::from(SENDER_ID),
+            destination : destination.to_string(),
+            namespace : namespace.to_string(),
+            payload : payload.to_string()
+        }.encode();
+
+        self.stream.write_all(&(msg.len() as u32).to_be_bytes())?;
+        self.stream.write_all(&msg)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /* Send a request, returning its id to match the answer */
+    pub fn request(self : &mut Self, destination : &str, namespace : &str, mut payload : serde_json::Value) -> Result<u64, Box<dyn std::error::Error>>
+    {
+        self.request_id += 1;
+        payload["requestId"] = serde_json::json!(self.request_id);
+        self.send(destination, namespace, &payload)?;
+        Ok(self.request_id)
+    }
+
+    /* Next message that is not a heartbeat, answering the pings meanwhile */
+    pub fn recv(self : &mut Self) -> Result<(CastMessage, serde_json::Value), Box<dyn std::error::Error>>
+    {
+        loop {
+            let mut len = [0u8; 4];
+            self.stream.read_exact(&mut len)?;
+
+            let mut buf = vec![0u8; u32::from_be_bytes(len) as usize];
+            self.stream.read_exact(&mut buf)?;
+
+            let msg = CastMessage::decode(&buf)?;
+            let payload : serde_json::Value = serde_json::from_str(&msg.payload).unwrap_or_default();
+
+            if msg.namespace == NS_HEARTBEAT
+            {
+                if payload["type"] == "PING"
+                {
+                    self.send(&msg.source, NS_HEARTBEAT, &serde_json::json!({ "type" : "PONG" }))?;
+                }
+                continue;
+            }
+
+            return Ok((msg, payload));
+        }
+    }
+
+    /* Status of the receiver, carrying the running applications */
+    pub fn receiver_status(self : &mut Self) -> Result<serde_json::Value, Box<dyn std::error::Error>>
+    {
+        let id = self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({ "type" : "GET_STATUS" }))?;
+
+        loop {
+            let (msg, payload) = self.recv()?;
+
+            if msg.namespace == NS_RECEIVER && payload["requestId"] == id
+            {
+                return Ok(payload["status"].clone());
+            }
+        }
+    }
+
+    /* Level of the device, from 0.0 to 1.0 */
+    pub fn volume(self : &mut Self) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        match self.receiver_status()?["volume"]["level"].as_f64() {
+            Some(l) => Ok(l as f32),
+            None => Err(NotifydError::new("Device did not report its volume"))
+        }
+    }
+
+    pub fn set_volume(self : &mut Self, level : f32) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let id = self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({ "type" : "SET_VOLUME", "volume" : { "level" : level } }))?;
+
+        loop {
+            let (msg, payload) = self.recv()?;
+
+            if msg.namespace == NS_RECEIVER && payload["requestId"] == id
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /* Launch the media receiver, returning its (transport, session) ids */
+    pub fn launch_media_receiver(self : &mut Self) -> Result<(String, String), Box<dyn std::error::Error>>
+    {
+        let id = self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({ "type" : "LAUNCH", "appId" : MEDIA_RECEIVER_APP }))?;
+
+        loop {
+            let (msg, payload) = self.recv()?;
+
+            if msg.namespace != NS_RECEIVER || payload["requestId"] != id
+            {
+                continue;
+            }
+
+            if payload["type"] == "LAUNCH_ERROR"
+            {
+                return Err(NotifydError::new(format!("Device refused to launch the media receiver : {}", payload["reason"]).as_str()));
+            }
+
+            let app = payload["status"]["applications"].as_array()
+                        .and_then(|apps| apps.iter().find(|a| a["appId"] == MEDIA_RECEIVER_APP).cloned());
+
+            match app {
+                Some(a) => {
+                    return Ok((a["transportId"].as_str().unwrap_or_default().to_string(),
+                               a["sessionId"].as_str().unwrap_or_default().to_string()));
+                },
+                None => return Err(NotifydError::new("Media receiver did not start on the device"))
+            }
+        }
+    }
+
+    /* Play the URL and wait until the device is done with it */
+    pub fn play(self : &mut Self, url : &str, mime : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let (transport, session) = self.launch_media_receiver()?;
+
+        self.send(&transport, NS_CONNECTION, &serde_json::json!({ "type" : "CONNECT" }))?;
+
+        let id = self.request(&transport, NS_MEDIA, serde_json::json!({
+            "type" : "LOAD",
+            "sessionId" : session,
+            "autoplay" : true,
+            "media" : {
+                "contentId" : url,
+                "contentType" : mime,
+                "streamType" : "BUFFERED"
+            }
+        }))?;
+
+        let mut started = false;
+
+        loop {
+            let (msg, payload) = self.recv()?;
+
+            if msg.namespace != NS_MEDIA
+            {
+                continue;
+            }
+
+            match payload["type"].as_str().unwrap_or_default() {
+                "LOAD_FAILED" | "LOAD_CANCELLED" | "INVALID_REQUEST" if payload["requestId"] == id => {
+                    let mut what = payload["type"].as_str().unwrap_or_default().to_string();
+                    if let Some(reason) = payload["reason"].as_str()
+                    {
+                        what = format!("{} : {}", what, reason);
+                    }
+                    return Err(NotifydError::new(format!("Device failed to load {} ({})", url, what).as_str()));
+                },
+                "MEDIA_STATUS" => {
+                    let status = match payload["status"].as_array().and_then(|s| s.first()) {
+                        Some(s) => s.clone(),
+                        None => continue
+                    };
+
+                    match (status["playerState"].as_str().unwrap_or_default(), status["idleReason"].as_str()) {
+                        ("PLAYING", _) | ("BUFFERING", _) | ("PAUSED", _) => started = true,
+                        ("IDLE", Some("FINISHED")) if started => return Ok(()),
+                        ("IDLE", Some("ERROR")) => {
+                            return Err(NotifydError::new(format!("Device failed to play {} (error {})", url, status["extendedStatus"]["detailedErrorCode"]).as_str()));
+                        },
+                        ("IDLE", Some(reason)) if started => {
+                            return Err(NotifydError::new(format!("Playback of {} ended early ({})", url, reason).as_str()));
+                        },
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /* Send a command such as PAUSE or PLAY to the media playing on the device */
+    pub fn media_command(self : &mut Self, command : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let status = self.receiver_status()?;
+
+        let transport = match status["applications"].as_array().and_then(|apps| apps.iter().find(|a| a["appId"] == MEDIA_RECEIVER_APP)) {
+            Some(a) => a["transportId"].as_str().unwrap_or_default().to_string(),
+            None => return Err(NotifydError::new("The media receiver is not running on the device"))
+        };
+
+        self.send(&transport, NS_CONNECTION, &serde_json::json!({ "type" : "CONNECT" }))?;
+
+        let id = self.request(&transport, NS_MEDIA, serde_json::json!({ "type" : "GET_STATUS" }))?;
+
+        let media_session = loop {
+            let (msg, payload) = self.recv()?;
+
+            if msg.namespace != NS_MEDIA || payload["requestId"] != id
+            {
+                continue;
+            }
+
+            match payload["status"].as_array().and_then(|s| s.first()).and_then(|s| s["mediaSessionId"].as_u64()) {
+                Some(m) => break m,
+                None => return Err(NotifydError::new("No media is loaded on the device"))
+            }
+        };
+
+        self.request(&transport, NS_MEDIA, serde_json::json!({ "type" : command, "mediaSessionId" : media_session }))?;
+
+        Ok(())
+    }
+
+    /* Stop whatever application plays on the device */
+    pub fn stop(self : &mut Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let status = self.receiver_status()?;
+
+        let sessions : Vec<String> = status["applications"].as_array()
+                                        .map(|apps| apps.iter()
+                                                        .filter(|a| a["isIdleScreen"] != true)
+                                                        .filter_map(|a| a["sessionId"].as_str().map(|s| s.to_string()))
+                                                        .collect())
+                                        .unwrap_or_default();
+
+        for session in sessions
+        {
+            self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({ "type" : "STOP", "sessionId" : session }))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::cast::{self, CastClient, CastDevice};
+use crate::error::NotifydError;
+use crate::now_in_usecs;
+use crate::store::default_device_kind;
+use crate::store::Device;
+use crate::tts::TtsSentence;
+use crate::wake::WakeConfig;
+
+/********************
+ * WAV FILE CASTING *
+ ********************/
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CastEncoding
+{
+    #[default]
+    Wav,
+    /* Compressed for devices on weak Wi-Fi, requires ffmpeg */
+    Mp3,
+    Opus
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CastConfig
+{
+    pub(crate) encoding : CastEncoding,
+    pub(crate) bitrate_kbps : u32,
+    /* Per target (UUID, name or alias) encodings */
+    pub(crate) targets : HashMap<String, CastEncoding>,
+    /* Play on the local speaker when a device cannot be cast to */
+    pub(crate) fallback_to_local : bool,
+    /* Attempts after the first one to reach a device or load the media, sleeping devices needing a wake-up */
+    pub(crate) retries : u32,
+    /* Wait before the first retry, doubled for each of the next ones */
+    pub(crate) retry_backoff_ms : u64,
+    /* Per target (UUID, name or alias) way to wake it up before casting */
+    pub(crate) wake : HashMap<String, WakeConfig>
+}
+
+impl Default for CastConfig
+{
+    fn default() -> Self
+    {
+        CastConfig {
+            encoding : CastEncoding::Wav,
+            bitrate_kbps : 32,
+            targets : HashMap::new(),
+            fallback_to_local : true,
+            retries : 2,
+            retry_backoff_ms : 500,
+            wake : HashMap::new()
+        }
+    }
+}
+
+impl CastConfig
+{
+    /* Wait before retry number attempt, starting from 1 */
+    pub(crate) fn backoff(self : &Self, attempt : u32) -> Duration
+    {
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(1 << attempt.saturating_sub(1).min(16)))
+    }
+
+    /* The sentence as it should be sent to a device wanting this encoding */
+    pub(crate) fn encode(self : &Self, sentence : &TtsSentence, encoding : CastEncoding) -> Result<TtsSentence, Box<dyn std::error::Error>>
+    {
+        let bitrate = format!("{}k", self.bitrate_kbps);
+
+        match encoding {
+            CastEncoding::Wav => Ok(sentence.clone()),
+            /* Speech needs no stereo */
+            CastEncoding::Mp3 => sentence.transcode("mp3", &["-ac", "1", "-b:a", &bitrate]),
+            CastEncoding::Opus => sentence.transcode("opus", &["-ac", "1", "-c:a", "libopus", "-b:a", &bitrate, "-application", "voip"])
+        }
+    }
+}
+
+/* Time given to receivers to answer discovery */
+pub const DISCOVERY_TIMEOUT : Duration = Duration::from_secs(3);
+
+/* Interval between two steps of a volume ramp */
+pub const VOLUME_STEP : Duration = Duration::from_millis(50);
+
+/* Bring the device to level over ramp, returning the level it was at */
+pub(crate) fn ramp_volume(device : &CastDevice, level : f32, ramp : Duration) -> Result<f32, Box<dyn std::error::Error>>
+{
+    let mut client = CastClient::connect(&device.addr)?;
+    let from = client.volume()?;
+    let steps = (ramp.as_millis() / VOLUME_STEP.as_millis()).max(1) as u32;
+
+    for i in 1..=steps
+    {
+        client.set_volume(from + (level - from) * i as f32 / steps as f32)?;
+
+        if i < steps
+        {
+            std::thread::sleep(VOLUME_STEP);
+        }
+    }
+
+    Ok(from)
+}
+
+pub struct Caster
+{
+    pub(crate) device : CastDevice,
+    pub(crate) url : String,
+    pub(crate) mime : String
+}
+
+impl Drop for Caster
+{
+    fn drop(&mut self)
+    {
+        let _ = self.stop();
+    }
+}
+
+
+impl Caster
+{
+    pub fn load(self : &Self) ->  Result<(), Box<dyn std::error::Error>>
+    {
+        CastClient::connect(&self.device.addr)?.play(&self.url, &self.mime)
+    }
+
+    pub fn stop(self : &Self) ->  Result<(), Box<dyn std::error::Error>>
+    {
+        CastClient::connect(&self.device.addr)?.stop()
+    }
+
+    pub fn pause(self : &Self) ->  Result<(), Box<dyn std::error::Error>>
+    {
+        CastClient::connect(&self.device.addr)?.media_command("PAUSE")
+    }
+
+    pub fn resume(self : &Self) ->  Result<(), Box<dyn std::error::Error>>
+    {
+        CastClient::connect(&self.device.addr)?.media_command("PLAY")
+    }
+
+    /* Returns the level the device was at */
+    pub fn set_volume(self : &Self, level : f32, ramp : Duration) ->  Result<f32, Box<dyn std::error::Error>>
+    {
+        ramp_volume(&self.device, level, ramp)
+    }
+
+    /* Volume of a device without casting anything to it */
+    pub fn volume_of(uid : &str) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        CastClient::connect(&Caster::find(uid)?.addr)?.volume()
+    }
+
+    pub fn set_volume_of(uid : &str, level : f32, ramp : Duration) -> Result<(), Box<dyn std::error::Error>>
+    {
+        ramp_volume(&Caster::find(uid)?, level, ramp)?;
+        Ok(())
+    }
+
+    /* Whether the device answers discovery on the LAN */
+    pub fn is_reachable(uid : &str) -> Result<bool, Box<dyn std::error::Error>>
+    {
+        Ok(cast::resolve(uid, DISCOVERY_TIMEOUT)?.is_some())
+    }
+
+    pub fn discover() -> Result<Vec<Device>, Box<dyn std::error::Error>>
+    {
+        let now = (now_in_usecs() / 1_000_000) as i64;
+        let mut ret = Vec::new();
+
+        for d in cast::discover(DISCOVERY_TIMEOUT)?
+        {
+            let mut capabilities = vec![String::from("audio")];
+            if d.is_group()
+            {
+                capabilities.push(String::from("group"));
+            }
+
+            ret.push(Device {
+                uuid : d.uuid,
+                name : d.name,
+                ip : Some(d.addr.ip().to_string()),
+                model : Some(d.model).filter(|m| !m.is_empty()),
+                kind : default_device_kind(),
+                capabilities : capabilities,
+                alias : None,
+                last_seen : Some(now)
+            });
+        }
+
+        Ok(ret)
+    }
+
+    pub(crate) fn find(uid : &str) -> Result<CastDevice, Box<dyn std::error::Error>>
+    {
+        match cast::resolve(uid, DISCOVERY_TIMEOUT)? {
+            Some(d) => Ok(d),
+            None => Err(NotifydError::new(format!("Cast device {} was not discovered on the network", uid).as_str()))
+        }
+    }
+
+    pub fn new(uid:String, url : String, mime : &str) ->  Result<Caster, Box<dyn std::error::Error>>
+    {
+        Ok(Caster{
+            device : Caster::find(&uid)?,
+            url : url,
+            mime : mime.to_string()
+        })
+    }
+
+}
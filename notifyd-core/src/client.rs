@@ -0,0 +1,67 @@
+use crate::cloud::{curl, curl_option};
+use crate::error::NotifydError;
+
+/**************
+ * CLI CLIENT *
+ **************/
+
+/* Time a waiting request may take, the notification being played meanwhile */
+pub const WAIT_TIMEOUT_SECS : u64 = 600;
+
+/* Base URL of a daemon given as "pi.local", "pi.local:8090" or a full URL such as "https://pi.local/kitchen" */
+pub fn base_url(host : &str, port : u32) -> String
+{
+    let host = host.trim_end_matches('/');
+
+    if host.starts_with("http://") || host.starts_with("https://")
+    {
+        return host.to_string();
+    }
+
+    /* Bracketed IPv6 addresses carry colons of their own */
+    let has_port = match host.rfind(']') {
+        Some(end) => host[end..].contains(':'),
+        None => host.contains(':')
+    };
+
+    if has_port
+    {
+        format!("http://{}", host)
+    }
+    else
+    {
+        format!("http://{}:{}", host, port)
+    }
+}
+
+/* POST the notification to /notify and return the reason given by the daemon */
+pub fn send(base : &str, token : Option<&str>, body : &serde_json::Value) -> Result<String, Box<dyn std::error::Error>>
+{
+    let mut options = vec![curl_option("header", "Content-Type: application/json")];
+
+    if let Some(t) = token
+    {
+        options.push(curl_option("header", format!("Authorization: Bearer {}", t).as_str()));
+    }
+
+    if body["wait"] == true
+    {
+        options.push(curl_option("max-time", WAIT_TIMEOUT_SECS.to_string().as_str()));
+    }
+
+    let (code, content) = curl(&format!("{}/notify", base), &options, Some(body.to_string().as_bytes()))?;
+
+    let answer = match serde_json::from_slice::<serde_json::Value>(&content) {
+        Ok(v) => v,
+        Err(_) => return Err(NotifydError::new(format!("{} answered HTTP {} : {}", base, code, String::from_utf8_lossy(&content).trim()).as_str()))
+    };
+
+    let reason = answer["reason"].as_str().unwrap_or_default().to_string();
+
+    if !(200..300).contains(&code) || answer["success"] == false
+    {
+        return Err(NotifydError::new(format!("{} : {}", reason, answer["err"].as_str().unwrap_or_default()).as_str()));
+    }
+
+    Ok(reason)
+}
@@ -0,0 +1,512 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use base64::Engine;
+use serde::{Serialize, Deserialize};
+use tempdir::TempDir;
+use crate::audio::PcmAudio;
+use crate::error::NotifydError;
+use crate::tts::{TtsBackend, Utterance, VoiceInfo, voice_for_lang};
+
+/******************
+ * CLOUD SETTINGS *
+ ******************/
+
+/* Enabled by a [google] section, the key being read from $GOOGLE_TTS_API_KEY */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GoogleConfig
+{
+    /* Voice spoken when none is requested, e.g. "en-US-Neural2-C" */
+    pub(crate) voice : Option<String>,
+    pub(crate) endpoint : String
+}
+
+impl Default for GoogleConfig
+{
+    fn default() -> Self
+    {
+        GoogleConfig {
+            voice : None,
+            endpoint : String::from("https://texttospeech.googleapis.com")
+        }
+    }
+}
+
+/* Enabled by a [polly] section, credentials being read from $AWS_ACCESS_KEY_ID,
+   $AWS_SECRET_ACCESS_KEY and optionally $AWS_SESSION_TOKEN */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PollyConfig
+{
+    pub(crate) region : String,
+    /* Voice spoken when none is requested, e.g. "Joanna" */
+    pub(crate) voice : Option<String>,
+    /* "standard", "neural", "long-form" or "generative" */
+    pub(crate) engine : String,
+    /* Defaults to the endpoint of the region */
+    pub(crate) endpoint : Option<String>
+}
+
+impl Default for PollyConfig
+{
+    fn default() -> Self
+    {
+        PollyConfig {
+            region : String::from("us-east-1"),
+            voice : None,
+            engine : String::from("neural"),
+            endpoint : None
+        }
+    }
+}
+
+/* Enabled by an [azure] section, the key being read from $AZURE_SPEECH_KEY */
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct AzureConfig
+{
+    /* Region of the Speech resource, e.g. "westeurope" */
+    pub(crate) region : Option<String>,
+    /* Voice spoken when none is requested, e.g. "fr-FR-DeniseNeural" */
+    pub(crate) voice : Option<String>,
+    /* Defaults to the endpoint of the region */
+    pub(crate) endpoint : Option<String>
+}
+
+pub(crate) fn env_key(name : &str) -> Result<String, Box<dyn std::error::Error>>
+{
+    match env::var(name) {
+        Ok(k) if !k.is_empty() => Ok(k),
+        _ => Err(NotifydError::new(format!("{} is not set", name).as_str()))
+    }
+}
+
+/*****************
+ * HTTP REQUESTS *
+ *****************/
+
+pub(crate) fn xml_escape(text : &str) -> String
+{
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/* Speech rates of SSML prosody, relative to the natural one */
+pub(crate) fn ssml_rate(factor : f32) -> String
+{
+    format!("{}%", (factor * 100.0).round() as i32)
+}
+
+/* SSML prosody attributes of an utterance, the pitch being left out when
+   unchanged as some voices reject it */
+pub(crate) fn ssml_prosody(u : &Utterance) -> String
+{
+    let mut attrs = format!("rate=\"{}\"", ssml_rate(u.factor));
+
+    if u.pitch != 1.0
+    {
+        attrs.push_str(format!(" pitch=\"{:+}%\"", ((u.pitch - 1.0) * 100.0).round() as i32).as_str());
+    }
+
+    attrs
+}
+
+/* A line of a curl config file */
+pub(crate) fn curl_option(name : &str, value : &str) -> String
+{
+    format!("{} = \"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/* Runs curl with the options given as curl config lines over stdin and the body
+   in a file, keeping keys and texts out of the process list. Returns the HTTP
+   status along with the answer */
+pub(crate) fn curl(url : &str, options : &[String], body : Option<&[u8]>) -> Result<(u16, Vec<u8>), Box<dyn std::error::Error>>
+{
+    let mut config = format!("{}\n", curl_option("url", url));
+
+    for o in options
+    {
+        config.push_str(o);
+        config.push('\n');
+    }
+
+    /* Dropped, and removed, once curl is done */
+    let scratch = TempDir::new("notifydreq")?;
+
+    if let Some(b) = body
+    {
+        let path = scratch.path().join("body");
+        File::create(&path)?.write_all(b)?;
+        config.push_str(format!("{}\n", curl_option("data-binary", format!("@{}", path.to_string_lossy()).as_str())).as_str());
+    }
+
+    let mut child = Command::new("curl")
+                    .args(["-s", "-S", "-m", "20", "-K", "-", "-w", "%{http_code}"])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("Failed to open stdin");
+    stdin.write_all(config.as_bytes())?;
+    drop(stdin);
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success()
+    {
+        return Err(NotifydError::new(format!("Request to {} failed : {}", url, String::from_utf8_lossy(&output.stderr).trim()).as_str()));
+    }
+
+    /* The status is written after the answer */
+    let mut content = output.stdout;
+
+    if content.len() < 3
+    {
+        return Err(NotifydError::new(format!("No answer from {}", url).as_str()));
+    }
+
+    let code = String::from_utf8_lossy(&content.split_off(content.len() - 3)).parse::<u16>()?;
+
+    Ok((code, content))
+}
+
+/* The answer of a successful request, the error reported by the service otherwise */
+pub(crate) fn expect_success(service : &str, code : u16, content : Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>>
+{
+    if (200..300).contains(&code)
+    {
+        return Ok(content);
+    }
+
+    let desc = match serde_json::from_slice::<serde_json::Value>(&content) {
+        Ok(v) => {
+            let msg = [&v["error"]["message"], &v["message"], &v["Message"]].into_iter().find_map(|m| m.as_str());
+            msg.map(|m| m.to_string()).unwrap_or(v.to_string())
+        },
+        Err(_) => String::from_utf8_lossy(&content).trim().to_string()
+    };
+
+    if desc.is_empty()
+    {
+        return Err(NotifydError::new(format!("{} answered HTTP {}", service, code).as_str()));
+    }
+
+    Err(NotifydError::new(format!("{} answered HTTP {} : {}", service, code, desc).as_str()))
+}
+
+pub(crate) fn no_phonemes(service : &str, u : &Utterance) -> Result<(), Box<dyn std::error::Error>>
+{
+    if u.phonemes.is_some()
+    {
+        return Err(NotifydError::new(format!("TTS engine {} does not accept phoneme input", service).as_str()));
+    }
+    Ok(())
+}
+
+/********************
+ * GOOGLE CLOUD TTS *
+ ********************/
+
+pub struct GoogleTts
+{
+    pub(crate) config : GoogleConfig,
+    pub(crate) key : String
+}
+
+impl GoogleTts
+{
+    pub(crate) fn new(config : &GoogleConfig) -> Result<GoogleTts, Box<dyn std::error::Error>>
+    {
+        Ok(GoogleTts {
+            config : config.clone(),
+            key : env_key("GOOGLE_TTS_API_KEY")?
+        })
+    }
+
+    pub(crate) fn request(self : &Self, path : &str, body : Option<serde_json::Value>) -> Result<serde_json::Value, Box<dyn std::error::Error>>
+    {
+        let url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), path);
+        let mut options = vec![curl_option("header", format!("X-Goog-Api-Key: {}", self.key).as_str())];
+
+        if body.is_some()
+        {
+            options.push(curl_option("header", "Content-Type: application/json"));
+        }
+
+        let (code, content) = curl(&url, &options, body.map(|b| b.to_string().into_bytes()).as_deref())?;
+
+        Ok(serde_json::from_slice(&expect_success("Google Cloud TTS", code, content)?)?)
+    }
+}
+
+impl TtsBackend for GoogleTts
+{
+    fn location(self : &Self) -> String
+    {
+        self.config.endpoint.clone()
+    }
+
+    fn voices(self : &Self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>>
+    {
+        let answer = self.request("/v1/voices", None)?;
+        let mut voices = Vec::new();
+
+        for v in answer["voices"].as_array().unwrap_or(&Vec::new())
+        {
+            let name = v["name"].as_str().unwrap_or("");
+
+            for l in v["languageCodes"].as_array().unwrap_or(&Vec::new()).iter().filter_map(|l| l.as_str())
+            {
+                voices.push(VoiceInfo {
+                    name : name.to_string(),
+                    lang : l.to_string()
+                });
+            }
+        }
+
+        Ok(voices)
+    }
+
+    fn synthesize(self : &Self, u : &Utterance) -> Result<String, Box<dyn std::error::Error>>
+    {
+        no_phonemes("google", u)?;
+
+        let voice = u.voice.map(|v| v.to_string()).or(self.config.voice.clone());
+
+        /* The language is required, the voice only narrows it down */
+        let mut selection = serde_json::json!({ "languageCode" : u.lang });
+
+        if let Some(v) = &voice
+        {
+            selection["name"] = serde_json::json!(v);
+            /* Voices are named after their language, e.g. "fr-FR-Neural2-A" */
+            selection["languageCode"] = serde_json::json!(v.split('-').take(2).collect::<Vec<&str>>().join("-"));
+        }
+
+        let answer = self.request("/v1/text:synthesize", Some(serde_json::json!({
+            "input" : { "text" : u.text },
+            "voice" : selection,
+            /* LINEAR16 comes with a WAV header */
+            "audioConfig" : {
+                "audioEncoding" : "LINEAR16",
+                "speakingRate" : u.factor,
+                /* In semitones */
+                "pitch" : (12.0 * u.pitch.log2()).clamp(-20.0, 20.0)
+            }
+        })))?;
+
+        let audio = match answer["audioContent"].as_str() {
+            Some(a) => base64::engine::general_purpose::STANDARD.decode(a)?,
+            None => return Err(NotifydError::new("Google Cloud TTS answered without audio"))
+        };
+
+        File::create(u.outpath)?.write_all(&audio)?;
+
+        Ok(voice.unwrap_or(u.lang.to_string()))
+    }
+}
+
+/****************
+ * AMAZON POLLY *
+ ****************/
+
+pub struct Polly
+{
+    pub(crate) config : PollyConfig,
+    pub(crate) access_key : String,
+    pub(crate) secret_key : String,
+    pub(crate) session_token : Option<String>
+}
+
+/* Rate of the PCM Polly answers with */
+pub const POLLY_RATE : u32 = 16000;
+
+impl Polly
+{
+    pub(crate) fn new(config : &PollyConfig) -> Result<Polly, Box<dyn std::error::Error>>
+    {
+        Ok(Polly {
+            config : config.clone(),
+            access_key : env_key("AWS_ACCESS_KEY_ID")?,
+            secret_key : env_key("AWS_SECRET_ACCESS_KEY")?,
+            session_token : env_key("AWS_SESSION_TOKEN").ok()
+        })
+    }
+
+    pub(crate) fn endpoint(self : &Self) -> String
+    {
+        match &self.config.endpoint {
+            Some(e) => e.trim_end_matches('/').to_string(),
+            None => format!("https://polly.{}.amazonaws.com", self.config.region)
+        }
+    }
+
+    /* Requests are signed by curl */
+    pub(crate) fn request(self : &Self, path : &str, body : Option<serde_json::Value>) -> Result<Vec<u8>, Box<dyn std::error::Error>>
+    {
+        let url = format!("{}{}", self.endpoint(), path);
+        let mut options = vec![curl_option("user", format!("{}:{}", self.access_key, self.secret_key).as_str()),
+                               curl_option("aws-sigv4", format!("aws:amz:{}:polly", self.config.region).as_str())];
+
+        if let Some(t) = &self.session_token
+        {
+            options.push(curl_option("header", format!("X-Amz-Security-Token: {}", t).as_str()));
+        }
+
+        if body.is_some()
+        {
+            options.push(curl_option("header", "Content-Type: application/json"));
+        }
+
+        let (code, content) = curl(&url, &options, body.map(|b| b.to_string().into_bytes()).as_deref())?;
+
+        expect_success("Amazon Polly", code, content)
+    }
+}
+
+impl TtsBackend for Polly
+{
+    fn location(self : &Self) -> String
+    {
+        self.endpoint()
+    }
+
+    fn voices(self : &Self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>>
+    {
+        let answer : serde_json::Value = serde_json::from_slice(&self.request(format!("/v1/voices?Engine={}", self.config.engine).as_str(), None)?)?;
+
+        Ok(answer["Voices"].as_array().unwrap_or(&Vec::new()).iter().map(|v| VoiceInfo {
+            name : v["Id"].as_str().unwrap_or("").to_string(),
+            lang : v["LanguageCode"].as_str().unwrap_or("").to_string()
+        }).collect())
+    }
+
+    fn synthesize(self : &Self, u : &Utterance) -> Result<String, Box<dyn std::error::Error>>
+    {
+        no_phonemes("polly", u)?;
+
+        /* Polly has no default voice, pick one speaking the language */
+        let voice = match u.voice.map(|v| v.to_string()).or(self.config.voice.clone()) {
+            Some(v) => v,
+            None => match voice_for_lang(&self.voices()?, u.lang) {
+                Some(v) => v.name.clone(),
+                None => return Err(NotifydError::new(format!("No Polly voice speaks {}, set [polly] voice", u.lang).as_str()))
+            }
+        };
+
+        let ssml = format!("<speak><prosody {}>{}</prosody></speak>", ssml_prosody(u), xml_escape(u.text));
+
+        let pcm = self.request("/v1/speech", Some(serde_json::json!({
+            "Engine" : self.config.engine,
+            "OutputFormat" : "pcm",
+            "SampleRate" : POLLY_RATE.to_string(),
+            "Text" : ssml,
+            "TextType" : "ssml",
+            "VoiceId" : voice
+        })))?;
+
+        /* 16 bits mono samples without any header */
+        let mut out = PcmAudio::wav_header(POLLY_RATE, 1, pcm.len() as u32);
+        out.extend_from_slice(&pcm);
+        File::create(u.outpath)?.write_all(&out)?;
+
+        Ok(voice)
+    }
+}
+
+/****************
+ * AZURE SPEECH *
+ ****************/
+
+pub struct AzureTts
+{
+    pub(crate) config : AzureConfig,
+    pub(crate) key : String
+}
+
+impl AzureTts
+{
+    pub(crate) fn new(config : &AzureConfig) -> Result<AzureTts, Box<dyn std::error::Error>>
+    {
+        if config.region.is_none() && config.endpoint.is_none()
+        {
+            return Err(NotifydError::new("Azure Speech requires a region in the [azure] section"));
+        }
+
+        Ok(AzureTts {
+            config : config.clone(),
+            key : env_key("AZURE_SPEECH_KEY")?
+        })
+    }
+
+    pub(crate) fn endpoint(self : &Self) -> String
+    {
+        match &self.config.endpoint {
+            Some(e) => e.trim_end_matches('/').to_string(),
+            None => format!("https://{}.tts.speech.microsoft.com", self.config.region.clone().unwrap_or_default())
+        }
+    }
+
+    pub(crate) fn request(self : &Self, path : &str, ssml : Option<String>) -> Result<Vec<u8>, Box<dyn std::error::Error>>
+    {
+        let url = format!("{}{}", self.endpoint(), path);
+        let mut options = vec![curl_option("header", format!("Ocp-Apim-Subscription-Key: {}", self.key).as_str()),
+                               curl_option("user-agent", "notifyd")];
+
+        if ssml.is_some()
+        {
+            options.push(curl_option("header", "Content-Type: application/ssml+xml"));
+            options.push(curl_option("header", "X-Microsoft-OutputFormat: riff-24khz-16bit-mono-pcm"));
+        }
+
+        let (code, content) = curl(&url, &options, ssml.map(|s| s.into_bytes()).as_deref())?;
+
+        expect_success("Azure Speech", code, content)
+    }
+}
+
+impl TtsBackend for AzureTts
+{
+    fn location(self : &Self) -> String
+    {
+        self.endpoint()
+    }
+
+    fn voices(self : &Self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>>
+    {
+        let answer : serde_json::Value = serde_json::from_slice(&self.request("/cognitiveservices/voices/list", None)?)?;
+
+        Ok(answer.as_array().unwrap_or(&Vec::new()).iter().map(|v| VoiceInfo {
+            name : v["ShortName"].as_str().unwrap_or("").to_string(),
+            lang : v["Locale"].as_str().unwrap_or("").to_string()
+        }).collect())
+    }
+
+    fn synthesize(self : &Self, u : &Utterance) -> Result<String, Box<dyn std::error::Error>>
+    {
+        no_phonemes("azure", u)?;
+
+        let voice = match u.voice.map(|v| v.to_string()).or(self.config.voice.clone()) {
+            Some(v) => v,
+            None => match voice_for_lang(&self.voices()?, u.lang) {
+                Some(v) => v.name.clone(),
+                None => return Err(NotifydError::new(format!("No Azure voice speaks {}, set [azure] voice", u.lang).as_str()))
+            }
+        };
+
+        let ssml = format!("<speak version=\"1.0\" xmlns=\"http://www.w3.org/2001/10/synthesis\" xml:lang=\"{}\">\
+                            <voice name=\"{}\"><prosody {}>{}</prosody></voice></speak>",
+                           xml_escape(u.lang), xml_escape(&voice), ssml_prosody(u), xml_escape(u.text));
+
+        let audio = self.request("/cognitiveservices/v1", Some(ssml))?;
+        File::create(u.outpath)?.write_all(&audio)?;
+
+        Ok(voice)
+    }
+}
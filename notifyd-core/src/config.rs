@@ -0,0 +1,337 @@
+use std::path::PathBuf;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::airplay::AirPlayConfig;
+use crate::mqtt::MqttConfig;
+use crate::snapcast::SnapcastConfig;
+use crate::access_log::AccessLogConfig;
+use crate::github::GitHubConfig;
+use crate::webhooks::WebhookConfig;
+use crate::callbacks::CallbackConfig;
+use crate::announcements::WeatherConfig;
+use crate::audio::{BeepConfig, ChimeConfig, VolumeConfig};
+use crate::auth::AuthConfig;
+use crate::caster::CastConfig;
+use crate::cloud::{AzureConfig, GoogleConfig, PollyConfig};
+use crate::dbus::DbusConfig;
+use crate::desktop::DesktopConfig;
+use crate::dedup::DedupConfig;
+use crate::digest::DigestConfig;
+use crate::error::NotifydError;
+use crate::history::{ArchiveConfig, HistoryConfig};
+use crate::idempotency::IdempotencyConfig;
+use crate::journal::PersistenceConfig;
+use crate::limits::LimitsConfig;
+use crate::queue::{PreemptionConfig, Priority};
+use crate::quiet::QuietConfig;
+use crate::rules::RuleConfig;
+use crate::templates::PresetConfig;
+use crate::sinks::TerminalConfig;
+use crate::timers::AlarmConfig;
+use crate::tts::{CacheConfig, DispatchConfig, PiperConfig, Speed};
+
+/*****************
+ * CONFIGURATION *
+ *****************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WatchdogConfig
+{
+    /* Seconds between two reachability checks */
+    pub(crate) interval : u64,
+    /* Where to announce that a target went offline ("local" for the local speaker) */
+    pub(crate) alert_target : Option<String>,
+    /* "{target}" is substituted */
+    pub(crate) alert_message : String
+}
+
+impl Default for WatchdogConfig
+{
+    fn default() -> Self
+    {
+        WatchdogConfig {
+            interval : 60,
+            alert_target : None,
+            alert_message : String::from("{target} is unreachable")
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CleanupConfig
+{
+    /* Seconds between two sweeps of the tmpdir, 0 disables them */
+    pub(crate) interval : u64,
+    /* Seconds after which a file nothing claims is removed */
+    pub(crate) stale_after : u64
+}
+
+impl Default for CleanupConfig
+{
+    fn default() -> Self
+    {
+        CleanupConfig {
+            interval : 60,
+            stale_after : 3600 * 12
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ShutdownConfig
+{
+    /* Seconds given to the queue to play out on SIGTERM or SIGINT before the rest is dropped */
+    pub(crate) drain_secs : u64
+}
+
+impl Default for ShutdownConfig
+{
+    fn default() -> Self
+    {
+        ShutdownConfig {
+            drain_secs : 30
+        }
+    }
+}
+
+/* Defaults applied to requests tagged with a given topic */
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct TopicConfig
+{
+    pub(crate) speed : Option<Speed>,
+    pub(crate) rate : Option<f32>,
+    pub(crate) pitch : Option<f32>,
+    pub(crate) gain : Option<f32>,
+    pub(crate) volume : Option<f32>,
+    pub(crate) variant : Option<String>,
+    /* Either an engine specific voice name or an "engine:voice" spec */
+    pub(crate) voice : Option<String>,
+    pub(crate) priority : Option<Priority>,
+    /* Where /notify/t/<topic> and the notifications of the topic play unless they name a target */
+    pub(crate) target : Option<String>,
+    /* Secrets only allowed to post to /notify/t/<topic>, required there once one is set */
+    pub(crate) tokens : Vec<String>,
+    /* Times these notifications are played in a row, and the silence in between */
+    pub(crate) repeat : Option<u32>,
+    pub(crate) repeat_gap_ms : Option<u64>,
+    /* How the daily digest names these notifications, e.g. "package deliveries" */
+    pub(crate) digest_label : Option<String>,
+    /* Days the audio of these notifications is archived, 0 to keep none */
+    pub(crate) archive_days : Option<u32>,
+    /* Played before these notifications instead of the configured chime, "" for none */
+    pub(crate) chime : Option<PathBuf>
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Config
+{
+    /* File the configuration was read from */
+    #[serde(skip)]
+    pub(crate) source : Option<PathBuf>,
+    /* Instance of that file this configuration is merged for */
+    #[serde(skip)]
+    pub(crate) instance : Option<String>,
+    /* Default "engine" or "engine:voice", the first installed engine when absent */
+    pub(crate) engine : Option<String>,
+    /* Language to "engine:voice" mapping, e.g. "fr-FR" = "pipertts:fr_FR-siwis-medium" */
+    pub(crate) voices : HashMap<String, String>,
+    /* Overrides of the speed-up factor of each preset, e.g. slow = 0.7 */
+    pub(crate) speeds : HashMap<Speed, f32>,
+    pub(crate) topics : HashMap<String, TopicConfig>,
+    /* Phrasings filled by /notify/template/<name>, e.g. washer = "The washing machine finished after {minutes} minutes",
+       "washer.fr" = "..." being used for the requests in French */
+    pub(crate) templates : HashMap<String, String>,
+    /* Announcements played as configured by /notify/preset/<name>, for buttons sending an empty body */
+    pub(crate) presets : HashMap<String, PresetConfig>,
+    /* Routing rules, the first one a notification matches deciding where and how it plays */
+    pub(crate) rules : Vec<RuleConfig>,
+    /* Names standing for a target, e.g. kitchen = "<UUID>" or desk = "local" */
+    pub(crate) devices : HashMap<String, String>,
+    /* Targets played at once, e.g. downstairs = ["kitchen", "living-room"] */
+    pub(crate) groups : HashMap<String, Vec<String>>,
+    /* Voice used when no engine supports the requested language */
+    pub(crate) fallback_voice : Option<String>,
+    /* Said before messages spoken with the fallback voice, "{lang}" is substituted */
+    pub(crate) fallback_note : Option<String>,
+    /* One line per HTTP request, disabled when absent */
+    pub(crate) access_log : Option<AccessLogConfig>,
+    /* Lifecycle signals on the bus, disabled when absent */
+    pub(crate) dbus : Option<DbusConfig>,
+    /* Popups shown with notify-send as notifications are queued, disabled when absent */
+    pub(crate) desktop : Option<DesktopConfig>,
+    /* Notifications published to a broker, disabled when absent */
+    pub(crate) mqtt : Option<MqttConfig>,
+    /* CI results announced from GitHub webhooks, enabled by its section */
+    pub(crate) github : Option<GitHubConfig>,
+    /* Webhooks served under /webhooks/<name>, each turning its JSON body into text */
+    pub(crate) webhooks : HashMap<String, WebhookConfig>,
+    /* URLs posted to as notifications are queued, played or fail */
+    pub(crate) callbacks : Vec<CallbackConfig>,
+    pub(crate) cast : CastConfig,
+    /* Sonos zones by name, e.g. "Living room" = "192.168.1.20" */
+    pub(crate) sonos : HashMap<String, String>,
+    pub(crate) airplay : AirPlayConfig,
+    /* Synchronized multi-room playback through snapserver, enabled by its section */
+    pub(crate) snapcast : Option<SnapcastConfig>,
+    pub(crate) archive : ArchiveConfig,
+    pub(crate) history : HistoryConfig,
+    /* Sentence spoken by /admin/selftest */
+    pub(crate) selftest_phrase : Option<String>,
+    /* Seconds during which a cast file is protected from flushes */
+    pub(crate) cast_fetch_ttl : Option<u64>,
+    /* Synthesized audio served under /static/, swept by the janitor, a temporary directory when absent */
+    pub(crate) static_dir : Option<PathBuf>,
+    pub(crate) cleanup : CleanupConfig,
+    pub(crate) shutdown : ShutdownConfig,
+    pub(crate) persistence : PersistenceConfig,
+    pub(crate) preemption : PreemptionConfig,
+    pub(crate) watchdog : WatchdogConfig,
+    pub(crate) limits : LimitsConfig,
+    pub(crate) beep : BeepConfig,
+    pub(crate) chimes : ChimeConfig,
+    pub(crate) volume : VolumeConfig,
+    pub(crate) digest : DigestConfig,
+    pub(crate) quiet : QuietConfig,
+    pub(crate) dedup : DedupConfig,
+    pub(crate) idempotency : IdempotencyConfig,
+    pub(crate) weather : WeatherConfig,
+    pub(crate) alarms : AlarmConfig,
+    /* Named sounds /action/play and alarms can use, e.g. doorbell = "/usr/share/sounds/doorbell.wav" */
+    pub(crate) sounds : HashMap<String, PathBuf>,
+    pub(crate) terminal : TerminalConfig,
+    pub(crate) dispatch : DispatchConfig,
+    /* Reuse of the audio synthesized for identical requests */
+    pub(crate) cache : CacheConfig,
+    pub(crate) piper : PiperConfig,
+    /* Cloud engines, each enabled by its section */
+    pub(crate) google : Option<GoogleConfig>,
+    pub(crate) polly : Option<PollyConfig>,
+    pub(crate) azure : Option<AzureConfig>,
+    /* Play local audio with this command (e.g. ["aplay", "-q"]) instead of Soloud */
+    pub(crate) player : Option<Vec<String>>,
+    /* Trade features for memory on small boards */
+    pub(crate) low_footprint : bool,
+    /* Said before every announcement, "{hostname}" is substituted */
+    pub(crate) prefix : Option<String>,
+    /* SQLite database, ":memory:" keeping nothing across restarts */
+    pub(crate) database : Option<PathBuf>,
+    pub(crate) auth : AuthConfig,
+    /* Named instances, each overriding parts of this configuration */
+    pub(crate) instances : HashMap<String, toml::Table>,
+    /* The file as read, instances being merged over it */
+    #[serde(skip)]
+    pub(crate) raw : toml::Table
+}
+
+/* Keys of an instance section that are not configuration overrides */
+#[derive(Deserialize, Default)]
+pub struct InstanceSettings
+{
+    #[serde(skip)]
+    pub name : String,
+    /* Default target of the instance */
+    pub target : Option<String>,
+    /* Served on its own port when given, under /<name>/ otherwise */
+    pub port : Option<u32>,
+    pub lang : Option<String>
+}
+
+impl Config
+{
+    pub fn load(path : &Path) -> Result<Config, Box<dyn std::error::Error>>
+    {
+        let content = std::fs::read_to_string(path)?;
+        let mut config : Config = toml::from_str(&content)?;
+        config.source = Some(path.to_path_buf());
+        config.raw = toml::from_str(&content)?;
+        Ok(config)
+    }
+
+    pub(crate) fn merge(base : &mut toml::Table, overlay : &toml::Table)
+    {
+        for (k, v) in overlay
+        {
+            match (base.get_mut(k), v) {
+                (Some(toml::Value::Table(b)), toml::Value::Table(o)) => Config::merge(b, o),
+                _ => {
+                    base.insert(k.clone(), v.clone());
+                }
+            }
+        }
+    }
+
+    /* Configuration of every named instance, merged over the main one */
+    pub fn instance_configs(self : &Self) -> Result<Vec<(InstanceSettings, Config)>, Box<dyn std::error::Error>>
+    {
+        let mut ret = Vec::new();
+
+        for (name, overlay) in &self.instances
+        {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(NotifydError::new(format!("Invalid instance name '{}'", name).as_str()));
+            }
+
+            let mut settings : InstanceSettings = toml::Value::Table(overlay.clone()).try_into()?;
+            settings.name = name.clone();
+
+            let mut overlay = overlay.clone();
+            for k in ["target", "port", "lang"]
+            {
+                overlay.remove(k);
+            }
+
+            let mut merged = self.raw.clone();
+            merged.remove("instances");
+            Config::merge(&mut merged, &overlay);
+
+            let mut config : Config = toml::Value::Table(merged.clone()).try_into()?;
+            config.source = self.source.clone();
+            config.instance = Some(name.clone());
+            config.raw = merged;
+
+            ret.push((settings, config));
+        }
+
+        Ok(ret)
+    }
+
+    pub(crate) fn is_secret(key : &str) -> bool
+    {
+        let key = key.to_lowercase();
+        ["key", "token", "secret", "password", "passwd", "credential"].iter().any(|s| key.contains(s))
+    }
+
+    /* Mask the value of every field looking like a secret */
+    pub(crate) fn redact(value : &mut serde_json::Value)
+    {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (k, v) in map.iter_mut()
+                {
+                    if Config::is_secret(k) && !v.is_null()
+                    {
+                        *v = serde_json::Value::String(String::from("<redacted>"));
+                    }
+                    else
+                    {
+                        Config::redact(v);
+                    }
+                }
+            },
+            serde_json::Value::Array(a) => {
+                for v in a.iter_mut()
+                {
+                    Config::redact(v);
+                }
+            },
+            _ => {}
+        }
+    }
+}
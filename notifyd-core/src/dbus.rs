@@ -0,0 +1,103 @@
+use which::which;
+use std::process::Command;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::error::NotifydError;
+use crate::queue::{JobInfo, Priority};
+
+/*****************
+ * D-BUS SIGNALS *
+ *****************/
+
+pub const DBUS_PATH : &str = "/org/notifyd/Notifyd";
+pub const DBUS_INTERFACE : &str = "org.notifyd.Notifyd";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DbusBus
+{
+    #[default]
+    Session,
+    System
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DbusConfig
+{
+    pub(crate) bus : DbusBus
+}
+
+/* Lifecycle signals for local tools, sent with dbus-send */
+pub struct DbusSignals
+{
+    pub(crate) bus : DbusBus
+}
+
+impl DbusSignals
+{
+    pub(crate) fn new(config : &DbusConfig) -> Result<DbusSignals, Box<dyn std::error::Error>>
+    {
+        if which("dbus-send").is_err()
+        {
+            return Err(NotifydError::new("Cannot locate dbus-send in path"));
+        }
+
+        Ok(DbusSignals {
+            bus : config.bus
+        })
+    }
+
+    pub(crate) fn emit(self : &Self, member : &str, args : Vec<String>)
+    {
+        let mut cmd = Command::new("dbus-send");
+        cmd.arg(match self.bus {
+               DbusBus::Session => "--session",
+               DbusBus::System => "--system"
+           })
+           .arg("--type=signal")
+           .arg(DBUS_PATH)
+           .arg(format!("{}.{}", DBUS_INTERFACE, member))
+           .args(args);
+
+        /* Never hold the queue back for a signal */
+        let member = member.to_string();
+        std::thread::spawn(move || {
+            match cmd.output() {
+                Ok(out) if !out.status.success() => {
+                    warn!("Failed to emit D-Bus signal {} : {}", member, String::from_utf8_lossy(&out.stderr).trim());
+                },
+                Err(e) => {
+                    warn!("Failed to emit D-Bus signal {} : {}", member, e);
+                },
+                _ => {}
+            }
+        });
+    }
+
+    /* Received(uint64 id, string text, string target, string priority) */
+    pub(crate) fn received(self : &Self, id : u64, text : &str, target : &str, priority : Priority)
+    {
+        self.emit("Received", vec![format!("uint64:{}", id),
+                                   format!("string:{}", text),
+                                   format!("string:{}", target),
+                                   format!("string:{:?}", priority).to_lowercase()]);
+    }
+
+    /* Played(uint64 id, string text, string target) */
+    pub(crate) fn played(self : &Self, job : &JobInfo)
+    {
+        self.emit("Played", vec![format!("uint64:{}", job.id),
+                                 format!("string:{}", job.text),
+                                 format!("string:{}", job.target)]);
+    }
+
+    /* Failed(uint64 id, string text, string target, string error) */
+    pub(crate) fn failed(self : &Self, job : &JobInfo, err : &str)
+    {
+        self.emit("Failed", vec![format!("uint64:{}", job.id),
+                                 format!("string:{}", job.text),
+                                 format!("string:{}", job.target),
+                                 format!("string:{}", err)]);
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+/*****************
+ * DEDUPLICATION *
+ *****************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DedupConfig
+{
+    /* Seconds from the first notification of a burst during which the same ones are merged, 0 disables it */
+    pub(crate) window_secs : u64,
+    /* Also merge notifications without a dedup_key when their text is the same */
+    pub(crate) identical_text : bool,
+    /* What the pending notification says once merged, "{text}" and "{count}" are substituted */
+    pub(crate) message : String
+}
+
+impl Default for DedupConfig
+{
+    fn default() -> Self
+    {
+        DedupConfig {
+            window_secs : 60,
+            identical_text : false,
+            message : String::from("{text}, {count} times")
+        }
+    }
+}
+
+impl DedupConfig
+{
+    pub(crate) fn merged_text(self : &Self, text : &str, count : u32) -> String
+    {
+        self.message.replace("{text}", text).replace("{count}", &count.to_string())
+    }
+}
+
+pub struct Burst
+{
+    pub(crate) first : Instant,
+    pub(crate) count : u32,
+    /* Text of the first notification, as queued */
+    pub(crate) text : String
+}
+
+/* Notifications seen per target and key during their window */
+pub struct Deduplicator
+{
+    pub(crate) bursts : Mutex<HashMap<(String, String), Burst>>
+}
+
+impl Deduplicator
+{
+    pub(crate) fn new() -> Deduplicator
+    {
+        Deduplicator {
+            bursts : Mutex::new(HashMap::new())
+        }
+    }
+
+    /* Number of notifications of the burst so far along with the text of its first one,
+       a notification arriving once the window is over starting a new burst */
+    pub(crate) fn observe(self : &Self, target : &str, key : &str, text : &str, window : Duration) -> (u32, String)
+    {
+        let mut bursts = self.bursts.lock().unwrap();
+        bursts.retain(|_, b| b.first.elapsed() < window);
+
+        let burst = bursts.entry((target.to_string(), key.to_string())).or_insert(Burst {
+            first : Instant::now(),
+            count : 0,
+            text : text.to_string()
+        });
+
+        burst.count += 1;
+        (burst.count, burst.text.clone())
+    }
+}
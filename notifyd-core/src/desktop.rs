@@ -0,0 +1,97 @@
+use std::process::Command;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use which::which;
+use crate::error::NotifydError;
+use crate::queue::Priority;
+
+/*************************
+ * DESKTOP NOTIFICATIONS *
+ *************************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DesktopConfig
+{
+    /* Title of the popups, "{topic}" being substituted */
+    pub(crate) summary : String,
+    pub(crate) app_name : String,
+    /* Icon name or path */
+    pub(crate) icon : Option<String>,
+    /* Time the popups stay, as the desktop decides when absent */
+    pub(crate) expire_secs : Option<u64>,
+    /* Less important notifications are only spoken */
+    pub(crate) min_priority : Priority
+}
+
+impl Default for DesktopConfig
+{
+    fn default() -> Self
+    {
+        DesktopConfig {
+            summary : String::from("notifyd"),
+            app_name : String::from("notifyd"),
+            icon : None,
+            expire_secs : None,
+            min_priority : Priority::Low
+        }
+    }
+}
+
+impl DesktopConfig
+{
+    pub(crate) fn check(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        if which("notify-send").is_err()
+        {
+            return Err(NotifydError::new("Cannot locate notify-send in path"));
+        }
+
+        Ok(())
+    }
+
+    /* Pop the text up with notify-send, alongside its announcement */
+    pub(crate) fn show(self : &Self, text : &str, topic : Option<&str>, priority : Priority)
+    {
+        if priority < self.min_priority
+        {
+            return;
+        }
+
+        let urgency = match priority {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High | Priority::Urgent => "critical"
+        };
+
+        let summary = self.summary.replace("{topic}", topic.unwrap_or_default());
+
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["-a", &self.app_name, "-u", urgency]);
+
+        if let Some(icon) = &self.icon
+        {
+            cmd.args(["-i", icon]);
+        }
+
+        if let Some(secs) = self.expire_secs
+        {
+            cmd.args(["-t", &(secs * 1000).to_string()]);
+        }
+
+        cmd.args(["--", summary.trim(), text]);
+
+        /* Never hold the queue back for a popup */
+        std::thread::spawn(move || {
+            match cmd.output() {
+                Ok(out) if !out.status.success() => {
+                    warn!("Failed to show the desktop notification : {}", String::from_utf8_lossy(&out.stderr).trim());
+                },
+                Err(e) => {
+                    warn!("Failed to show the desktop notification : {}", e);
+                },
+                _ => {}
+            }
+        });
+    }
+}
@@ -0,0 +1,36 @@
+use serde::{Serialize, Deserialize};
+use crate::queue::Priority;
+
+/****************
+ * DAILY DIGEST *
+ ****************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DigestConfig
+{
+    /* Local time of the announcement ("HH:MM"), disabled when absent */
+    pub(crate) at : Option<String>,
+    /* Notifications up to this priority are kept for the digest */
+    pub(crate) priority : Priority,
+    pub(crate) intro : String
+}
+
+impl Default for DigestConfig
+{
+    fn default() -> Self
+    {
+        DigestConfig {
+            at : None,
+            priority : Priority::Low,
+            intro : String::from("Today there were")
+        }
+    }
+}
+
+pub struct DigestItem
+{
+    pub(crate) time : chrono::DateTime<chrono::Local>,
+    pub(crate) text : String,
+    pub(crate) topic : Option<String>
+}
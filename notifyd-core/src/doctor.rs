@@ -0,0 +1,226 @@
+use std::path::PathBuf;
+use which::which;
+use crate::audio::AudioOutput;
+use crate::caster::Caster;
+use crate::config::Config;
+use crate::tts::{Backends, TTS, VoiceSpec};
+
+/**********
+ * DOCTOR *
+ **********/
+
+pub struct Doctor
+{
+    pub(crate) failures : usize
+}
+
+impl Doctor
+{
+    pub(crate) fn ok(self : &mut Self, what : &str)
+    {
+        println!("[ OK ] {}", what);
+    }
+
+    pub(crate) fn warn(self : &mut Self, what : &str, hint : &str)
+    {
+        println!("[WARN] {}\n       -> {}", what, hint);
+    }
+
+    pub(crate) fn fail(self : &mut Self, what : &str, hint : &str)
+    {
+        self.failures += 1;
+        println!("[FAIL] {}\n       -> {}", what, hint);
+    }
+
+    /* Cloud engines are only checked when the configuration enables them */
+    pub(crate) fn check_engines(self : &mut Self, config : &Option<PathBuf>)
+    {
+        let mut found = false;
+
+        let backends = match config.as_ref().map(|p| Config::load(p)) {
+            Some(Ok(c)) => Backends::new(&c),
+            _ => Backends::default()
+        };
+
+        for e in TTS::all_engines()
+        {
+            let name = TTS::tts_to_bin_name(&e);
+
+            match TTS::backend(&e, &backends) {
+                Ok(b) => {
+                    found = true;
+                    match b.voices() {
+                        Ok(v) => self.ok(format!("TTS engine {} at {} ({} voices)", name, b.location(), v.len()).as_str()),
+                        Err(err) if e.is_cloud() => self.warn(format!("TTS engine {} at {} failed to list its voices: {}", name, b.location(), err).as_str(),
+                                                              "check the API key in the environment and that the service is reachable"),
+                        Err(err) => self.warn(format!("TTS engine {} at {} failed to list its voices: {}", name, b.location(), err).as_str(),
+                                              "check the engine runs by hand, for piper that PIPER_VOICES or [piper] voices points to the models")
+                    }
+                },
+                Err(err) if e.is_cloud() => {
+                    println!("[ -- ] TTS engine {} not available: {}", name, err);
+                },
+                Err(_) => {
+                    println!("[ -- ] TTS engine {} not installed", name);
+                }
+            }
+        }
+
+        if !found
+        {
+            self.fail("No TTS engine found in PATH", "install piper, pico2wave, espeak or espeak-ng, or configure a cloud engine");
+        }
+
+        match which("ffmpeg") {
+            Ok(_) => self.ok("ffmpeg is available for audio transcoding"),
+            Err(_) => self.warn("ffmpeg not found", "install ffmpeg to serve audio/mpeg content and compressed cast audio")
+        }
+    }
+
+    pub(crate) fn check_audio(self : &mut Self)
+    {
+        match AudioOutput::open(&None) {
+            Ok(_) => self.ok("Local audio output initialized"),
+            Err(e) => self.warn(format!("Cannot initialize local audio output: {}", e).as_str(),
+                                "local playback (/action/speak) will fail, check the sound server or ALSA device permissions")
+        }
+    }
+
+    pub(crate) fn check_network(self : &mut Self, port : u32)
+    {
+        use local_ip_address::{local_ip, list_afinet_netifas};
+
+        match local_ip() {
+            Ok(ip) if ip.is_loopback() => self.fail(format!("Local IP resolves to loopback {}", ip).as_str(),
+                                                    "cast devices will not be able to fetch audio, connect the host to the LAN"),
+            Ok(ip) => self.ok(format!("Cast devices will fetch audio from http://{}:{}/static/", ip, port).as_str()),
+            Err(e) => self.fail(format!("Cannot determine the local IP: {}", e).as_str(),
+                                "cast devices will not be able to fetch audio, check the network configuration")
+        }
+
+        if let Ok(ifs) = list_afinet_netifas()
+        {
+            for (name, ip) in ifs.iter().filter(|(_, ip)| !ip.is_loopback())
+            {
+                println!("[ -- ] Interface {} : {}", name, ip);
+            }
+        }
+    }
+
+    pub(crate) fn check_cast(self : &mut Self, target_uuid : &str)
+    {
+        if target_uuid == "terminal"
+        {
+            match which("wall") {
+                Ok(_) => self.ok("Notifications are written to terminals"),
+                Err(_) => self.warn("wall not found", "install util-linux or set mode = \"pipe\" in the [terminal] section")
+            }
+            return;
+        }
+
+        let devices = match Caster::discover() {
+            Ok(d) => d,
+            Err(e) => {
+                self.fail(format!("Cast discovery failed: {}", e).as_str(), "check that multicast (mDNS, UDP 5353) is allowed on this host");
+                return;
+            }
+        };
+
+        if devices.is_empty()
+        {
+            self.warn("No cast device discovered on the network", "check the devices are powered and on the same LAN as this host");
+        }
+
+        for d in &devices
+        {
+            println!("[ -- ] Cast device {} : {} ({})", d.name, d.uuid, d.ip.as_deref().unwrap_or("?"));
+        }
+
+        if target_uuid == "Use Local Speaker"
+        {
+            return;
+        }
+
+        match devices.iter().find(|d| d.uuid.replace('-', "").eq_ignore_ascii_case(&target_uuid.replace('-', "")) || d.name == target_uuid) {
+            Some(_) => self.ok(format!("Chromecast {} is reachable", target_uuid).as_str()),
+            None => self.fail(format!("Chromecast {} was not discovered on the network", target_uuid).as_str(),
+                              "check the device is powered and on the same LAN, the visible ones are listed above")
+        }
+    }
+
+    pub(crate) fn check_config(self : &mut Self, path : &Option<PathBuf>)
+    {
+        let path = match path {
+            Some(p) => p,
+            None => {
+                println!("[ -- ] No configuration file given");
+                return;
+            }
+        };
+
+        let config = match Config::load(path) {
+            Ok(c) => c,
+            Err(e) => {
+                self.fail(format!("Cannot load {} : {}", path.to_string_lossy(), e).as_str(), "fix the TOML syntax or the offending key");
+                return;
+            }
+        };
+
+        self.ok(format!("Configuration {} parsed", path.to_string_lossy()).as_str());
+
+        for (lang, desc) in config.voices.iter().map(|(l, d)| (l.as_str(), d))
+                                  .chain(config.fallback_voice.iter().map(|d| ("fallback", d)))
+                                  .chain(config.engine.iter().map(|d| ("default", d)))
+        {
+            match VoiceSpec::parse(desc) {
+                Ok(spec) => {
+                    if let Err(e) = TTS::backend(&spec.engine, &Backends::new(&config))
+                    {
+                        self.fail(format!("Voice for {} ({}) uses an engine which cannot be used : {}", lang, desc, e).as_str(),
+                                  "install or configure the engine, or change the voice mapping");
+                    }
+                },
+                Err(e) => self.fail(format!("Invalid voice for {} : {}", lang, e).as_str(), "use the engine:voice syntax with a known engine")
+            }
+        }
+
+        if let Some(m) = &config.piper.model
+        {
+            let model = TTS::piper_model_path(&config.piper, m);
+
+            if model.is_file()
+            {
+                self.ok(format!("Piper model {} found", model.to_string_lossy()).as_str());
+            }
+            else
+            {
+                self.fail(format!("Piper model {} does not exist", model.to_string_lossy()).as_str(),
+                          "download the .onnx and .onnx.json files of the voice or fix [piper] model and voices");
+            }
+        }
+    }
+
+    /* Returns true when nothing is blocking */
+    /* Whether no blocking issue was found */
+    pub fn run(port : u32, target_uuid : &str, config : &Option<PathBuf>) -> bool
+    {
+        let mut doc = Doctor { failures : 0 };
+
+        doc.check_engines(config);
+        doc.check_audio();
+        doc.check_network(port);
+        doc.check_cast(target_uuid);
+        doc.check_config(config);
+
+        if doc.failures == 0
+        {
+            println!("No blocking issue found");
+        }
+        else
+        {
+            println!("{} blocking issue(s) found", doc.failures);
+        }
+
+        doc.failures == 0
+    }
+}
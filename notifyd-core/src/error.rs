@@ -0,0 +1,48 @@
+use std::error::Error;
+use std::fmt::{self};
+
+/****************
+ * DEFINE ERROR *
+ ****************/
+
+#[derive(Debug)]
+pub struct NotifydError(String);
+
+impl fmt::Display for NotifydError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for NotifydError {}
+
+impl NotifydError {
+    pub(crate) fn new(desc : &str) -> Box<dyn Error>
+    {
+        Box::new(NotifydError(String::from(desc)))
+    }
+}
+
+/* Raised when the audio backend never reports the end of a playback */
+#[derive(Debug)]
+pub struct PlaybackStalled;
+
+impl fmt::Display for PlaybackStalled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Playback did not complete, the audio backend looks wedged")
+    }
+}
+
+impl Error for PlaybackStalled {}
+
+/* Raised when playback is stopped, by /action/stop or to make way for an urgent notification */
+#[derive(Debug)]
+pub struct PlaybackInterrupted;
+
+impl fmt::Display for PlaybackInterrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Playback was interrupted")
+    }
+}
+
+impl Error for PlaybackInterrupted {}
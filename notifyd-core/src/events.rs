@@ -0,0 +1,230 @@
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use rouille::{ReadWrite, Request, Upgrade};
+use serde::{Serialize, Deserialize};
+use crate::notifyd::SynthesisInfo;
+use crate::queue::{JobInfo, Priority};
+
+/********************
+ * LIFECYCLE EVENTS *
+ ********************/
+
+/* Interval of the comments keeping idle event streams open through proxies */
+pub const SSE_KEEPALIVE : Duration = Duration::from_secs(15);
+
+/* Clients following the daemon at once through /ws and /events */
+pub const MAX_SUBSCRIBERS : usize = 32;
+
+/* Interval of the pings sent to quiet websocket clients, a client that left failing to take one */
+pub const WS_PING : Duration = Duration::from_secs(15);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind
+{
+    Received,
+    Synthesized,
+    Played,
+    Failed,
+    /* A watched target became reachable or unreachable */
+    Availability
+}
+
+/* Body of an event, as published to every channel */
+pub(crate) fn event(kind : EventKind, id : u64, text : &str, target : &str, priority : Priority) -> serde_json::Value
+{
+    serde_json::json!({
+        "event" : kind, "id" : id, "text" : text, "target" : target, "priority" : priority
+    })
+}
+
+pub(crate) fn synthesized_event(job : &JobInfo, info : &SynthesisInfo) -> serde_json::Value
+{
+    let mut body = event(EventKind::Synthesized, job.id, &job.text, &info.target, job.priority);
+    body["engine"] = serde_json::json!(info.engine);
+    body["voice"] = serde_json::json!(info.voice);
+    body["duration_ms"] = serde_json::json!(info.duration_ms);
+    body["synthesis_ms"] = serde_json::json!(info.synthesis_ms);
+    body["audio_url"] = serde_json::json!(info.audio_url);
+    body
+}
+
+pub(crate) fn failed_event(job : &JobInfo, err : &str) -> serde_json::Value
+{
+    let mut body = event(EventKind::Failed, job.id, &job.text, &job.target, job.priority);
+    body["error"] = serde_json::json!(err);
+    body
+}
+
+pub(crate) fn availability_event(target : &str, available : bool, err : Option<&str>) -> serde_json::Value
+{
+    let mut body = serde_json::json!({ "event" : EventKind::Availability, "target" : target, "available" : available });
+
+    if let Some(err) = err
+    {
+        body["error"] = serde_json::json!(err);
+    }
+
+    body
+}
+
+/* Events handed to the clients following the daemon live */
+pub struct EventHub
+{
+    pub(crate) subscribers : Mutex<Vec<Sender<serde_json::Value>>>
+}
+
+impl EventHub
+{
+    pub(crate) fn new() -> EventHub
+    {
+        EventHub {
+            subscribers : Mutex::new(Vec::new())
+        }
+    }
+
+    /* None once MAX_SUBSCRIBERS clients are there, those that left being found with an empty probe first */
+    pub(crate) fn subscribe(self : &Self) -> Option<Receiver<serde_json::Value>>
+    {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        if subscribers.len() >= MAX_SUBSCRIBERS
+        {
+            subscribers.retain(|s| s.send(serde_json::Value::Null).is_ok());
+        }
+
+        if subscribers.len() >= MAX_SUBSCRIBERS
+        {
+            return None;
+        }
+
+        let (tx, rx) = channel();
+        subscribers.push(tx);
+        Some(rx)
+    }
+
+    /* Clients that went away are dropped on the way */
+    pub(crate) fn publish(self : &Self, body : &serde_json::Value)
+    {
+        self.subscribers.lock().unwrap().retain(|s| s.send(body.clone()).is_ok());
+    }
+}
+
+/* What a subscriber asked for, from "?events=played,failed" and "?target=" */
+#[derive(Clone, Default)]
+pub struct EventFilter
+{
+    pub(crate) events : Option<Vec<String>>,
+    pub(crate) target : Option<String>
+}
+
+impl EventFilter
+{
+    pub(crate) fn from_request(request : &Request) -> EventFilter
+    {
+        EventFilter {
+            events : request.get_param("events").map(|e| e.split(',').map(|e| e.trim().to_string()).collect()),
+            target : request.get_param("target")
+        }
+    }
+
+    pub(crate) fn wants(self : &Self, body : &serde_json::Value) -> bool
+    {
+        /* Probes of the hub are not events */
+        let kind = match body["event"].as_str() {
+            Some(k) => k,
+            None => return false
+        };
+
+        self.events.as_ref().map(|e| e.iter().any(|e| e == kind)).unwrap_or(true)
+            && self.target.as_ref().map(|t| body["target"] == t.as_str()).unwrap_or(true)
+    }
+}
+
+/* Takes the connection once the response head is sent, so that the events leave one by one without holding a worker of the server */
+pub struct ConnectionUpgrade
+{
+    pub(crate) socket : Sender<Box<dyn ReadWrite + Send>>
+}
+
+impl Upgrade for ConnectionUpgrade
+{
+    fn build(&mut self, socket : Box<dyn ReadWrite + Send>)
+    {
+        let _ = self.socket.send(socket);
+    }
+}
+
+/* Server-Sent Events until the client goes away, which the next event or keepalive tells */
+pub(crate) fn relay_events(mut socket : Box<dyn ReadWrite + Send>, subscription : Receiver<serde_json::Value>, filter : EventFilter)
+{
+    /* Sent right away so that clients know they are connected */
+    let mut message = String::from(": connected\n\n");
+
+    loop
+    {
+        if socket.write_all(message.as_bytes()).and_then(|_| socket.flush()).is_err()
+        {
+            return;
+        }
+
+        message = loop
+        {
+            match subscription.recv_timeout(SSE_KEEPALIVE) {
+                Ok(body) if filter.wants(&body) => break format!("event: {}\ndata: {}\n\n", body["event"].as_str().unwrap_or_default(), body),
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break String::from(": keepalive\n\n"),
+                Err(RecvTimeoutError::Disconnected) => return
+            }
+        };
+    }
+}
+
+/**************
+ * WEBSOCKETS *
+ **************/
+
+const WS_TEXT : u8 = 0x1;
+const WS_PING_FRAME : u8 = 0x9;
+
+/* Unmasked single frame, as servers send them (RFC 6455 section 5.2) */
+pub(crate) fn websocket_frame(opcode : u8, payload : &[u8]) -> Vec<u8>
+{
+    let mut frame = vec![0x80 | opcode];
+
+    match payload.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend((n as u16).to_be_bytes());
+        },
+        n => {
+            frame.push(127);
+            frame.extend((n as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/* Push the events to the client until it goes away, which the next event or ping tells */
+pub(crate) fn relay_websocket(mut socket : Box<dyn ReadWrite + Send>, subscription : Receiver<serde_json::Value>, filter : EventFilter)
+{
+    loop
+    {
+        let frame = match subscription.recv_timeout(WS_PING) {
+            Ok(body) if filter.wants(&body) => websocket_frame(WS_TEXT, body.to_string().as_bytes()),
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => websocket_frame(WS_PING_FRAME, &[]),
+            Err(RecvTimeoutError::Disconnected) => return
+        };
+
+        if socket.write_all(&frame).and_then(|_| socket.flush()).is_err()
+        {
+            return;
+        }
+    }
+}
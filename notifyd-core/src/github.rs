@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::error::NotifydError;
+use crate::queue::Priority;
+
+/**********************
+ * GITHUB CI WEBHOOKS *
+ **********************/
+
+/* Largest payload GitHub delivers */
+pub const GITHUB_MAX_PAYLOAD : u64 = 25 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GitHubConfig
+{
+    /* Secret of the webhook, deliveries not signed with it are refused */
+    pub(crate) secret : String,
+    /* Branches whose results are announced, "*" for every branch */
+    pub(crate) branches : Vec<String>,
+    /* Announce passing runs as well as failing ones */
+    pub(crate) successes : bool,
+    /* Where results are spoken, the default target when absent */
+    pub(crate) target : Option<String>,
+    pub(crate) priority : Option<Priority>,
+    /* Topic whose defaults apply to the announcements */
+    pub(crate) topic : Option<String>,
+    /* Overrides by repository, e.g. "besnardjb/notifyd" or "notifyd" */
+    pub(crate) repos : HashMap<String, GitHubRepoConfig>
+}
+
+impl Default for GitHubConfig
+{
+    fn default() -> Self
+    {
+        GitHubConfig {
+            secret : String::new(),
+            branches : vec![String::from("main"), String::from("master")],
+            successes : false,
+            target : None,
+            priority : None,
+            topic : None,
+            repos : HashMap::new()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GitHubRepoConfig
+{
+    /* Spoken instead of the repository name */
+    pub(crate) name : Option<String>,
+    pub(crate) branches : Option<Vec<String>>,
+    pub(crate) successes : Option<bool>,
+    pub(crate) target : Option<String>,
+    /* Nothing is announced for the repository */
+    pub(crate) muted : bool
+}
+
+/* What to say about a delivery, and where */
+pub struct GitHubAnnouncement
+{
+    pub(crate) text : String,
+    pub(crate) target : Option<String>
+}
+
+impl GitHubConfig
+{
+    pub(crate) fn check(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        if self.secret.is_empty()
+        {
+            return Err(NotifydError::new("The GitHub webhook needs a 'secret'"));
+        }
+
+        Ok(())
+    }
+
+    /* The X-Hub-Signature-256 header must be the HMAC-SHA256 of the payload */
+    pub(crate) fn verify(self : &Self, payload : &[u8], signature : Option<&str>) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let hex = match signature.and_then(|s| s.strip_prefix("sha256=")) {
+            Some(h) => h,
+            None => return Err(NotifydError::new("Missing X-Hub-Signature-256 header"))
+        };
+
+        let tag = (0..hex.len()).step_by(2)
+                                .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                                .collect::<Option<Vec<u8>>>()
+                                .ok_or(NotifydError::new("Malformed X-Hub-Signature-256 header"))?;
+
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, self.secret.as_bytes());
+
+        match ring::hmac::verify(&key, payload, &tag) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(NotifydError::new("The signature does not match the webhook secret"))
+        }
+    }
+
+    /* Sentence for a completed workflow run or check suite, None when not worth announcing */
+    pub(crate) fn announcement(self : &Self, event : &str, payload : &serde_json::Value) -> Option<GitHubAnnouncement>
+    {
+        let run = match event {
+            "workflow_run" => &payload["workflow_run"],
+            "check_suite" => &payload["check_suite"],
+            _ => return None
+        };
+
+        if payload["action"] != "completed"
+        {
+            return None;
+        }
+
+        let full_name = payload["repository"]["full_name"].as_str().unwrap_or_default();
+        let short_name = payload["repository"]["name"].as_str().unwrap_or(full_name);
+        let branch = run["head_branch"].as_str().unwrap_or_default();
+
+        let repo = self.repos.get(full_name).or(self.repos.get(short_name)).cloned().unwrap_or_default();
+
+        if repo.muted
+        {
+            return None;
+        }
+
+        let branches = repo.branches.as_ref().unwrap_or(&self.branches);
+
+        if !branches.iter().any(|b| b == "*" || b == branch)
+        {
+            return None;
+        }
+
+        let outcome = match run["conclusion"].as_str().unwrap_or_default() {
+            "success" if repo.successes.unwrap_or(self.successes) => "passed",
+            "failure" => "failed",
+            "timed_out" => "timed out",
+            "startup_failure" => "failed to start",
+            /* Cancelled, skipped or neutral runs say nothing about the code */
+            _ => return None
+        };
+
+        let name = repo.name.clone().unwrap_or(short_name.to_string());
+
+        let text = match run["name"].as_str() {
+            Some(workflow) if event == "workflow_run" => format!("CI {} on {} for {}, in workflow {}", outcome, branch, name, workflow),
+            _ => format!("CI {} on {} for {}", outcome, branch, name)
+        };
+
+        Some(GitHubAnnouncement {
+            text : text,
+            target : repo.target.or(self.target.clone())
+        })
+    }
+}
@@ -0,0 +1,306 @@
+use std::{path::PathBuf, fs::remove_file};
+use std::path::Path;
+use std::fs::read_dir;
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use std::collections::HashMap;
+use crate::config::TopicConfig;
+use crate::queue::Priority;
+use crate::store::Store;
+use crate::tts::SpeechParams;
+
+/***********
+ * HISTORY *
+ ***********/
+
+/* Entries returned by /history when no limit is given */
+pub const HISTORY_LIMIT : usize = 500;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HistoryConfig
+{
+    /* Days notifications are kept in the database, 0 keeping them forever */
+    pub(crate) retention_days : u32
+}
+
+impl Default for HistoryConfig
+{
+    fn default() -> Self
+    {
+        HistoryConfig {
+            retention_days : 30
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct HistoryEntry
+{
+    pub(crate) time : String,
+    pub(crate) id : Option<u64>,
+    pub(crate) text : String,
+    pub(crate) topic : Option<String>,
+    pub(crate) target : String,
+    pub(crate) priority : Priority,
+    /* "done", "failed", "digested" or "digest" */
+    pub(crate) status : String,
+    /* Notifications summarized by a digest */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) items : Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) err : Option<String>,
+    /* From the submission to the end of the playback */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) latency_ms : Option<u64>,
+    /* Where the archived audio can be downloaded */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) audio_url : Option<String>,
+    #[serde(skip)]
+    pub(crate) audio : Option<PathBuf>,
+    /* As the job was queued, for /action/repeat */
+    #[serde(skip)]
+    pub(crate) params : SpeechParams
+}
+
+impl HistoryEntry
+{
+    pub(crate) fn new(id : Option<u64>, text : &str, params : &SpeechParams, target : &str, status : &str) -> HistoryEntry
+    {
+        HistoryEntry {
+            time : chrono::Local::now().to_rfc3339(),
+            id : id,
+            text : text.to_string(),
+            topic : params.topic.clone(),
+            target : target.to_string(),
+            priority : params.priority.unwrap_or(Priority::Normal),
+            status : status.to_string(),
+            items : Vec::new(),
+            err : None,
+            latency_ms : None,
+            audio_url : None,
+            audio : None,
+            params : params.clone()
+        }
+    }
+
+    pub(crate) fn from_row(row : &rusqlite::Row) -> rusqlite::Result<HistoryEntry>
+    {
+        let priority : String = row.get(5)?;
+        let items : String = row.get(7)?;
+        let audio : Option<String> = row.get(11)?;
+        let params : Option<String> = row.get(12)?;
+
+        Ok(HistoryEntry {
+            time : row.get(0)?,
+            id : row.get(1)?,
+            text : row.get(2)?,
+            topic : row.get(3)?,
+            target : row.get(4)?,
+            priority : serde_json::from_value(serde_json::Value::String(priority)).unwrap_or(Priority::Normal),
+            status : row.get(6)?,
+            items : serde_json::from_str(&items).unwrap_or_default(),
+            err : row.get(8)?,
+            latency_ms : row.get(9)?,
+            audio_url : row.get(10)?,
+            audio : audio.map(PathBuf::from),
+            params : params.and_then(|p| serde_json::from_str(&p).ok()).unwrap_or_default()
+        })
+    }
+}
+
+pub const HISTORY_COLUMNS : &str = "time, job, text, topic, target, priority, status, items, err, latency_ms, audio_url, audio, params";
+
+/* Instances sharing a database each see their own history, None being the main one */
+impl Store
+{
+    pub(crate) fn record_history(self : &Self, instance : &Option<String>, entry : &HistoryEntry) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let timestamp = chrono::DateTime::parse_from_rfc3339(&entry.time)?.timestamp();
+        let priority = serde_json::to_value(entry.priority)?.as_str().unwrap_or("normal").to_string();
+
+        self.conn.lock().unwrap().execute(format!("INSERT INTO history ({}, timestamp, instance) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)", HISTORY_COLUMNS).as_str(),
+                                          rusqlite::params![entry.time, entry.id, entry.text, entry.topic, entry.target, priority, entry.status,
+                                                            serde_json::to_string(&entry.items)?, entry.err, entry.latency_ms, entry.audio_url,
+                                                            entry.audio.as_ref().map(|a| a.to_string_lossy().to_string()),
+                                                            serde_json::to_string(&entry.params)?, timestamp, instance])?;
+        Ok(())
+    }
+
+    /* The latest entries recorded from since (unix time) on, oldest first */
+    pub(crate) fn history(self : &Self, instance : &Option<String>, limit : usize, since : Option<i64>) -> Result<Vec<HistoryEntry>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(format!("SELECT {} FROM history WHERE instance IS ?1 AND timestamp >= ?2 ORDER BY id DESC LIMIT ?3", HISTORY_COLUMNS).as_str())?;
+        let rows = stmt.query_map(rusqlite::params![instance, since.unwrap_or(i64::MIN), limit as i64], HistoryEntry::from_row)?;
+
+        let mut ret = Vec::new();
+        for e in rows
+        {
+            ret.push(e?);
+        }
+
+        ret.reverse();
+        Ok(ret)
+    }
+
+    /* Highest job id this instance recorded or journaled, job ids going on from it after a restart */
+    pub(crate) fn last_job_id(self : &Self, instance : &Option<String>) -> Result<u64, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let last : Option<i64> = conn.query_row("SELECT MAX(job) FROM (SELECT job FROM history WHERE instance IS ?1 UNION ALL SELECT job FROM queue WHERE instance IS ?1)",
+                                                [instance], |row| row.get(0))?;
+        Ok(last.unwrap_or(0).max(0) as u64)
+    }
+
+    /* Databases written before job ids went on across restarts may hold several entries for an id, the latest one wins */
+    pub(crate) fn history_entry(self : &Self, instance : &Option<String>, id : u64) -> Result<Option<HistoryEntry>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(format!("SELECT {} FROM history WHERE instance IS ?1 AND job = ?2 ORDER BY id DESC LIMIT 1", HISTORY_COLUMNS).as_str())?;
+        let mut rows = stmt.query_map(rusqlite::params![instance, id as i64], HistoryEntry::from_row)?;
+
+        match rows.next() {
+            Some(e) => Ok(Some(e?)),
+            None => Ok(None)
+        }
+    }
+
+    /* Latest notification played on the target */
+    pub(crate) fn last_played(self : &Self, instance : &Option<String>, target : &str) -> Result<Option<HistoryEntry>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(format!("SELECT {} FROM history WHERE instance IS ?1 AND target = ?2 AND status = 'done' AND job IS NOT NULL ORDER BY id DESC LIMIT 1", HISTORY_COLUMNS).as_str())?;
+        let mut rows = stmt.query_map(rusqlite::params![instance, target], HistoryEntry::from_row)?;
+
+        match rows.next() {
+            Some(e) => Ok(Some(e?)),
+            None => Ok(None)
+        }
+    }
+
+    /* Archived audio of a notification still in the history */
+    pub(crate) fn history_audio(self : &Self, instance : &Option<String>, id : u64) -> Option<PathBuf>
+    {
+        self.history_entry(instance, id).ok().flatten().and_then(|e| e.audio)
+    }
+
+    /* Only the entries of the instance, the others having their own retention */
+    pub(crate) fn prune_history(self : &Self, instance : &Option<String>, days : u32) -> Result<usize, Box<dyn std::error::Error>>
+    {
+        let cutoff = chrono::Local::now().timestamp() - days as i64 * 86400;
+        Ok(self.conn.lock().unwrap().execute("DELETE FROM history WHERE instance IS ?1 AND timestamp < ?2", rusqlite::params![instance, cutoff])?)
+    }
+}
+
+/*****************
+ * AUDIO ARCHIVE *
+ *****************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ArchiveConfig
+{
+    /* Where the audio of announcements is kept, disabled when absent */
+    pub(crate) dir : Option<PathBuf>,
+    /* Days the audio is kept, topics overriding it with archive_days */
+    pub(crate) retention_days : u32
+}
+
+impl Default for ArchiveConfig
+{
+    fn default() -> Self
+    {
+        ArchiveConfig {
+            dir : None,
+            retention_days : 7
+        }
+    }
+}
+
+pub struct AudioArchive
+{
+    pub(crate) dir : PathBuf,
+    pub(crate) retention_days : u32,
+    pub(crate) topic_days : HashMap<String, u32>
+}
+
+impl AudioArchive
+{
+    pub(crate) fn new(config : &ArchiveConfig, topics : &HashMap<String, TopicConfig>) -> Result<Option<AudioArchive>, Box<dyn std::error::Error>>
+    {
+        let dir = match &config.dir {
+            Some(d) => d.clone(),
+            None => return Ok(None)
+        };
+
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Some(AudioArchive {
+            dir : dir,
+            retention_days : config.retention_days,
+            topic_days : topics.iter()
+                               .filter_map(|(name, t)| t.archive_days.map(|d| (name.clone(), d)))
+                               .collect()
+        }))
+    }
+
+    pub(crate) fn retention(self : &Self, topic : &Option<String>) -> u32
+    {
+        topic.as_ref()
+             .and_then(|t| self.topic_days.get(t).copied())
+             .unwrap_or(self.retention_days)
+    }
+
+    /* Each topic has its own directory, pruned with its own retention */
+    pub(crate) fn topic_dir(self : &Self, topic : &Option<String>) -> PathBuf
+    {
+        match topic {
+            Some(t) => self.dir.join(t.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect::<String>()),
+            None => self.dir.join("_default")
+        }
+    }
+
+    /* Copy the audio of a job, None when its topic keeps nothing */
+    pub(crate) fn store(self : &Self, audio : &Path, id : u64, topic : &Option<String>) -> Result<Option<PathBuf>, Box<dyn std::error::Error>>
+    {
+        let days = self.retention(topic);
+
+        if days == 0
+        {
+            return Ok(None);
+        }
+
+        let dir = self.topic_dir(topic);
+        std::fs::create_dir_all(&dir)?;
+
+        let ext = audio.extension().and_then(|e| e.to_str()).unwrap_or("wav");
+        let dest = dir.join(format!("{}-{}.{}", chrono::Local::now().format("%Y%m%d-%H%M%S"), id, ext));
+        std::fs::copy(audio, &dest)?;
+
+        AudioArchive::prune(&dir, days);
+
+        Ok(Some(dest))
+    }
+
+    pub(crate) fn prune(dir : &Path, days : u32)
+    {
+        let max_age = Duration::from_secs(days as u64 * 86400);
+
+        if let Ok(entries) = read_dir(dir)
+        {
+            for e in entries.flatten()
+            {
+                let expired = e.metadata()
+                               .and_then(|m| m.modified())
+                               .map(|t| t.elapsed().unwrap_or_default() > max_age)
+                               .unwrap_or(false);
+
+                if expired
+                {
+                    let _ = remove_file(e.path());
+                }
+            }
+        }
+    }
+}
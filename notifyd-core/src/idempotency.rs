@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+/********************
+ * IDEMPOTENCY KEYS *
+ ********************/
+
+/* Longest key accepted, keys being opaque strings such as UUIDs */
+pub const MAX_IDEMPOTENCY_KEY_LEN : usize = 255;
+/* Keys remembered at most, the oldest ones being forgotten first */
+pub const MAX_IDEMPOTENCY_KEYS : usize = 10000;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct IdempotencyConfig
+{
+    /* Seconds during which a retry with the same key gets the first answer back */
+    pub(crate) ttl : u64
+}
+
+impl Default for IdempotencyConfig
+{
+    fn default() -> Self
+    {
+        IdempotencyConfig {
+            ttl : 86400
+        }
+    }
+}
+
+/* Answer given to the first request with a key, None while it is being processed */
+pub struct KeyedRequest
+{
+    pub(crate) received : Instant,
+    pub(crate) answer : Option<(u16, serde_json::Value)>
+}
+
+pub enum KeyState
+{
+    /* First time the key is seen, it is now reserved */
+    New,
+    /* A request with the key is still being processed */
+    InProgress,
+    Answered(u16, serde_json::Value)
+}
+
+pub struct IdempotencyKeys
+{
+    pub(crate) keys : Mutex<HashMap<String, KeyedRequest>>
+}
+
+impl IdempotencyKeys
+{
+    pub(crate) fn new() -> IdempotencyKeys
+    {
+        IdempotencyKeys {
+            keys : Mutex::new(HashMap::new())
+        }
+    }
+
+    pub(crate) fn begin(self : &Self, key : &str, ttl : Duration) -> KeyState
+    {
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, k| k.received.elapsed() < ttl);
+
+        if let Some(k) = keys.get(key)
+        {
+            return match &k.answer {
+                Some((status, body)) => KeyState::Answered(*status, body.clone()),
+                None => KeyState::InProgress
+            };
+        }
+
+        if keys.len() >= MAX_IDEMPOTENCY_KEYS
+        {
+            if let Some(oldest) = keys.iter().min_by_key(|(_, k)| k.received).map(|(key, _)| key.clone())
+            {
+                keys.remove(&oldest);
+            }
+        }
+
+        keys.insert(key.to_string(), KeyedRequest {
+            received : Instant::now(),
+            answer : None
+        });
+
+        KeyState::New
+    }
+
+    /* Remember the answer for the retries */
+    pub(crate) fn answer(self : &Self, key : &str, status : u16, body : serde_json::Value)
+    {
+        if let Some(k) = self.keys.lock().unwrap().get_mut(key)
+        {
+            k.answer = Some((status, body));
+        }
+    }
+
+    /* The request failed, a retry is processed as a new one */
+    pub(crate) fn release(self : &Self, key : &str)
+    {
+        self.keys.lock().unwrap().remove(key);
+    }
+}
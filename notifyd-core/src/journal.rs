@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::queue::{JobInfo, Priority};
+use crate::store::Store;
+use crate::timers::{ScheduledEntry, TimerKind};
+use crate::tts::SpeechParams;
+
+/*****************
+ * QUEUE JOURNAL *
+ *****************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PersistenceConfig
+{
+    /* Keep pending and scheduled notifications in the database, to play them after a restart or a crash */
+    pub(crate) enabled : bool,
+    /* Seconds after which a notification left by an earlier run is too old to be played */
+    pub(crate) max_age : u64
+}
+
+impl Default for PersistenceConfig
+{
+    fn default() -> Self
+    {
+        PersistenceConfig {
+            enabled : true,
+            max_age : 900
+        }
+    }
+}
+
+/* Copy of the queue and of the scheduled notifications of a daemon, written through on every change */
+#[derive(Clone)]
+pub struct Journal
+{
+    pub(crate) store : Arc<Store>,
+    pub(crate) instance : Option<String>
+}
+
+/* Failing to journal is not worth refusing or losing a notification over */
+impl Journal
+{
+    pub(crate) fn save_job(self : &Self, job : &JobInfo)
+    {
+        if let Err(e) = self.store.journal_job(&self.instance, job)
+        {
+            warn!("Failed to journal job {} : {}", job.id, e);
+        }
+    }
+
+    pub(crate) fn forget_job(self : &Self, id : u64)
+    {
+        if let Err(e) = self.store.forget_job(&self.instance, id)
+        {
+            warn!("Failed to remove job {} from the journal : {}", id, e);
+        }
+    }
+
+    pub(crate) fn save_entry(self : &Self, entry : &ScheduledEntry)
+    {
+        if let Err(e) = self.store.journal_entry(&self.instance, entry)
+        {
+            warn!("Failed to journal scheduled entry {} : {}", entry.id, e);
+        }
+    }
+
+    pub(crate) fn forget_entry(self : &Self, id : u64)
+    {
+        if let Err(e) = self.store.forget_entry(&self.instance, id)
+        {
+            warn!("Failed to remove scheduled entry {} from the journal : {}", id, e);
+        }
+    }
+}
+
+/* Instances sharing a database each journal their own queue, None being the main one */
+impl Store
+{
+    pub(crate) fn journal_job(self : &Self, instance : &Option<String>, job : &JobInfo) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let priority = serde_json::to_value(job.priority)?.as_str().unwrap_or("normal").to_string();
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM queue WHERE instance IS ?1 AND job = ?2", rusqlite::params![instance, job.id as i64])?;
+        conn.execute("INSERT INTO queue (instance, job, text, target, priority, params, audio, queued) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                     rusqlite::params![instance, job.id as i64, job.text, job.target, priority, serde_json::to_string(&job.params)?,
+                                       job.params.audio.as_ref().map(|a| a.to_string_lossy().to_string()), job.queued as i64])?;
+        Ok(())
+    }
+
+    pub(crate) fn forget_job(self : &Self, instance : &Option<String>, id : u64) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.conn.lock().unwrap().execute("DELETE FROM queue WHERE instance IS ?1 AND job = ?2", rusqlite::params![instance, id as i64])?;
+        Ok(())
+    }
+
+    /* Jobs left by the previous run in their order of submission, removed from the journal */
+    pub(crate) fn take_journaled_jobs(self : &Self, instance : &Option<String>) -> Result<Vec<JobInfo>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT job, text, target, priority, params, audio, queued FROM queue WHERE instance IS ?1 ORDER BY queued, job")?;
+        let rows = stmt.query_map([instance], |row| {
+            let priority : String = row.get(3)?;
+            let params : String = row.get(4)?;
+            let audio : Option<String> = row.get(5)?;
+            let queued : i64 = row.get(6)?;
+
+            Ok(JobInfo {
+                id : row.get::<_, i64>(0)? as u64,
+                text : row.get(1)?,
+                target : row.get(2)?,
+                priority : serde_json::from_value(serde_json::Value::String(priority)).unwrap_or(Priority::Normal),
+                params : SpeechParams {
+                    audio : audio.map(PathBuf::from),
+                    ..serde_json::from_str(&params).unwrap_or_default()
+                },
+                queued : queued as u128,
+                started : None
+            })
+        })?;
+
+        let mut ret = Vec::new();
+        for j in rows
+        {
+            ret.push(j?);
+        }
+
+        conn.execute("DELETE FROM queue WHERE instance IS ?1", [instance])?;
+        Ok(ret)
+    }
+
+    pub(crate) fn journal_entry(self : &Self, instance : &Option<String>, entry : &ScheduledEntry) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let kind = serde_json::to_value(entry.kind)?.as_str().unwrap_or("notification").to_string();
+        let params = match &entry.params {
+            Some(p) => Some(serde_json::to_string(p)?),
+            None => None
+        };
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM timers WHERE instance IS ?1 AND entry = ?2", rusqlite::params![instance, entry.id as i64])?;
+        conn.execute("INSERT INTO timers (instance, entry, kind, label, due, target, sound, replace_key, params, audio, source) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                     rusqlite::params![instance, entry.id as i64, kind, entry.label, entry.due, entry.target, entry.sound, entry.replace_key, params,
+                                       entry.params.as_ref().and_then(|p| p.audio.as_ref()).map(|a| a.to_string_lossy().to_string()),
+                                       entry.params.as_ref().and_then(|p| p.source.clone())])?;
+        Ok(())
+    }
+
+    pub(crate) fn forget_entry(self : &Self, instance : &Option<String>, id : u64) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.conn.lock().unwrap().execute("DELETE FROM timers WHERE instance IS ?1 AND entry = ?2", rusqlite::params![instance, id as i64])?;
+        Ok(())
+    }
+
+    /* Timers, alarms and notifications sent later that the previous run left, removed from the journal.
+       Entries whose due time cannot be read anymore are dropped */
+    pub(crate) fn take_journaled_entries(self : &Self, instance : &Option<String>) -> Result<Vec<ScheduledEntry>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT entry, kind, label, due, target, sound, replace_key, params, audio, source FROM timers WHERE instance IS ?1 ORDER BY due")?;
+        let rows = stmt.query_map([instance], |row| {
+            let kind : String = row.get(1)?;
+            let due : String = row.get(3)?;
+            let params : Option<String> = row.get(7)?;
+            let audio : Option<String> = row.get(8)?;
+            let source : Option<String> = row.get(9)?;
+
+            Ok((due, ScheduledEntry {
+                id : row.get::<_, i64>(0)? as u64,
+                kind : serde_json::from_value(serde_json::Value::String(kind)).unwrap_or(TimerKind::Notification),
+                label : row.get(2)?,
+                due : String::new(),
+                target : row.get(4)?,
+                sound : row.get(5)?,
+                replace_key : row.get(6)?,
+                due_at : chrono::Local::now(),
+                params : params.map(|p| SpeechParams {
+                    audio : audio.map(PathBuf::from),
+                    source : source,
+                    ..serde_json::from_str(&p).unwrap_or_default()
+                })
+            }))
+        })?;
+
+        let mut ret = Vec::new();
+        for r in rows
+        {
+            let (due, entry) = r?;
+
+            match chrono::DateTime::parse_from_rfc3339(&due) {
+                Ok(d) => ret.push(ScheduledEntry {
+                    due : due,
+                    due_at : d.with_timezone(&chrono::Local),
+                    ..entry
+                }),
+                Err(e) => warn!("Dropping scheduled entry {} due at '{}' : {}", entry.id, due, e)
+            }
+        }
+
+        conn.execute("DELETE FROM timers WHERE instance IS ?1", [instance])?;
+        Ok(ret)
+    }
+
+    /* Audio files the journal still refers to */
+    pub(crate) fn journaled_audio(self : &Self, instance : &Option<String>) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT audio FROM queue WHERE instance IS ?1 AND audio IS NOT NULL
+                                     UNION SELECT audio FROM timers WHERE instance IS ?1 AND audio IS NOT NULL")?;
+        let rows = stmt.query_map([instance], |row| row.get::<_, String>(0))?;
+
+        let mut ret = Vec::new();
+        for a in rows
+        {
+            ret.push(PathBuf::from(a?));
+        }
+
+        Ok(ret)
+    }
+}
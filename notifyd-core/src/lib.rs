@@ -0,0 +1,57 @@
+use std::time::SystemTime;
+
+pub mod error;
+pub mod audio;
+pub mod tts;
+pub mod cloud;
+pub mod queue;
+pub mod store;
+pub mod auth;
+pub mod cast;
+pub mod caster;
+pub mod sonos;
+pub mod airplay;
+pub mod snapcast;
+pub mod sinks;
+pub mod access_log;
+pub mod metrics;
+pub mod dbus;
+pub mod desktop;
+pub mod events;
+pub mod mqtt;
+pub mod github;
+pub mod webhooks;
+pub mod callbacks;
+pub mod limits;
+pub mod announcements;
+pub mod timers;
+pub mod schedules;
+pub mod journal;
+pub mod sounds;
+pub mod history;
+pub mod digest;
+pub mod quiet;
+pub mod dedup;
+pub mod rules;
+pub mod templates;
+pub mod idempotency;
+pub mod wake;
+pub mod config;
+pub mod reload;
+pub mod shutdown;
+pub mod systemd;
+pub mod openapi;
+pub mod notifyd;
+pub mod doctor;
+pub mod client;
+
+/*******************
+ * HELPER FOR TIME *
+ *******************/
+
+pub fn now_in_usecs() -> u128 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.as_micros(),
+        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::{Arc, Mutex, Condvar};
+use serde::{Serialize, Deserialize};
+
+/*******************
+ * RESOURCE LIMITS *
+ *******************/
+
+/* What becomes of a notification arriving while the queue holds max_queue_depth ones */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOverflow
+{
+    /* Refused with a 429 */
+    #[default]
+    Reject,
+    /* Queued in place of the newest pending notification of a lower priority, refused when there is none */
+    DropLowest
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct LimitsConfig
+{
+    pub(crate) max_concurrent_syntheses : Option<usize>,
+    pub(crate) max_tempdir_bytes : Option<u64>,
+    pub(crate) max_queue_bytes : Option<usize>,
+    /* Pending notifications, the one being played aside */
+    pub(crate) max_queue_depth : Option<usize>,
+    pub(crate) overflow : QueueOverflow,
+    /* Decoded size of audio attachments, 8 MiB by default */
+    pub(crate) max_attachment_bytes : Option<usize>,
+    /* Size of the audio fetched by /action/play_url, 20 MiB by default */
+    pub(crate) max_url_bytes : Option<usize>
+}
+
+/* Counts syntheses in flight against the configured ceiling */
+pub struct SynthesisSlots
+{
+    pub(crate) max : Option<usize>,
+    pub(crate) busy : Mutex<usize>,
+    pub(crate) cond : Condvar
+}
+
+pub struct SlotGuard
+{
+    pub(crate) slots : Arc<SynthesisSlots>
+}
+
+impl Drop for SlotGuard
+{
+    fn drop(&mut self)
+    {
+        let mut busy = self.slots.busy.lock().unwrap();
+        *busy -= 1;
+        self.slots.cond.notify_one();
+    }
+}
+
+impl SynthesisSlots
+{
+    pub(crate) fn new(max : Option<usize>) -> SynthesisSlots
+    {
+        SynthesisSlots {
+            max : max,
+            busy : Mutex::new(0),
+            cond : Condvar::new()
+        }
+    }
+
+    pub(crate) fn is_full(self : &Self, busy : usize) -> bool
+    {
+        match self.max {
+            Some(m) => busy >= m,
+            None => false
+        }
+    }
+
+    pub(crate) fn try_acquire(self : &Arc<Self>) -> Option<SlotGuard>
+    {
+        let mut busy = self.busy.lock().unwrap();
+
+        if self.is_full(*busy)
+        {
+            return None;
+        }
+
+        *busy += 1;
+        Some(SlotGuard { slots : self.clone() })
+    }
+
+    pub(crate) fn acquire(self : &Arc<Self>) -> SlotGuard
+    {
+        let mut busy = self.busy.lock().unwrap();
+
+        while self.is_full(*busy)
+        {
+            busy = self.cond.wait(busy).unwrap();
+        }
+
+        *busy += 1;
+        SlotGuard { slots : self.clone() }
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/***********
+ * METRICS *
+ ***********/
+
+/* Upper bounds (seconds) of the histogram buckets, from a cached sentence to a long announcement */
+pub const DURATION_BUCKETS : &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/* Label combinations kept per metric, the rest being counted as "other" */
+pub const MAX_SERIES : usize = 200;
+
+pub(crate) fn escape_label(value : &str) -> String
+{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub(crate) fn format_labels(names : &[&str], values : &[String], extra : Option<(&str, String)>) -> String
+{
+    let mut pairs : Vec<String> = names.iter().zip(values)
+                                       .map(|(n, v)| format!("{}=\"{}\"", n, escape_label(v)))
+                                       .collect();
+
+    if let Some((n, v)) = extra
+    {
+        pairs.push(format!("{}=\"{}\"", n, v));
+    }
+
+    if pairs.is_empty() { String::new() } else { format!("{{{}}}", pairs.join(",")) }
+}
+
+/* Key of a new series, folded into "other" once the metric has too many */
+pub(crate) fn series_key<T>(series : &BTreeMap<Vec<String>, T>, values : &[&str]) -> Vec<String>
+{
+    let key : Vec<String> = values.iter().map(|v| v.to_string()).collect();
+
+    if series.len() >= MAX_SERIES && !series.contains_key(&key)
+    {
+        return values.iter().map(|_| String::from("other")).collect();
+    }
+
+    key
+}
+
+/* Routes of Notifyd::route_request as labelled, anything else being "other" so that random paths add no series */
+pub const ROUTES : &[&str] = &[
+    "/", "/index.html", "/openapi.json", "/static", "/readyz", "/metrics", "/ws", "/events",
+    "/action/speak", "/action/synthesize", "/synthesize", "/action/cast", "/action/timer", "/action/alarm",
+    "/action/time", "/action/weather", "/action/repeat", "/action/play", "/action/play_url",
+    "/action/stop", "/action/pause", "/action/resume",
+    "/notify", "/notify/homeassistant", "/notify/t/:id", "/notify/template/:id", "/notify/preset/:id",
+    "/message", "/integrations/github", "/webhooks/:id",
+    "/queue", "/status", "/volume", "/voices", "/jobs/:id", "/jobs/:id/bump",
+    "/timers", "/timers/:id/cancel", "/schedules", "/schedules/:id", "/history", "/history/:id/audio",
+    "/devices", "/devices/:id", "/sounds", "/sounds/:id",
+    "/admin/selftest", "/admin/engine", "/admin/dnd", "/admin/flush", "/admin/config", "/admin/reload",
+    "/admin/tokens", "/admin/tokens/:id"
+];
+
+/* Segments followed by a name rather than a number */
+pub const NAMED_SEGMENTS : &[&str] = &["devices", "sounds", "webhooks", "t", "template", "preset", "tokens"];
+
+/* Route of a request without its ids, e.g. "/jobs/:id" for "/jobs/12", the path of an instance being removed first */
+pub fn endpoint_label(url : &str) -> String
+{
+    if url.starts_with("/static/")
+    {
+        return String::from("/static");
+    }
+
+    let segments : Vec<&str> = url.split('/').filter(|s| !s.is_empty()).collect();
+
+    let route : Vec<&str> = segments.iter().enumerate().map(|(i, s)| {
+        let named = i > 0 && NAMED_SEGMENTS.contains(&segments[i - 1]);
+        if i > 0 && (named || s.chars().any(|c| c.is_ascii_digit())) { ":id" } else { s }
+    }).collect();
+
+    let label = format!("/{}", route.join("/"));
+
+    if ROUTES.contains(&label.as_str()) { label } else { String::from("other") }
+}
+
+pub struct Counter
+{
+    pub(crate) labels : &'static [&'static str],
+    pub(crate) series : Mutex<BTreeMap<Vec<String>, u64>>
+}
+
+impl Counter
+{
+    pub(crate) fn new(labels : &'static [&'static str]) -> Counter
+    {
+        Counter {
+            labels : labels,
+            series : Mutex::new(BTreeMap::new())
+        }
+    }
+
+    pub(crate) fn inc(self : &Self, values : &[&str])
+    {
+        let mut series = self.series.lock().unwrap();
+        let key = series_key(&series, values);
+        *series.entry(key).or_insert(0) += 1;
+    }
+
+    pub(crate) fn render(self : &Self, out : &mut String, name : &str, help : &str)
+    {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+
+        for (values, n) in self.series.lock().unwrap().iter()
+        {
+            let _ = writeln!(out, "{}{} {}", name, format_labels(self.labels, values, None), n);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Series
+{
+    /* Observations up to each bucket bound, cumulative as exposed */
+    pub(crate) buckets : Vec<u64>,
+    pub(crate) count : u64,
+    pub(crate) sum : f64
+}
+
+pub struct Histogram
+{
+    pub(crate) labels : &'static [&'static str],
+    pub(crate) series : Mutex<BTreeMap<Vec<String>, Series>>
+}
+
+impl Histogram
+{
+    pub(crate) fn new(labels : &'static [&'static str]) -> Histogram
+    {
+        Histogram {
+            labels : labels,
+            series : Mutex::new(BTreeMap::new())
+        }
+    }
+
+    pub(crate) fn observe(self : &Self, values : &[&str], duration : Duration)
+    {
+        let secs = duration.as_secs_f64();
+        let mut series = self.series.lock().unwrap();
+        let key = series_key(&series, values);
+
+        let s = series.entry(key).or_insert(Series {
+            buckets : vec![0; DURATION_BUCKETS.len()],
+            count : 0,
+            sum : 0.0
+        });
+
+        for (b, bound) in s.buckets.iter_mut().zip(DURATION_BUCKETS)
+        {
+            if secs <= *bound
+            {
+                *b += 1;
+            }
+        }
+
+        s.count += 1;
+        s.sum += secs;
+    }
+
+    pub(crate) fn render(self : &Self, out : &mut String, name : &str, help : &str)
+    {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+
+        for (values, s) in self.series.lock().unwrap().iter()
+        {
+            for (n, bound) in s.buckets.iter().zip(DURATION_BUCKETS)
+            {
+                let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(self.labels, values, Some(("le", bound.to_string()))), n);
+            }
+
+            let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(self.labels, values, Some(("le", String::from("+Inf")))), s.count);
+            let _ = writeln!(out, "{}_sum{} {}", name, format_labels(self.labels, values, None), s.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, format_labels(self.labels, values, None), s.count);
+        }
+    }
+}
+
+/* What /metrics exposes in the Prometheus text format, the queue gauges being read when scraped */
+pub struct Metrics
+{
+    pub(crate) received : Counter,
+    pub(crate) delivered : Counter,
+    pub(crate) cast_failures : Counter,
+    pub(crate) cast_retries : Counter,
+    pub(crate) synthesis : Histogram,
+    pub(crate) playback : Histogram,
+    pub(crate) requests : Histogram
+}
+
+impl Metrics
+{
+    pub(crate) fn new() -> Metrics
+    {
+        Metrics {
+            received : Counter::new(&["priority"]),
+            delivered : Counter::new(&["status"]),
+            cast_failures : Counter::new(&["device"]),
+            cast_retries : Counter::new(&["device"]),
+            synthesis : Histogram::new(&["engine", "cached"]),
+            playback : Histogram::new(&["target"]),
+            requests : Histogram::new(&["method", "endpoint"])
+        }
+    }
+
+    pub(crate) fn render(self : &Self, pending : usize, running : bool, pending_bytes : usize, targets : &[(String, bool)]) -> String
+    {
+        let mut out = String::new();
+
+        self.received.render(&mut out, "notifyd_notifications_received_total", "Notifications submitted, by priority");
+        self.delivered.render(&mut out, "notifyd_notifications_delivered_total", "Notifications the playback worker is done with, by outcome");
+        self.cast_failures.render(&mut out, "notifyd_cast_failures_total", "Notifications that could not be cast, by device");
+        self.cast_retries.render(&mut out, "notifyd_cast_retries_total", "Attempts to reach a device or load the media made again after a failure, by device");
+        self.synthesis.render(&mut out, "notifyd_synthesis_duration_seconds", "Time to produce the audio of a notification");
+        self.playback.render(&mut out, "notifyd_playback_duration_seconds", "Time spent playing or casting a notification once synthesized");
+        self.requests.render(&mut out, "notifyd_http_request_duration_seconds", "Time to answer HTTP requests, by endpoint");
+
+        let _ = writeln!(out, "# HELP notifyd_queue_pending Notifications waiting to be played\n# TYPE notifyd_queue_pending gauge\nnotifyd_queue_pending {}", pending);
+        let _ = writeln!(out, "# HELP notifyd_queue_running Whether a notification is being played\n# TYPE notifyd_queue_running gauge\nnotifyd_queue_running {}", running as u8);
+        let _ = writeln!(out, "# HELP notifyd_queue_pending_bytes Approximate memory held by the pending notifications\n# TYPE notifyd_queue_pending_bytes gauge\nnotifyd_queue_pending_bytes {}", pending_bytes);
+
+        let _ = writeln!(out, "# HELP notifyd_target_up Whether the watchdog found the target reachable on its last check\n# TYPE notifyd_target_up gauge");
+        for (target, up) in targets
+        {
+            let _ = writeln!(out, "notifyd_target_up{} {}", format_labels(&["target"], std::slice::from_ref(target), None), *up as u8);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use serde::{Serialize, Deserialize};
+use tempdir::TempDir;
+use tracing::warn;
+use crate::error::NotifydError;
+use crate::events::EventKind;
+
+/********
+ * MQTT *
+ ********/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MqttConfig
+{
+    /* "mqtt://host:port", or "mqtts://" over TLS */
+    pub(crate) broker : String,
+    /* Topic, wildcards allowed, whose messages are notified, "" to only publish events */
+    pub(crate) topic : String,
+    pub(crate) client_id : Option<String>,
+    pub(crate) username : Option<String>,
+    pub(crate) password : Option<String>,
+    pub(crate) qos : u8,
+    /* Lifecycle events published to the broker, disabled when absent */
+    pub(crate) events : Option<MqttEventsConfig>
+}
+
+impl Default for MqttConfig
+{
+    fn default() -> Self
+    {
+        MqttConfig {
+            broker : String::from("mqtt://localhost:1883"),
+            topic : String::from("notifyd/speak"),
+            client_id : None,
+            username : None,
+            password : None,
+            qos : 1,
+            events : None
+        }
+    }
+}
+
+/* Topic of each event, "" to leave it out */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MqttEventsConfig
+{
+    pub(crate) received : String,
+    pub(crate) synthesized : String,
+    pub(crate) played : String,
+    pub(crate) failed : String,
+    pub(crate) availability : String,
+    /* Kept by the broker for the clients subscribing later */
+    pub(crate) retain : bool
+}
+
+impl Default for MqttEventsConfig
+{
+    fn default() -> Self
+    {
+        MqttEventsConfig {
+            received : String::from("notifyd/events/received"),
+            synthesized : String::from("notifyd/events/synthesized"),
+            played : String::from("notifyd/events/played"),
+            failed : String::from("notifyd/events/failed"),
+            availability : String::from("notifyd/events/availability"),
+            retain : false
+        }
+    }
+}
+
+/* Topic and payload of a line of mosquitto_sub, printed as "%t %x" */
+pub(crate) fn parse_message(line : &str) -> Option<(String, Vec<u8>)>
+{
+    let (topic, hex) = line.rsplit_once(' ')?;
+
+    let payload = (0..hex.len()).step_by(2)
+                                .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                                .collect::<Option<Vec<u8>>>()?;
+
+    Some((topic.to_string(), payload))
+}
+
+impl MqttConfig
+{
+    /* Host, port and whether TLS is used */
+    pub(crate) fn endpoint(self : &Self) -> Result<(String, u16, bool), Box<dyn std::error::Error>>
+    {
+        let (tls, rest) = match self.broker.split_once("://") {
+            Some(("mqtt", r)) => (false, r),
+            Some(("mqtts", r)) => (true, r),
+            _ => return Err(NotifydError::new(format!("MQTT broker {} must be an mqtt:// or mqtts:// URL", self.broker).as_str()))
+        };
+
+        let rest = rest.trim_end_matches('/');
+
+        match rest.rsplit_once(':') {
+            Some((host, port)) => Ok((host.to_string(), port.parse()?, tls)),
+            None => Ok((rest.to_string(), if tls { 8883 } else { 1883 }, tls))
+        }
+    }
+
+    /* Credentials go through the options file of the tools in scratch, out of the process list */
+    pub(crate) fn write_options(self : &Self, tool : &str, scratch : &Path) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut options = String::new();
+        if let Some(u) = &self.username
+        {
+            options.push_str(&format!("-u {}\n", u));
+        }
+        if let Some(p) = &self.password
+        {
+            options.push_str(&format!("-P {}\n", p));
+        }
+
+        /* Readable by the daemon only */
+        let mut file = std::fs::OpenOptions::new();
+        file.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        file.mode(0o600);
+
+        file.open(scratch.join(tool))?.write_all(options.as_bytes())?;
+        Ok(())
+    }
+
+    /* mosquitto_sub or mosquitto_pub connected to the broker, its options file being in scratch */
+    pub(crate) fn client(self : &Self, tool : &str, scratch : &Path) -> Result<Command, Box<dyn std::error::Error>>
+    {
+        let (host, port, tls) = self.endpoint()?;
+
+        let mut cmd = Command::new(tool);
+        cmd.env("XDG_CONFIG_HOME", scratch)
+           .args(["-h", &host, "-p", &port.to_string(), "-q", &self.qos.min(2).to_string()]);
+
+        if tls
+        {
+            cmd.args(["--capath", "/etc/ssl/certs"]);
+        }
+
+        Ok(cmd)
+    }
+
+    /* mosquitto_sub printing one "topic hex-payload" line per message */
+    pub(crate) fn subscriber(self : &Self, scratch : &Path) -> Result<Command, Box<dyn std::error::Error>>
+    {
+        self.write_options("mosquitto_sub", scratch)?;
+
+        let mut cmd = self.client("mosquitto_sub", scratch)?;
+        cmd.args(["-t", &self.topic, "-F", "%t %x"])
+           .stdout(Stdio::piped())
+           .stderr(Stdio::piped());
+
+        if let Some(id) = &self.client_id
+        {
+            /* Sessions are kept by the broker for a fixed client id, messages sent while away included */
+            cmd.args(["-i", id, "-c"]);
+        }
+
+        Ok(cmd)
+    }
+}
+
+/* Lifecycle events, published with mosquitto_pub */
+pub struct MqttEvents
+{
+    pub(crate) config : MqttConfig,
+    pub(crate) topics : MqttEventsConfig,
+    /* Holds the options file of mosquitto_pub */
+    pub(crate) scratch : TempDir
+}
+
+impl MqttEvents
+{
+    pub(crate) fn new(config : &MqttConfig) -> Result<Option<MqttEvents>, Box<dyn std::error::Error>>
+    {
+        let topics = match &config.events {
+            Some(t) => t.clone(),
+            None => return Ok(None)
+        };
+
+        if which::which("mosquitto_pub").is_err()
+        {
+            return Err(NotifydError::new("Cannot locate mosquitto_pub in path"));
+        }
+
+        let events = MqttEvents {
+            config : config.clone(),
+            topics : topics,
+            scratch : TempDir::new("notifydmqtt")?
+        };
+
+        events.config.endpoint()?;
+        events.config.write_options("mosquitto_pub", events.scratch.path())?;
+
+        Ok(Some(events))
+    }
+
+    pub(crate) fn publish(self : &Self, topic : &str, payload : serde_json::Value)
+    {
+        if topic.is_empty()
+        {
+            return;
+        }
+
+        let mut cmd = match self.config.client("mosquitto_pub", self.scratch.path()) {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Failed to publish the MQTT event on {} : {}", topic, e);
+                return;
+            }
+        };
+
+        cmd.args(["-t", topic, "-s"])
+           .stdin(Stdio::piped())
+           .stdout(Stdio::null())
+           .stderr(Stdio::piped());
+
+        if self.topics.retain
+        {
+            cmd.arg("-r");
+        }
+
+        /* Never hold the queue back for an event */
+        let topic = topic.to_string();
+        std::thread::spawn(move || {
+            let sent = cmd.spawn().and_then(|mut child| {
+                if let Some(mut stdin) = child.stdin.take()
+                {
+                    stdin.write_all(payload.to_string().as_bytes())?;
+                }
+                child.wait_with_output()
+            });
+
+            match sent {
+                Ok(out) if !out.status.success() => {
+                    warn!("Failed to publish the MQTT event on {} : {}", topic, String::from_utf8_lossy(&out.stderr).trim());
+                },
+                Err(e) => {
+                    warn!("Failed to publish the MQTT event on {} : {}", topic, e);
+                },
+                _ => {}
+            }
+        });
+    }
+
+    pub(crate) fn send(self : &Self, kind : EventKind, body : &serde_json::Value)
+    {
+        let topic = match kind {
+            EventKind::Received => &self.topics.received,
+            EventKind::Synthesized => &self.topics.synthesized,
+            EventKind::Played => &self.topics.played,
+            EventKind::Failed => &self.topics.failed,
+            EventKind::Availability => &self.topics.availability
+        };
+
+        self.publish(topic, body.clone());
+    }
+}
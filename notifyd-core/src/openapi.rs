@@ -0,0 +1,794 @@
+use serde_json::{json, Map, Value};
+
+/*************************
+ * OPENAPI SPECIFICATION *
+ *************************/
+
+fn schema(name : &str) -> Value
+{
+    json!({ "$ref" : format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(name : &str) -> Value
+{
+    json!({ "type" : "array", "items" : schema(name) })
+}
+
+fn json_body(name : &str, required : bool) -> Value
+{
+    json!({
+        "required" : required,
+        "content" : { "application/json" : { "schema" : schema(name) } }
+    })
+}
+
+fn json_answer(description : &str, body : Value) -> Value
+{
+    json!({
+        "description" : description,
+        "content" : { "application/json" : { "schema" : body } }
+    })
+}
+
+fn path_param(name : &str, description : &str, kind : &str) -> Value
+{
+    json!({ "name" : name, "in" : "path", "required" : true, "description" : description, "schema" : { "type" : kind } })
+}
+
+fn query_param(name : &str, description : &str, kind : &str) -> Value
+{
+    json!({ "name" : name, "in" : "query", "required" : false, "description" : description, "schema" : { "type" : kind } })
+}
+
+/* An operation answering JSON, failures being described by the Outcome schema */
+fn op(tag : &str, summary : &str, description : &str, body : Option<Value>, answer : Value) -> Value
+{
+    let mut op = json!({
+        "tags" : [tag],
+        "summary" : summary,
+        "description" : description,
+        "responses" : {
+            "200" : json_answer("Success", answer),
+            "400" : json_answer("Bad arguments", schema("Outcome")),
+            "401" : json_answer("Missing or invalid token", schema("Outcome")),
+            "403" : json_answer("The token lacks the scope", schema("Outcome"))
+        }
+    });
+
+    if let Some(b) = body
+    {
+        op["requestBody"] = b;
+    }
+
+    op
+}
+
+/* Notifications are answered once queued, or once delivered with "wait" */
+fn queued_op(tag : &str, summary : &str, description : &str, body : Value) -> Value
+{
+    let mut op = op(tag, summary, description, Some(body), schema("Outcome"));
+    op["responses"]["200"]["description"] = json!("Delivered, when \"wait\" was set");
+    op["responses"]["202"] = json_answer("Queued", schema("Queued"));
+    op["responses"]["429"] = json_answer("The queue is full, Retry-After telling when to come back", schema("Outcome"));
+    op["responses"]["503"] = json_answer("Out of resources or shutting down, Retry-After telling when to come back", schema("Outcome"));
+    op
+}
+
+fn open(mut op : Value) -> Value
+{
+    op["security"] = json!([]);
+    op
+}
+
+fn with_params(mut op : Value, params : Vec<Value>) -> Value
+{
+    op["parameters"] = Value::Array(params);
+    op
+}
+
+fn with_answer(mut op : Value, code : &str, description : &str) -> Value
+{
+    op["responses"][code] = json_answer(description, schema("Outcome"));
+    op
+}
+
+fn audio_answer(description : &str) -> Value
+{
+    json!({
+        "description" : description,
+        "content" : {
+            "audio/wav" : { "schema" : { "type" : "string", "format" : "binary" } },
+            "audio/mpeg" : { "schema" : { "type" : "string", "format" : "binary" } },
+            "application/json" : { "schema" : schema("Outcome") }
+        }
+    })
+}
+
+fn paths() -> Value
+{
+    let id = || path_param("id", "Identifier", "integer");
+
+    let mut speak = op("speech", "Speak on the local speaker", "Same as /notify on the local speaker. With Accept: audio/wav or audio/mpeg, nothing is played and the audio is returned instead.", Some(json_body("SpeakRequest", true)), schema("Outcome"));
+    speak["responses"]["200"] = audio_answer("Played, or the synthesized audio");
+
+    let mut synthesize = op("speech", "Synthesize without playing", "Returns the audio (WAV unless audio/mpeg is accepted), streamed sentence by sentence with \"stream\", or a signed audio_url with \"as_url\".", Some(json_body("SpeakRequest", true)), schema("Outcome"));
+    synthesize["responses"]["200"] = audio_answer("The synthesized audio, or its URL with \"as_url\"");
+
+    let mut notify = queued_op("speech", "Send a notification", "Speaks the text, or plays the audio attachment, on the target. With \"delay_seconds\" or \"at\", the notification is scheduled instead and answered with its schedule_id. A retry carrying the idempotency key of an earlier request gets its answer back, with the status of its job, instead of being queued again.", json_body("Notification", true));
+    notify["responses"]["200"]["content"]["application/json"]["schema"] = json!({ "oneOf" : [schema("Outcome"), schema("Scheduled")] });
+    notify["responses"]["409"] = json_answer("A request with the same idempotency key is being processed", schema("Outcome"));
+    notify["parameters"] = json!([{
+        "name" : "Idempotency-Key", "in" : "header", "required" : false, "schema" : { "type" : "string" },
+        "description" : "Key identifying the request across retries, taking precedence over the id field"
+    }]);
+
+    let mut static_audio = open(op("audio", "Synthesized audio", "Files handed to cast devices and clients, only served through the signed audio_url of the daemon.", None, json!({})));
+    static_audio["responses"]["200"] = audio_answer("The audio file");
+    static_audio["responses"]["403"] = json_answer("Unsigned or expired URL", schema("Outcome"));
+
+    let mut history_audio = op("history", "Archived audio of a notification", "", None, json!({}));
+    history_audio["responses"]["200"] = audio_answer("The archived audio");
+
+    let mut sound = op("sounds", "Download a sound", "", None, json!({}));
+    sound["responses"]["200"] = audio_answer("The sound file");
+
+    let mut upload = op("sounds", "Upload a sound", "Replaces the uploaded sound of the same name.", Some(json_body("SoundUpload", true)), schema("Outcome"));
+    upload["responses"]["201"] = json_answer("Uploaded", schema("Sound"));
+
+    let mut metrics = op("status", "Prometheus metrics", "Counters, gauges and histograms in the Prometheus text format.", None, json!({}));
+    metrics["responses"]["200"] = json!({ "description" : "Metrics", "content" : { "text/plain" : { "schema" : { "type" : "string" } } } });
+
+    let mut dashboard = open(op("status", "Web dashboard", "Page following the queue, the history and the devices, with controls.", None, json!({})));
+    dashboard["responses"]["200"] = json!({ "description" : "The dashboard", "content" : { "text/html" : { "schema" : { "type" : "string" } } } });
+
+    let stream_filters = vec![
+        query_param("events", "Comma separated kinds of events to send, e.g. played,failed", "string"),
+        query_param("target", "Only the events of this target", "string"),
+        query_param("token", "Token with the read scope, for clients that cannot set headers", "string")
+    ];
+
+    let mut ws = with_params(op("events", "Lifecycle events over a WebSocket", "Upgrades to a WebSocket on which every event is pushed as a JSON text message.", None, schema("Event")), stream_filters.clone());
+    ws["responses"]["101"] = json!({ "description" : "Switching to the WebSocket protocol" });
+    ws["responses"]["503"] = json_answer("Too many clients follow the events", schema("Outcome"));
+
+    let mut events = with_params(op("events", "Lifecycle events as Server-Sent Events", "Each event is sent under its name with the JSON object as data.", None, json!({})), stream_filters);
+    events["responses"]["200"] = json!({ "description" : "Event stream", "content" : { "text/event-stream" : { "schema" : { "type" : "string" } } } });
+    events["responses"]["503"] = json_answer("Too many clients follow the events", schema("Outcome"));
+
+    let mut readyz = open(op("status", "Readiness probe", "", None, schema("Readiness")));
+    readyz["responses"]["503"] = json_answer("The default target is unreachable", schema("Readiness"));
+
+    let mut gotify = op("integrations", "Gotify message API", "POST /message of Gotify, the app token being any token with the notify scope.", None, schema("GotifyMessage"));
+    gotify["requestBody"] = json!({
+        "required" : true,
+        "content" : {
+            "application/json" : { "schema" : schema("GotifyRequest") },
+            "application/x-www-form-urlencoded" : { "schema" : schema("GotifyRequest") }
+        }
+    });
+    gotify["parameters"] = json!([
+        query_param("token", "App token", "string"),
+        { "name" : "X-Gotify-Key", "in" : "header", "required" : false, "description" : "App token", "schema" : { "type" : "string" } }
+    ]);
+
+    let mut github = open(op("integrations", "GitHub webhooks", "Workflow run and check suite deliveries, announced when completed. Deliveries must be signed with the secret of the [github] section.", Some(json!({ "required" : true, "content" : { "application/json" : { "schema" : { "type" : "object" } } } })), schema("Outcome")));
+    github["parameters"] = json!([
+        { "name" : "X-Hub-Signature-256", "in" : "header", "required" : true, "schema" : { "type" : "string" } },
+        { "name" : "X-GitHub-Event", "in" : "header", "required" : true, "schema" : { "type" : "string" } }
+    ]);
+    github["responses"]["401"] = json_answer("Bad signature", schema("Outcome"));
+
+    /* Buttons send nothing */
+    let mut preset = queued_op("speech", "Play a preset announcement", "Queues the text, target and speech fields of the [presets.<name>] section. The body, if any, is ignored.", json!({}));
+    if let Some(p) = preset.as_object_mut()
+    {
+        p.remove("requestBody");
+    }
+
+    let mut paths = Map::new();
+
+    paths.insert(String::from("/"), json!({ "get" : dashboard }));
+    paths.insert(String::from("/openapi.json"), json!({
+        "get" : open(op("status", "This specification", "", None, json!({ "type" : "object" })))
+    }));
+    paths.insert(String::from("/notify"), json!({ "post" : notify }));
+    paths.insert(String::from("/notify/t/{topic}"), json!({
+        "post" : with_answer(with_params(queued_op("speech", "Send a notification of a topic", "Like /notify, the defaults of the [topics.<topic>] section applying, its target included. Once the topic has tokens, they are accepted besides the tokens with the notify scope.", json_body("Notification", true)), vec![
+            path_param("topic", "Name of the topic in the configuration", "string"),
+            query_param("token", "Token of the topic, for devices that cannot set headers", "string"),
+            notify["parameters"][0].clone()
+        ]), "404", "Unknown topic")
+    }));
+    paths.insert(String::from("/notify/template/{name}"), json!({
+        "post" : with_answer(with_params(queued_op("speech", "Send a notification phrased by a template", "Fills the {variables} of the template of the configuration with the fields of the body, which may also carry a target and the speech fields of /notify. The template suffixed with the language of the request (\"washer.fr-FR\", then \"washer.fr\") is preferred.", json!({
+            "required" : true,
+            "content" : { "application/json" : { "schema" : { "type" : "object", "additionalProperties" : true, "example" : { "minutes" : 42 } } } }
+        })), vec![
+            path_param("name", "Name of the template in the configuration", "string")
+        ]), "404", "Unknown template")
+    }));
+    paths.insert(String::from("/notify/preset/{name}"), json!({
+        "post" : with_answer(with_params(preset, vec![
+            path_param("name", "Name of the preset in the configuration", "string")
+        ]), "404", "Unknown preset")
+    }));
+    paths.insert(String::from("/notify/homeassistant"), json!({
+        "post" : queued_op("integrations", "Home Assistant RESTful notify platform", "The message is spoken after the title, speech fields being taken from the top level or from data.", json_body("HomeAssistantRequest", true))
+    }));
+    paths.insert(String::from("/message"), json!({ "post" : gotify }));
+    paths.insert(String::from("/integrations/github"), json!({ "post" : github }));
+    paths.insert(String::from("/webhooks/{name}"), json!({
+        "post" : with_params(queued_op("integrations", "Generic webhook", "Speaks the text selected by the JSONPath or rendered by the template of the [webhooks.<name>] section, nothing when it is empty.", json!({ "required" : true, "content" : { "application/json" : { "schema" : { "type" : "object" } } } })), vec![
+            path_param("name", "Name of the webhook in the configuration", "string"),
+            query_param("token", "Token with the notify scope, for services that cannot set headers", "string")
+        ])
+    }));
+    paths.insert(String::from("/action/speak"), json!({ "post" : speak }));
+    paths.insert(String::from("/action/synthesize"), json!({ "post" : synthesize.clone() }));
+    paths.insert(String::from("/synthesize"), json!({ "post" : synthesize }));
+    paths.insert(String::from("/action/cast"), json!({
+        "post" : queued_op("speech", "Cast to a device", "", json_body("CastRequest", true))
+    }));
+    paths.insert(String::from("/action/time"), json!({
+        "post" : queued_op("speech", "Announce the time", "", json_body("TargetedSpeech", false))
+    }));
+    paths.insert(String::from("/action/weather"), json!({
+        "post" : queued_op("speech", "Announce the weather", "Fetched from the provider of the [weather] section.", json_body("TargetedSpeech", false))
+    }));
+    paths.insert(String::from("/action/repeat"), json!({
+        "post" : with_answer(queued_op("speech", "Play the last notification again", "", json_body("TargetedSpeech", false)), "404", "Nothing was played on the target yet")
+    }));
+    paths.insert(String::from("/action/play"), json!({
+        "post" : with_answer(queued_op("audio", "Play a named sound", "", json_body("PlayRequest", true)), "404", "Unknown sound")
+    }));
+    paths.insert(String::from("/action/play_url"), json!({
+        "post" : with_answer(queued_op("audio", "Play the audio at a URL", "WAV, MP3, OGG or FLAC, fetched by the daemon.", json_body("PlayUrlRequest", true)), "502", "The audio could not be fetched")
+    }));
+    paths.insert(String::from("/action/stop"), json!({
+        "post" : with_answer(op("playback", "Stop the notification being played", "", None, schema("Outcome")), "409", "Nothing is playing")
+    }));
+    paths.insert(String::from("/action/pause"), json!({
+        "post" : with_answer(op("playback", "Pause the notification being played", "", None, schema("Outcome")), "409", "Nothing is playing or already paused")
+    }));
+    paths.insert(String::from("/action/resume"), json!({
+        "post" : with_answer(op("playback", "Resume the paused notification", "", None, schema("Outcome")), "409", "Nothing is paused")
+    }));
+    paths.insert(String::from("/action/timer"), json!({
+        "post" : op("timers", "Start a timer", "Rings after \"minutes\", then says the label.", Some(json_body("TimerRequest", true)), schema("TimerCreated"))
+    }));
+    paths.insert(String::from("/action/alarm"), json!({
+        "post" : op("timers", "Set an alarm", "Rings at the next occurrence of \"at\" (HH:MM), then says the label.", Some(json_body("TimerRequest", true)), schema("TimerCreated"))
+    }));
+    paths.insert(String::from("/timers"), json!({
+        "get" : op("timers", "List the pending timers, alarms and delayed notifications", "", None, array_of("ScheduledEntry"))
+    }));
+    paths.insert(String::from("/timers/{id}/cancel"), json!({
+        "post" : with_params(op("timers", "Cancel a timer, alarm or delayed notification", "", None, schema("Outcome")), vec![id()])
+    }));
+    paths.insert(String::from("/schedules"), json!({
+        "get" : op("schedules", "List the recurring announcements", "", None, array_of("Schedule")),
+        "post" : op("schedules", "Create a recurring announcement", "", Some(json_body("ScheduleRequest", true)), schema("Schedule"))
+    }));
+    paths.insert(String::from("/schedules/{id}"), json!({
+        "parameters" : [id()],
+        "get" : op("schedules", "Show a recurring announcement", "", None, schema("Schedule")),
+        "post" : op("schedules", "Update a recurring announcement", "Fields left out are kept.", Some(json_body("ScheduleUpdate", true)), schema("Schedule")),
+        "delete" : op("schedules", "Remove a recurring announcement", "", None, schema("Outcome"))
+    }));
+    paths.insert(String::from("/queue"), json!({
+        "get" : op("status", "Pending notifications", "", None, schema("QueueListing"))
+    }));
+    paths.insert(String::from("/jobs/{id}"), json!({
+        "get" : with_answer(with_params(op("status", "Where a job stands", "Its queue entry while pending or running, then its history entry.", None, json!({ "oneOf" : [schema("QueueEntry"), schema("HistoryEntry")] })), vec![id()]), "404", "No such job")
+    }));
+    paths.insert(String::from("/jobs/{id}/bump"), json!({
+        "post" : with_params(op("playback", "Move a job to the front of the queue", "Or re-rank it when a priority is given.", Some(json_body("BumpRequest", false)), schema("Outcome")), vec![id()])
+    }));
+    paths.insert(String::from("/status"), json!({
+        "get" : op("status", "What the daemon is doing", "", None, schema("Status"))
+    }));
+    paths.insert(String::from("/readyz"), json!({ "get" : readyz }));
+    paths.insert(String::from("/ws"), json!({ "get" : ws }));
+    paths.insert(String::from("/events"), json!({ "get" : events }));
+    paths.insert(String::from("/volume"), json!({
+        "get" : with_params(op("playback", "Level of a target", "", None, schema("VolumeState")), vec![query_param("uid", "Target, the default one otherwise", "string")]),
+        "post" : op("playback", "Set the level of a target", "", Some(json_body("VolumeRequest", true)), schema("VolumeState"))
+    }));
+    paths.insert(String::from("/metrics"), json!({ "get" : metrics }));
+    paths.insert(String::from("/voices"), json!({
+        "get" : op("status", "Installed engines and their voices", "", None, schema("Voices"))
+    }));
+    paths.insert(String::from("/history"), json!({
+        "get" : with_params(op("history", "Recorded notifications, oldest first", "", None, array_of("HistoryEntry")), vec![
+            query_param("limit", "Number of last entries, 500 by default", "integer"),
+            query_param("since", "RFC 3339 time or unix timestamp", "string")
+        ])
+    }));
+    paths.insert(String::from("/history/{id}/audio"), json!({
+        "get" : with_params(history_audio, vec![id()])
+    }));
+    paths.insert(String::from("/devices"), json!({
+        "get" : with_params(op("devices", "Discover and list the devices", "Discovery takes a few seconds, ?cached lists the registry right away.", None, array_of("Device")), vec![
+            json!({ "name" : "cached", "in" : "query", "required" : false, "allowEmptyValue" : true, "schema" : { "type" : "string" } })
+        ]),
+        "post" : op("devices", "Add a device by hand", "", Some(json_body("Device", true)), schema("Outcome"))
+    }));
+    paths.insert(String::from("/devices/refresh"), json!({
+        "post" : op("devices", "Discover the cast devices", "", None, schema("Outcome"))
+    }));
+    paths.insert(String::from("/devices/{uuid}"), json!({
+        "parameters" : [path_param("uuid", "UUID of the device", "string")],
+        "get" : op("devices", "Show a device", "", None, schema("Device")),
+        "post" : op("devices", "Update a device", "An empty alias removes it.", Some(json_body("DeviceUpdate", true)), schema("Device")),
+        "delete" : op("devices", "Remove a device", "", None, schema("Outcome"))
+    }));
+    paths.insert(String::from("/sounds"), json!({
+        "get" : op("sounds", "The sound library", "", None, array_of("Sound")),
+        "post" : upload
+    }));
+    paths.insert(String::from("/sounds/{name}"), json!({
+        "parameters" : [path_param("name", "Name of the sound", "string")],
+        "get" : sound,
+        "delete" : op("sounds", "Remove an uploaded sound", "", None, schema("Outcome"))
+    }));
+    paths.insert(String::from("/static/{file}"), json!({
+        "get" : with_params(static_audio, vec![
+            path_param("file", "File name", "string"),
+            query_param("expires", "Unix time the URL expires", "integer"),
+            query_param("sig", "Signature of the URL", "string")
+        ])
+    }));
+    paths.insert(String::from("/admin/selftest"), json!({
+        "post" : op("admin", "Speak a test phrase through the whole pipeline", "", None, schema("SelfTest"))
+    }));
+    paths.insert(String::from("/admin/engine"), json!({
+        "get" : op("admin", "Default TTS engine", "", None, schema("EngineState")),
+        "post" : op("admin", "Switch the default TTS engine", "The engine is probed before being used.", Some(json_body("EngineRequest", true)), schema("EngineState"))
+    }));
+    paths.insert(String::from("/admin/dnd"), json!({
+        "get" : op("admin", "Do-not-disturb state", "", None, schema("DndState")),
+        "post" : op("admin", "Override the quiet hours", "", Some(json_body("DndRequest", true)), schema("DndState"))
+    }));
+    paths.insert(String::from("/admin/flush"), json!({
+        "post" : op("admin", "Delete the synthesized audio files", "", None, schema("Flushed"))
+    }));
+    paths.insert(String::from("/admin/config"), json!({
+        "get" : op("admin", "Effective configuration, secrets redacted", "", None, json!({ "type" : "object" }))
+    }));
+    paths.insert(String::from("/admin/reload"), json!({
+        "post" : op("admin", "Re-read the configuration file", "", None, schema("Reloaded"))
+    }));
+    paths.insert(String::from("/admin/tokens"), json!({
+        "get" : op("admin", "List the client tokens", "", None, array_of("ClientToken")),
+        "post" : op("admin", "Create a client token", "The token itself is only returned here.", Some(json_body("TokenRequest", true)), schema("CreatedToken"))
+    }));
+    paths.insert(String::from("/admin/tokens/{id}"), json!({
+        "parameters" : [id()],
+        "get" : op("admin", "Show a client token", "", None, schema("ClientToken")),
+        "post" : op("admin", "Change the label or scopes of a client token", "", Some(json_body("TokenUpdate", true)), schema("ClientToken")),
+        "delete" : op("admin", "Revoke a client token", "", None, schema("Outcome"))
+    }));
+
+    Value::Object(paths)
+}
+
+/* Object made of the speech settings and of its own properties */
+fn with_speech(properties : Value, required : &[&str]) -> Value
+{
+    let mut own = json!({ "type" : "object", "properties" : properties });
+
+    /* OpenAPI 3.0 does not allow an empty list */
+    if !required.is_empty()
+    {
+        own["required"] = json!(required);
+    }
+
+    json!({ "allOf" : [schema("SpeechParams"), own] })
+}
+
+fn schemas() -> Value
+{
+    let string = json!({ "type" : "string" });
+    let nullable_string = json!({ "type" : "string", "nullable" : true });
+    let integer = json!({ "type" : "integer" });
+    let number = json!({ "type" : "number" });
+    let boolean = json!({ "type" : "boolean" });
+
+    let mut schemas = Map::new();
+
+    schemas.insert(String::from("Priority"), json!({ "type" : "string", "enum" : ["low", "normal", "high", "urgent"] }));
+    schemas.insert(String::from("Scope"), json!({ "type" : "string", "enum" : ["notify", "read", "admin"] }));
+    schemas.insert(String::from("Segment"), json!({
+        "type" : "object",
+        "properties" : { "text" : string, "lang" : string, "voice" : string, "phonemes" : string }
+    }));
+    schemas.insert(String::from("SpeechParams"), json!({
+        "type" : "object",
+        "properties" : {
+            "topic" : { "type" : "string", "description" : "Topic whose configured defaults apply" },
+            "speed" : { "type" : "string", "enum" : ["slow", "normal", "fast", "urgent"] },
+            "rate" : { "type" : "number", "minimum" : 0.25, "maximum" : 4.0, "description" : "Speed-up factor, overriding the speed" },
+            "pitch" : { "type" : "number", "minimum" : 0.5, "maximum" : 2.0 },
+            "gain" : { "type" : "number", "minimum" : 0.0, "maximum" : 4.0 },
+            "volume" : { "type" : "number", "minimum" : 0.0, "maximum" : 1.0, "description" : "Level of the target while this plays" },
+            "variant" : { "type" : "string", "description" : "espeak voice variant, e.g. f3" },
+            "lang" : string,
+            "voice" : { "type" : "string", "description" : "Voice name or engine:voice" },
+            "phonemes" : { "type" : "string", "description" : "Spoken instead of the text, for engines supporting it" },
+            "segments" : array_of("Segment"),
+            "priority" : schema("Priority"),
+            "prefix" : { "type" : "string", "description" : "Said before the text" },
+            "replace_key" : { "type" : "string", "description" : "Pending notifications with the same key are replaced" },
+            "dedup_key" : { "type" : "string", "description" : "Merged into an earlier notification with the same key" },
+            "wait" : { "type" : "boolean", "description" : "Answer once delivered instead of once queued" },
+            "repeat" : { "type" : "integer", "minimum" : 1, "maximum" : 10 },
+            "repeat_gap_ms" : { "type" : "integer", "maximum" : 60000 },
+            "interrupt" : { "type" : "boolean", "description" : "Stop the notification being played and go first" }
+        }
+    }));
+    schemas.insert(String::from("Notification"), json!(with_speech(json!({
+        "text" : string,
+        "audio_b64" : { "type" : "string", "format" : "byte", "description" : "WAV, MP3, OGG or FLAC played instead of the text" },
+        "target" : { "type" : "string", "description" : "Device, group, alias or local; the default target otherwise" },
+        "delay_seconds" : number,
+        "at" : { "type" : "string", "format" : "date-time" },
+        "id" : { "type" : "string", "description" : "Idempotency key, when the Idempotency-Key header is not given" }
+    }), &[])));
+    schemas.insert(String::from("CastRequest"), json!(with_speech(json!({ "text" : string, "uid" : string }), &["uid"])));
+    schemas.insert(String::from("SpeakRequest"), json!(with_speech(json!({
+        "text" : string,
+        "stream" : { "type" : "boolean", "description" : "Chunked WAV rendered sentence by sentence" },
+        "as_url" : { "type" : "boolean", "description" : "Answer with a signed audio_url" }
+    }), &[])));
+    schemas.insert(String::from("TargetedSpeech"), json!(with_speech(json!({ "uid" : string }), &[])));
+    schemas.insert(String::from("PlayRequest"), json!(with_speech(json!({ "sound" : string, "uid" : string }), &["sound"])));
+    schemas.insert(String::from("PlayUrlRequest"), json!(with_speech(json!({ "url" : { "type" : "string", "format" : "uri" }, "text" : string, "uid" : string }), &["url"])));
+    schemas.insert(String::from("HomeAssistantRequest"), json!(with_speech(json!({
+        "message" : string,
+        "title" : string,
+        "target" : { "oneOf" : [string, { "type" : "array", "items" : string, "maxItems" : 1 }] },
+        "data" : schema("SpeechParams")
+    }), &["message"])));
+    schemas.insert(String::from("GotifyRequest"), json!({
+        "type" : "object",
+        "required" : ["message"],
+        "properties" : {
+            "title" : string,
+            "message" : string,
+            "priority" : { "type" : "integer", "minimum" : 0, "maximum" : 10 },
+            "extras" : { "type" : "object", "properties" : { "notifyd::speech" : with_speech(json!({ "target" : string }), &[]) } }
+        }
+    }));
+    schemas.insert(String::from("GotifyMessage"), json!({
+        "type" : "object",
+        "properties" : { "id" : integer, "appid" : integer, "title" : string, "message" : string, "priority" : integer, "date" : { "type" : "string", "format" : "date-time" } }
+    }));
+    schemas.insert(String::from("Outcome"), json!({
+        "type" : "object",
+        "required" : ["success", "reason", "err"],
+        "properties" : {
+            "success" : boolean,
+            "reason" : string,
+            "err" : string,
+            "audio_url" : string,
+            "duration_ms" : { "type" : "integer", "nullable" : true },
+            "synthesis_ms" : integer,
+            "engine" : string,
+            "voice" : string,
+            "target" : string,
+            "fallback" : boolean,
+            "fallback_from" : string,
+            "cast_attempts" : integer,
+            "cached" : boolean
+        }
+    }));
+    schemas.insert(String::from("Queued"), json!({
+        "type" : "object",
+        "properties" : {
+            "success" : boolean, "reason" : string, "err" : string, "id" : integer, "status_url" : string,
+            "status" : { "type" : "string", "description" : "Where the job stands, in the answers to retries" }
+        }
+    }));
+    schemas.insert(String::from("Scheduled"), json!({
+        "type" : "object",
+        "properties" : { "success" : boolean, "reason" : string, "err" : string, "schedule_id" : integer, "due" : string }
+    }));
+    schemas.insert(String::from("QueueEntry"), json!({
+        "type" : "object",
+        "properties" : {
+            "id" : integer,
+            "position" : integer,
+            "priority" : schema("Priority"),
+            "target" : string,
+            "text" : string,
+            "eta_ms" : integer,
+            "replace_key" : string,
+            "status" : { "type" : "string", "enum" : ["pending", "running"] }
+        }
+    }));
+    schemas.insert(String::from("QueueListing"), json!({
+        "type" : "object",
+        "properties" : { "running" : { "allOf" : [schema("QueueEntry")], "nullable" : true }, "pending" : array_of("QueueEntry") }
+    }));
+    schemas.insert(String::from("Status"), json!({
+        "type" : "object",
+        "properties" : {
+            "version" : string,
+            "instance" : nullable_string,
+            "uptime_secs" : integer,
+            "playing" : { "allOf" : [schema("QueueEntry")], "nullable" : true },
+            "pending" : array_of("QueueEntry"),
+            "engine" : string,
+            "voice" : nullable_string,
+            "lang" : string,
+            "target" : string,
+            "paused" : boolean,
+            "do_not_disturb" : boolean
+        }
+    }));
+    schemas.insert(String::from("Readiness"), json!({
+        "type" : "object",
+        "properties" : {
+            "ready" : boolean,
+            "targets" : {
+                "type" : "object",
+                "additionalProperties" : {
+                    "type" : "object",
+                    "properties" : { "available" : boolean, "since" : integer, "checked" : integer, "err" : string }
+                }
+            }
+        }
+    }));
+    schemas.insert(String::from("HistoryEntry"), json!({
+        "type" : "object",
+        "properties" : {
+            "time" : { "type" : "string", "format" : "date-time" },
+            "id" : { "type" : "integer", "nullable" : true },
+            "text" : string,
+            "topic" : nullable_string,
+            "target" : string,
+            "priority" : schema("Priority"),
+            "status" : {
+                "type" : "string",
+                "enum" : ["done", "failed", "replaced", "interrupted", "stopped", "deduplicated", "deferred", "suppressed", "digested", "digest", "dropped", "expired"]
+            },
+            "items" : { "type" : "array", "items" : string },
+            "err" : string,
+            "latency_ms" : integer,
+            "audio_url" : string
+        }
+    }));
+    schemas.insert(String::from("Event"), json!({
+        "type" : "object",
+        "properties" : {
+            "event" : { "type" : "string", "enum" : ["received", "synthesized", "played", "failed", "availability"] },
+            "id" : integer,
+            "text" : string,
+            "target" : string,
+            "priority" : schema("Priority"),
+            "engine" : string,
+            "voice" : string,
+            "duration_ms" : integer,
+            "synthesis_ms" : integer,
+            "audio_url" : string,
+            "available" : boolean,
+            "error" : string
+        }
+    }));
+    schemas.insert(String::from("VolumeRequest"), json!({
+        "type" : "object",
+        "required" : ["level"],
+        "properties" : { "level" : { "type" : "number", "minimum" : 0.0, "maximum" : 1.0 }, "uid" : string, "ramp_ms" : integer }
+    }));
+    schemas.insert(String::from("VolumeState"), json!({
+        "type" : "object",
+        "properties" : { "success" : boolean, "target" : string, "level" : number }
+    }));
+    schemas.insert(String::from("TimerRequest"), json!({
+        "type" : "object",
+        "properties" : {
+            "minutes" : { "type" : "number", "description" : "Delay of a timer" },
+            "at" : { "type" : "string", "description" : "Time of day of an alarm, HH:MM" },
+            "label" : string,
+            "sound" : string,
+            "uid" : string,
+            "replace_key" : string
+        }
+    }));
+    schemas.insert(String::from("TimerCreated"), json!({
+        "type" : "object",
+        "properties" : { "success" : boolean, "reason" : string, "err" : string, "id" : integer, "due" : string }
+    }));
+    schemas.insert(String::from("ScheduledEntry"), json!({
+        "type" : "object",
+        "properties" : {
+            "id" : integer,
+            "kind" : { "type" : "string", "enum" : ["timer", "alarm", "notification"] },
+            "label" : string,
+            "due" : string,
+            "target" : string,
+            "sound" : string,
+            "replace_key" : string
+        }
+    }));
+    schemas.insert(String::from("ScheduleRequest"), json!(with_speech(json!({
+        "cron" : { "type" : "string", "description" : "Five field cron expression, or @hourly, @daily, @weekly, @monthly, @yearly" },
+        "text" : string,
+        "target" : string,
+        "enabled" : boolean
+    }), &["cron"])));
+    schemas.insert(String::from("ScheduleUpdate"), json!(with_speech(json!({ "cron" : string, "text" : string, "target" : string, "enabled" : boolean }), &[])));
+    schemas.insert(String::from("Schedule"), json!({
+        "type" : "object",
+        "properties" : {
+            "id" : integer,
+            "cron" : string,
+            "text" : string,
+            "target" : nullable_string,
+            "params" : schema("SpeechParams"),
+            "enabled" : boolean,
+            "created" : integer,
+            "last_run" : { "type" : "integer", "nullable" : true },
+            "next" : string
+        }
+    }));
+    schemas.insert(String::from("BumpRequest"), json!({
+        "type" : "object",
+        "properties" : { "priority" : schema("Priority") }
+    }));
+    schemas.insert(String::from("Device"), json!({
+        "type" : "object",
+        "required" : ["uuid", "name"],
+        "properties" : {
+            "uuid" : string,
+            "name" : string,
+            "ip" : nullable_string,
+            "model" : nullable_string,
+            "kind" : { "type" : "string", "description" : "chromecast, dlna, sonos, airplay, ..." },
+            "capabilities" : { "type" : "array", "items" : string },
+            "alias" : nullable_string,
+            "last_seen" : { "type" : "integer", "nullable" : true }
+        }
+    }));
+    schemas.insert(String::from("DeviceUpdate"), json!({
+        "type" : "object",
+        "properties" : {
+            "name" : string,
+            "ip" : string,
+            "model" : string,
+            "kind" : string,
+            "capabilities" : { "type" : "array", "items" : string },
+            "alias" : string
+        }
+    }));
+    schemas.insert(String::from("Sound"), json!({
+        "type" : "object",
+        "properties" : {
+            "name" : string,
+            "source" : { "type" : "string", "enum" : ["config", "upload"] },
+            "format" : { "type" : "string", "enum" : ["wav", "mp3", "ogg", "flac"] },
+            "bytes" : integer,
+            "created" : integer
+        }
+    }));
+    schemas.insert(String::from("SoundUpload"), json!({
+        "type" : "object",
+        "required" : ["name", "audio_b64"],
+        "properties" : {
+            "name" : { "type" : "string", "pattern" : "^[A-Za-z0-9_-]+$" },
+            "audio_b64" : { "type" : "string", "format" : "byte" }
+        }
+    }));
+    schemas.insert(String::from("SelfTest"), json!({
+        "type" : "object",
+        "properties" : {
+            "success" : boolean,
+            "target" : string,
+            "total_ms" : integer,
+            "synthesis_ms" : integer,
+            "playback_ms" : integer,
+            "engine" : string,
+            "voice" : string,
+            "audio_url" : string,
+            "reason" : string,
+            "err" : string
+        }
+    }));
+    schemas.insert(String::from("EngineInfo"), json!({
+        "type" : "object",
+        "properties" : {
+            "engine" : string,
+            "path" : string,
+            "active" : boolean,
+            "voices" : { "type" : "array", "items" : { "type" : "object" } },
+            "variants" : { "type" : "array", "items" : string },
+            "err" : string
+        }
+    }));
+    schemas.insert(String::from("EngineRequest"), json!({
+        "type" : "object",
+        "required" : ["engine"],
+        "properties" : { "engine" : string, "voice" : string }
+    }));
+    schemas.insert(String::from("EngineState"), json!({
+        "type" : "object",
+        "properties" : { "success" : boolean, "engine" : string, "path" : string, "voice" : nullable_string, "available" : array_of("EngineInfo") }
+    }));
+    schemas.insert(String::from("Voices"), json!({
+        "type" : "object",
+        "properties" : { "default_lang" : string, "engines" : array_of("EngineInfo") }
+    }));
+    schemas.insert(String::from("DndRequest"), json!({
+        "type" : "object",
+        "required" : ["mode"],
+        "properties" : { "mode" : { "type" : "string", "enum" : ["auto", "on", "off"] } }
+    }));
+    schemas.insert(String::from("DndState"), json!({
+        "type" : "object",
+        "properties" : {
+            "success" : boolean,
+            "active" : boolean,
+            "mode" : { "type" : "string", "enum" : ["auto", "on", "off"] },
+            "start" : nullable_string,
+            "end" : nullable_string,
+            "deferred" : integer
+        }
+    }));
+    schemas.insert(String::from("Flushed"), json!({
+        "type" : "object",
+        "properties" : { "success" : boolean, "files_removed" : integer, "bytes_reclaimed" : integer, "kept_for_cast" : integer }
+    }));
+    schemas.insert(String::from("Reloaded"), json!({
+        "type" : "object",
+        "properties" : { "success" : boolean, "reason" : string, "err" : string, "restart_required" : { "type" : "array", "items" : string } }
+    }));
+    schemas.insert(String::from("ClientToken"), json!({
+        "type" : "object",
+        "properties" : {
+            "id" : integer,
+            "label" : string,
+            "scopes" : array_of("Scope"),
+            "created" : integer,
+            "last_used" : { "type" : "integer", "nullable" : true }
+        }
+    }));
+    schemas.insert(String::from("TokenRequest"), json!({
+        "type" : "object",
+        "required" : ["label", "scopes"],
+        "properties" : { "label" : string, "scopes" : array_of("Scope") }
+    }));
+    schemas.insert(String::from("TokenUpdate"), json!({
+        "type" : "object",
+        "properties" : { "label" : string, "scopes" : array_of("Scope") }
+    }));
+    schemas.insert(String::from("CreatedToken"), json!({
+        "allOf" : [
+            schema("ClientToken"),
+            { "type" : "object", "properties" : { "token" : string } }
+        ]
+    }));
+
+    Value::Object(schemas)
+}
+
+/* OpenAPI 3 document of the REST API, as served by the instance mounted at the given path */
+pub fn openapi(mount : &str) -> Value
+{
+    json!({
+        "openapi" : "3.0.3",
+        "info" : {
+            "title" : "notifyd",
+            "description" : "Text-to-speech notification daemon. Scopes: notify (speaking, casting, announcements and timers), read (listings, status, history, devices, metrics, events) and admin (everything).",
+            "version" : env!("CARGO_PKG_VERSION")
+        },
+        "servers" : [{ "url" : if mount.is_empty() { "/" } else { mount } }],
+        "security" : [{ "bearer" : [] }],
+        "paths" : paths(),
+        "components" : {
+            "securitySchemes" : {
+                "bearer" : { "type" : "http", "scheme" : "bearer", "description" : "Admin or client token, required once one exists" }
+            },
+            "schemas" : schemas()
+        }
+    })
+}
@@ -0,0 +1,575 @@
+use std::fmt::{self};
+use std::path::PathBuf;
+use std::sync::{Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use serde::{Serialize, Deserialize};
+use crate::error::NotifydError;
+use crate::journal::Journal;
+use crate::notifyd::SynthesisInfo;
+use crate::now_in_usecs;
+use crate::tts::{Segment, SpeechParams};
+
+/*************
+ * JOB QUEUE *
+ *************/
+
+/* Rough speech rate used to estimate how long an item will keep the speaker busy */
+pub const MS_PER_CHAR : u64 = 70;
+pub const SYNTHESIS_OVERHEAD_MS : u64 = 500;
+
+pub fn estimated_duration_ms(text : &str) -> u64
+{
+    SYNTHESIS_OVERHEAD_MS + text.chars().count() as u64 * MS_PER_CHAR
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority
+{
+    Low,
+    Normal,
+    High,
+    Urgent
+}
+
+/* What becomes of the job an urgent one interrupted */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InterruptedJob
+{
+    /* Played again from the start once the urgent one is done */
+    #[default]
+    Resume,
+    Drop
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PreemptionConfig
+{
+    /* Whether urgent notifications stop the playback in progress */
+    pub(crate) enabled : bool,
+    pub(crate) interrupted : InterruptedJob
+}
+
+impl Default for PreemptionConfig
+{
+    fn default() -> Self
+    {
+        PreemptionConfig {
+            enabled : true,
+            interrupted : InterruptedJob::Resume
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JobInfo
+{
+    pub(crate) id : u64,
+    pub(crate) text : String,
+    pub(crate) params : SpeechParams,
+    pub(crate) target : String,
+    pub(crate) priority : Priority,
+    /* Submission time, kept when an interrupted job is queued again */
+    pub(crate) queued : u128,
+    pub(crate) started : Option<u128>
+}
+
+impl JobInfo
+{
+    /* Rough memory footprint, dominated by the texts */
+    pub(crate) fn approx_bytes(self : &Self) -> usize
+    {
+        std::mem::size_of::<Job>()
+            + self.text.len()
+            + self.target.len()
+            + self.params.segments.iter().map(|s| s.text.len() + std::mem::size_of::<Segment>()).sum::<usize>()
+            + self.params.phonemes.as_ref().map(|p| p.len()).unwrap_or(0)
+    }
+}
+pub struct Job
+{
+    pub(crate) info : JobInfo,
+    pub(crate) reply : Sender<JobOutcome>
+}
+
+/* What the playback worker reports back for a job */
+pub struct JobSuccess
+{
+    pub(crate) reason : String,
+    /* Absent for sinks that do not emit audio */
+    pub(crate) info : Option<SynthesisInfo>
+}
+
+impl JobSuccess
+{
+    pub(crate) fn new(reason : &str, info : SynthesisInfo) -> JobSuccess
+    {
+        JobSuccess {
+            reason : reason.to_string(),
+            info : Some(info)
+        }
+    }
+
+    pub(crate) fn without_audio(reason : &str) -> JobSuccess
+    {
+        JobSuccess {
+            reason : reason.to_string(),
+            info : None
+        }
+    }
+}
+
+pub struct JobFailure
+{
+    pub(crate) reason : String,
+    pub(crate) err : String,
+    /* HTTP status reported to the requester */
+    pub(crate) status : u16,
+    /* Seconds after which a refused request is worth trying again */
+    pub(crate) retry_after : Option<u64>
+}
+
+impl JobFailure
+{
+    pub(crate) fn new(reason : &str, err : impl fmt::Display) -> JobFailure
+    {
+        JobFailure {
+            reason : reason.to_string(),
+            err : err.to_string(),
+            status : 400,
+            retry_after : None
+        }
+    }
+
+    pub(crate) fn with_status(self, status : u16) -> JobFailure
+    {
+        JobFailure {
+            status : status,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_retry_after(self, secs : u64) -> JobFailure
+    {
+        JobFailure {
+            retry_after : Some(secs),
+            ..self
+        }
+    }
+}
+
+pub type JobOutcome = Result<JobSuccess, JobFailure>;
+
+#[derive(Serialize)]
+pub struct QueueEntry
+{
+    pub(crate) id : u64,
+    pub(crate) position : usize,
+    pub(crate) priority : Priority,
+    pub(crate) target : String,
+    pub(crate) text : String,
+    pub(crate) eta_ms : u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) replace_key : Option<String>
+}
+
+#[derive(Serialize)]
+pub struct QueueListing
+{
+    pub(crate) running : Option<QueueEntry>,
+    pub(crate) pending : Vec<QueueEntry>
+}
+
+pub struct QueueState
+{
+    pub(crate) pending : Vec<Job>,
+    pub(crate) running : Option<JobInfo>,
+    pub(crate) next_id : u64
+}
+
+pub struct JobQueue
+{
+    pub(crate) state : Mutex<QueueState>,
+    pub(crate) cond : Condvar,
+    /* Raised for the sinks to stop the running job, an urgent one waiting */
+    pub(crate) preempted : AtomicBool,
+    /* The running job was stopped on request, it is not to be played again */
+    pub(crate) stopped : AtomicBool,
+    /* Raised for the sinks to hold the running job where it is */
+    pub(crate) paused : AtomicBool,
+    /* Kept in the database to survive restarts, when persistence is enabled */
+    pub(crate) journal : Option<Journal>,
+    /* Raised on shutdown, jobs cut short then stay journaled for the next start */
+    pub(crate) closing : AtomicBool
+}
+
+impl JobQueue
+{
+    /* Ids start after the last one of the previous runs, so that they still name a single job in the history */
+    pub(crate) fn new(journal : Option<Journal>, first_id : u64) -> JobQueue
+    {
+        JobQueue {
+            state : Mutex::new(QueueState{
+                pending : Vec::new(),
+                running : None,
+                next_id : first_id.max(1)
+            }),
+            cond : Condvar::new(),
+            preempted : AtomicBool::new(false),
+            stopped : AtomicBool::new(false),
+            paused : AtomicBool::new(false),
+            journal : journal,
+            closing : AtomicBool::new(false)
+        }
+    }
+
+    fn save(self : &Self, info : &JobInfo)
+    {
+        if let Some(j) = &self.journal
+        {
+            j.save_job(info);
+        }
+    }
+
+    fn forget(self : &Self, id : u64)
+    {
+        if let Some(j) = &self.journal
+        {
+            j.forget_job(id);
+        }
+    }
+
+    /* Jobs are kept sorted by priority, FIFO among equal priorities,
+       the ones asking to interrupt going before all the others */
+    pub(crate) fn insert_sorted(pending : &mut Vec<Job>, job : Job)
+    {
+        let interrupt = job.info.params.interrupt;
+        let pos = pending.iter()
+                         .position(|j| !j.info.params.interrupt && (interrupt || j.info.priority < job.info.priority))
+                         .unwrap_or(pending.len());
+        pending.insert(pos, job);
+    }
+
+    /* Returns the id of the job along with the ones it replaced */
+    pub(crate) fn push(self : &Self, text : String, params : SpeechParams, target : String, priority : Priority, reply : Sender<JobOutcome>) -> (u64, Vec<JobInfo>)
+    {
+        let mut st = self.state.lock().unwrap();
+
+        let id = st.next_id;
+        st.next_id += 1;
+
+        let mut replaced_info = Vec::new();
+
+        /* Like freedesktop's replaces_id, a newer notification supersedes pending ones with its key */
+        if let Some(key) = &params.replace_key
+        {
+            let (replaced, kept) : (Vec<Job>, Vec<Job>) = std::mem::take(&mut st.pending)
+                                                            .into_iter()
+                                                            .partition(|j| j.info.params.replace_key.as_ref() == Some(key));
+            st.pending = kept;
+
+            for j in replaced
+            {
+                let _ = j.reply.send(Ok(JobSuccess::without_audio(format!("Replaced by job {}", id).as_str())));
+                self.forget(j.info.id);
+                replaced_info.push(j.info);
+            }
+        }
+
+        let job = Job {
+            info : JobInfo {
+                id : id,
+                text : text,
+                params : params,
+                target : target,
+                priority : priority,
+                queued : now_in_usecs(),
+                started : None
+            },
+            reply : reply
+        };
+
+        self.save(&job.info);
+        JobQueue::insert_sorted(&mut st.pending, job);
+        self.cond.notify_all();
+
+        (id, replaced_info)
+    }
+
+    /* Id for a job that is not going to be queued */
+    pub(crate) fn allocate_id(self : &Self) -> u64
+    {
+        let mut st = self.state.lock().unwrap();
+        st.next_id += 1;
+        st.next_id - 1
+    }
+
+    /* Queue a job left by an earlier run under a fresh id, keeping its submission time */
+    pub(crate) fn restore(self : &Self, mut info : JobInfo, reply : Sender<JobOutcome>) -> u64
+    {
+        info.id = self.allocate_id();
+        info.started = None;
+
+        let mut st = self.state.lock().unwrap();
+
+        let id = info.id;
+        self.save(&info);
+        JobQueue::insert_sorted(&mut st.pending, Job { info : info, reply : reply });
+        self.cond.notify_all();
+
+        id
+    }
+
+    /* Give a pending job with this deduplication key the text counting its duplicates,
+       returning its id. Segments, phonemes and attachments are left as they are */
+    pub(crate) fn merge_duplicate(self : &Self, target : &str, key : &str, text : &str) -> Option<u64>
+    {
+        let mut st = self.state.lock().unwrap();
+
+        let job = st.pending.iter_mut().find(|j| j.info.target == target && j.info.params.dedup_key.as_deref() == Some(key))?;
+
+        let p = &job.info.params;
+        if p.segments.is_empty() && p.phonemes.is_none() && p.audio.is_none()
+        {
+            job.info.text = text.to_string();
+            self.save(&job.info);
+        }
+
+        Some(job.info.id)
+    }
+
+    /* Blocks until a job is available and marks it as running */
+    pub(crate) fn pop(self : &Self) -> Job
+    {
+        let mut st = self.state.lock().unwrap();
+
+        while st.pending.is_empty()
+        {
+            st = self.cond.wait(st).unwrap();
+        }
+
+        let mut job = st.pending.remove(0);
+        job.info.started = Some(now_in_usecs());
+        self.preempted.store(false, Ordering::SeqCst);
+        self.stopped.store(false, Ordering::SeqCst);
+        self.paused.store(false, Ordering::SeqCst);
+        st.running = Some(job.info.clone());
+        job
+    }
+
+    pub(crate) fn finish(self : &Self)
+    {
+        let mut st = self.state.lock().unwrap();
+
+        if let Some(r) = st.running.take()
+        {
+            if !self.closing.load(Ordering::SeqCst)
+            {
+                self.forget(r.id);
+            }
+        }
+    }
+
+    /* Ask for the running job to be stopped when it is less urgent than priority,
+       returning the id of the job to stop */
+    pub(crate) fn preempt(self : &Self, priority : Priority) -> Option<u64>
+    {
+        let st = self.state.lock().unwrap();
+
+        match &st.running {
+            Some(r) if r.priority < priority => {
+                self.preempted.store(true, Ordering::SeqCst);
+                Some(r.id)
+            },
+            _ => None
+        }
+    }
+
+    pub(crate) fn running(self : &Self) -> Option<JobInfo>
+    {
+        self.state.lock().unwrap().running.clone()
+    }
+
+    /* Ask the running job to stop whatever its priority, returning its id */
+    pub(crate) fn interrupt(self : &Self) -> Option<u64>
+    {
+        let st = self.state.lock().unwrap();
+
+        st.running.as_ref().map(|r| {
+            self.preempted.store(true, Ordering::SeqCst);
+            r.id
+        })
+    }
+
+    /* Whether the job that just ended was asked to stop, clearing the request */
+    pub(crate) fn take_preempted(self : &Self) -> bool
+    {
+        self.preempted.swap(false, Ordering::SeqCst)
+    }
+
+    /* Stop the running job for good, returning its id */
+    pub(crate) fn stop(self : &Self) -> Option<u64>
+    {
+        let st = self.state.lock().unwrap();
+
+        st.running.as_ref().map(|r| {
+            self.stopped.store(true, Ordering::SeqCst);
+            self.preempted.store(true, Ordering::SeqCst);
+            self.paused.store(false, Ordering::SeqCst);
+            r.id
+        })
+    }
+
+    /* Whether the job that just ended was stopped on request, clearing it */
+    pub(crate) fn take_stopped(self : &Self) -> bool
+    {
+        self.stopped.swap(false, Ordering::SeqCst)
+    }
+
+    /* Pause or resume the running job, returning its id when the state changed */
+    pub(crate) fn set_paused(self : &Self, paused : bool) -> Result<u64, Box<dyn std::error::Error>>
+    {
+        let st = self.state.lock().unwrap();
+
+        let running = match &st.running {
+            Some(r) => r.id,
+            None => return Err(NotifydError::new("Nothing is playing"))
+        };
+
+        if self.paused.swap(paused, Ordering::SeqCst) == paused
+        {
+            return Err(NotifydError::new(if paused { "Playback is already paused" } else { "Playback is not paused" }));
+        }
+
+        Ok(running)
+    }
+
+    /* Put an interrupted job back ahead of the ones of its priority */
+    pub(crate) fn requeue(self : &Self, mut job : Job)
+    {
+        let mut st = self.state.lock().unwrap();
+
+        /* It had its turn, the job that interrupted it goes first */
+        job.info.started = None;
+        job.info.params.interrupt = false;
+        self.save(&job.info);
+        let pos = st.pending.iter()
+                            .position(|j| !j.info.params.interrupt && j.info.priority <= job.info.priority)
+                            .unwrap_or(st.pending.len());
+        st.pending.insert(pos, job);
+        self.cond.notify_all();
+    }
+
+    /* Move a pending job to the front, or re-rank it under a new priority.
+       Returns its new (1-based) position in the queue */
+    pub(crate) fn bump(self : &Self, id : u64, priority : Option<Priority>) -> Result<usize, Box<dyn std::error::Error>>
+    {
+        let mut st = self.state.lock().unwrap();
+
+        let idx = match st.pending.iter().position(|j| j.info.id == id) {
+            Some(i) => i,
+            None => return Err(NotifydError::new(format!("No pending job with id {}", id).as_str()))
+        };
+
+        let mut job = st.pending.remove(idx);
+
+        match priority
+        {
+            Some(p) => {
+                job.info.priority = p;
+                self.save(&job.info);
+                JobQueue::insert_sorted(&mut st.pending, job);
+                Ok(st.pending.iter().position(|j| j.info.id == id).unwrap() + 1)
+            },
+            None => {
+                /* Keep the queue sorted by lifting the job to the head priority */
+                if let Some(head) = st.pending.first()
+                {
+                    if head.info.priority > job.info.priority
+                    {
+                        job.info.priority = head.info.priority;
+                        self.save(&job.info);
+                    }
+                }
+                st.pending.insert(0, job);
+                Ok(1)
+            }
+        }
+    }
+
+    pub(crate) fn pending_count(self : &Self) -> usize
+    {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    /* Remove the newest pending job less urgent than priority, to make room for one of that priority.
+       Jobs asking to interrupt are left alone */
+    pub(crate) fn evict_lowest(self : &Self, priority : Priority) -> Option<Job>
+    {
+        let mut st = self.state.lock().unwrap();
+
+        let idx = st.pending.iter().rposition(|j| !j.info.params.interrupt && j.info.priority < priority)?;
+        let job = st.pending.remove(idx);
+        self.forget(job.info.id);
+
+        Some(job)
+    }
+
+    /* Attachments of the jobs still to be played, the running one included */
+    pub(crate) fn pending_audio(self : &Self) -> Vec<PathBuf>
+    {
+        let st = self.state.lock().unwrap();
+        st.pending.iter().map(|j| &j.info).chain(st.running.iter()).filter_map(|i| i.params.audio.clone()).collect()
+    }
+
+    pub(crate) fn pending_bytes(self : &Self) -> usize
+    {
+        let st = self.state.lock().unwrap();
+        st.pending.iter().map(|j| j.info.approx_bytes()).sum()
+    }
+
+    pub(crate) fn snapshot(self : &Self) -> QueueListing
+    {
+        let st = self.state.lock().unwrap();
+
+        let mut eta : u64 = 0;
+
+        let running = st.running.as_ref().map(|r| {
+            let elapsed = ((now_in_usecs() - r.started.unwrap_or(0)) / 1000) as u64;
+            eta = estimated_duration_ms(&r.text).saturating_sub(elapsed);
+            QueueEntry {
+                id : r.id,
+                position : 0,
+                priority : r.priority,
+                target : r.target.clone(),
+                text : r.text.clone(),
+                eta_ms : 0,
+                replace_key : r.params.replace_key.clone()
+            }
+        });
+
+        let mut pending = Vec::new();
+
+        for (i, j) in st.pending.iter().enumerate()
+        {
+            pending.push(QueueEntry {
+                id : j.info.id,
+                position : i + 1,
+                priority : j.info.priority,
+                target : j.info.target.clone(),
+                text : j.info.text.clone(),
+                eta_ms : eta,
+                replace_key : j.info.params.replace_key.clone()
+            });
+            eta += estimated_duration_ms(&j.info.text);
+        }
+
+        QueueListing {
+            running : running,
+            pending : pending
+        }
+    }
+}
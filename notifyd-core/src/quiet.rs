@@ -0,0 +1,145 @@
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use crate::error::NotifydError;
+use crate::queue::Priority;
+use crate::tts::SpeechParams;
+
+/***************
+ * QUIET HOURS *
+ ***************/
+
+/* What becomes of the notifications arriving during quiet hours */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietAction
+{
+    /* Kept until the quiet hours end, then played */
+    #[default]
+    Defer,
+    /* Only recorded in the history */
+    Suppress
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct QuietConfig
+{
+    /* Local times ("HH:MM") of the quiet hours, which may span midnight, disabled when absent */
+    pub(crate) start : Option<String>,
+    pub(crate) end : Option<String>,
+    /* Notifications of this priority and above still go through */
+    pub(crate) priority : Priority,
+    pub(crate) action : QuietAction
+}
+
+impl Default for QuietConfig
+{
+    fn default() -> Self
+    {
+        QuietConfig {
+            start : None,
+            end : None,
+            priority : Priority::High,
+            action : QuietAction::Defer
+        }
+    }
+}
+
+impl QuietConfig
+{
+    pub(crate) fn hours(self : &Self) -> Result<Option<QuietHours>, Box<dyn std::error::Error>>
+    {
+        let parse = |t : &String| chrono::NaiveTime::parse_from_str(t, "%H:%M")
+                                      .map_err(|e| NotifydError::new(format!("Bad quiet hours time '{}' : {}", t, e).as_str()));
+
+        match (&self.start, &self.end) {
+            (Some(s), Some(e)) => Ok(Some(QuietHours { start : parse(s)?, end : parse(e)? })),
+            (None, None) => Ok(None),
+            _ => Err(NotifydError::new("Quiet hours need both a start and an end"))
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct QuietHours
+{
+    pub(crate) start : chrono::NaiveTime,
+    pub(crate) end : chrono::NaiveTime
+}
+
+impl QuietHours
+{
+    pub(crate) fn contains(self : &Self, t : chrono::NaiveTime) -> bool
+    {
+        if self.start <= self.end
+        {
+            self.start <= t && t < self.end
+        }
+        else
+        {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+/* Manual override of the schedule */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DndMode
+{
+    /* Follow the configured quiet hours */
+    #[default]
+    Auto,
+    On,
+    Off
+}
+
+pub struct HeldNotification
+{
+    pub(crate) time : chrono::DateTime<chrono::Local>,
+    pub(crate) text : String,
+    pub(crate) params : SpeechParams,
+    pub(crate) target : String
+}
+
+pub struct DoNotDisturb
+{
+    pub(crate) mode : Mutex<DndMode>,
+    /* Deferred notifications, played once do-not-disturb ends */
+    pub(crate) held : Mutex<Vec<HeldNotification>>
+}
+
+impl DoNotDisturb
+{
+    pub(crate) fn new() -> DoNotDisturb
+    {
+        DoNotDisturb {
+            mode : Mutex::new(DndMode::Auto),
+            held : Mutex::new(Vec::new())
+        }
+    }
+
+    pub(crate) fn is_active(self : &Self, hours : Option<QuietHours>) -> bool
+    {
+        match *self.mode.lock().unwrap() {
+            DndMode::On => true,
+            DndMode::Off => false,
+            DndMode::Auto => hours.map(|h| h.contains(chrono::Local::now().time())).unwrap_or(false)
+        }
+    }
+
+    pub(crate) fn hold(self : &Self, text : &str, params : &SpeechParams, target : &str)
+    {
+        self.held.lock().unwrap().push(HeldNotification {
+            time : chrono::Local::now(),
+            text : text.to_string(),
+            params : params.clone(),
+            target : target.to_string()
+        });
+    }
+
+    pub(crate) fn release(self : &Self) -> Vec<HeldNotification>
+    {
+        std::mem::take(&mut *self.held.lock().unwrap())
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(unix)]
+use std::sync::Once;
+
+/**********
+ * RELOAD *
+ **********/
+
+/* Bumped on every SIGHUP, daemons reload when they see it change */
+static SIGHUP_COUNT : AtomicU64 = AtomicU64::new(0);
+#[cfg(unix)]
+static SIGHUP_HANDLER : Once = Once::new();
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_ : libc::c_int)
+{
+    SIGHUP_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+/* Catch SIGHUP instead of being terminated by it, once for the whole process */
+pub fn watch_sighup()
+{
+    #[cfg(unix)]
+    SIGHUP_HANDLER.call_once(|| unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as *const () as libc::sighandler_t);
+    });
+}
+
+pub fn sighup_count() -> u64
+{
+    SIGHUP_COUNT.load(Ordering::SeqCst)
+}
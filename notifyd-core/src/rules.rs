@@ -0,0 +1,117 @@
+use serde::{Serialize, Deserialize};
+use crate::error::NotifydError;
+use crate::queue::Priority;
+use crate::quiet::QuietHours;
+
+/*****************
+ * ROUTING RULES *
+ *****************/
+
+/* A [[rules]] section: the conditions it has must all hold, the first matching rule deciding */
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RuleConfig
+{
+    /* Shown in the logs and the history */
+    pub(crate) name : Option<String>,
+    /* Label of the token the notification came with, "admin" for the admin token */
+    pub(crate) source : Option<String>,
+    pub(crate) topic : Option<String>,
+    /* Notifications of this priority and above */
+    pub(crate) priority : Option<Priority>,
+    /* Regular expression searched in the text, e.g. "(?i)disk|cpu" */
+    pub(crate) text : Option<String>,
+    /* Local times ("HH:MM-HH:MM") of the day, which may span midnight */
+    pub(crate) hours : Option<String>,
+    /* Device, group or "local" to play on */
+    pub(crate) target : Option<String>,
+    pub(crate) volume : Option<f32>,
+    pub(crate) voice : Option<String>,
+    /* Only record the notification in the history */
+    pub(crate) suppress : bool
+}
+
+#[derive(Clone)]
+pub struct Rule
+{
+    pub(crate) config : RuleConfig,
+    pub(crate) text : Option<regex::Regex>,
+    pub(crate) hours : Option<QuietHours>
+}
+
+/* What a notification is checked against */
+pub struct RuleInput<'a>
+{
+    pub(crate) source : Option<&'a str>,
+    pub(crate) topic : Option<&'a str>,
+    pub(crate) priority : Priority,
+    pub(crate) text : &'a str,
+    pub(crate) time : chrono::NaiveTime
+}
+
+impl Rule
+{
+    pub(crate) fn new(index : usize, config : &RuleConfig) -> Result<Rule, Box<dyn std::error::Error>>
+    {
+        let name = config.name.clone().unwrap_or(format!("#{}", index + 1));
+
+        if config.volume.is_some_and(|v| !(0.0..=1.0).contains(&v))
+        {
+            return Err(NotifydError::new(format!("Rule {} : 'volume' must be between 0 and 1", name).as_str()));
+        }
+
+        let text = match &config.text {
+            Some(t) => Some(regex::Regex::new(t).map_err(|e| NotifydError::new(format!("Rule {} : bad 'text' expression : {}", name, e).as_str()))?),
+            None => None
+        };
+
+        let hours = match &config.hours {
+            Some(h) => {
+                let parse = |t : &str| chrono::NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                                          .map_err(|e| NotifydError::new(format!("Rule {} : bad 'hours' time '{}' : {}", name, t, e).as_str()));
+
+                match h.split_once('-') {
+                    Some((start, end)) => Some(QuietHours { start : parse(start)?, end : parse(end)? }),
+                    None => return Err(NotifydError::new(format!("Rule {} : 'hours' must look like \"09:00-18:00\"", name).as_str()))
+                }
+            },
+            None => None
+        };
+
+        Ok(Rule {
+            config : RuleConfig {
+                name : Some(name),
+                ..config.clone()
+            },
+            text : text,
+            hours : hours
+        })
+    }
+
+    pub(crate) fn name(self : &Self) -> &str
+    {
+        self.config.name.as_deref().unwrap_or_default()
+    }
+
+    pub(crate) fn matches(self : &Self, input : &RuleInput) -> bool
+    {
+        let c = &self.config;
+
+        (c.source.is_none() || c.source.as_deref() == input.source)
+            && (c.topic.is_none() || c.topic.as_deref() == input.topic)
+            && c.priority.is_none_or(|p| input.priority >= p)
+            && self.text.as_ref().is_none_or(|r| r.is_match(input.text))
+            && self.hours.is_none_or(|h| h.contains(input.time))
+    }
+}
+
+pub fn compile_rules(rules : &[RuleConfig]) -> Result<Vec<Rule>, Box<dyn std::error::Error>>
+{
+    rules.iter().enumerate().map(|(i, r)| Rule::new(i, r)).collect()
+}
+
+/* First rule the notification matches */
+pub fn first_match<'a>(rules : &'a [Rule], input : &RuleInput) -> Option<&'a Rule>
+{
+    rules.iter().find(|r| r.matches(input))
+}
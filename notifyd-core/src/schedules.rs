@@ -0,0 +1,297 @@
+use chrono::{Datelike, Timelike};
+use serde::Serialize;
+use crate::error::NotifydError;
+use crate::now_in_usecs;
+use crate::store::Store;
+use crate::tts::SpeechParams;
+
+/********************
+ * CRON EXPRESSIONS *
+ ********************/
+
+pub const MONTH_NAMES : &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+pub const WEEKDAY_NAMES : &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/* The usual five fields (minute, hour, day of month, month, day of week), one bit per allowed value */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CronExpr
+{
+    pub(crate) minutes : u64,
+    pub(crate) hours : u64,
+    pub(crate) days : u64,
+    pub(crate) months : u64,
+    pub(crate) weekdays : u64,
+    /* As in cron, a day matches either field when both are restricted */
+    pub(crate) any_day : bool,
+    pub(crate) any_weekday : bool
+}
+
+impl CronExpr
+{
+    pub(crate) fn parse(expr : &str) -> Result<CronExpr, Box<dyn std::error::Error>>
+    {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            e => e
+        };
+
+        let fields : Vec<&str> = expr.split_whitespace().collect();
+
+        if fields.len() != 5
+        {
+            return Err(NotifydError::new(format!("'{}' does not have the 5 fields minute, hour, day of month, month and day of week", expr).as_str()));
+        }
+
+        let mut weekdays = CronExpr::parse_field(fields[4], 0, 7, WEEKDAY_NAMES, 0)?;
+
+        /* Both 0 and 7 stand for Sunday */
+        if weekdays & (1 << 7) != 0
+        {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(CronExpr {
+            minutes : CronExpr::parse_field(fields[0], 0, 59, &[], 0)?,
+            hours : CronExpr::parse_field(fields[1], 0, 23, &[], 0)?,
+            days : CronExpr::parse_field(fields[2], 1, 31, &[], 0)?,
+            months : CronExpr::parse_field(fields[3], 1, 12, MONTH_NAMES, 1)?,
+            weekdays : weekdays,
+            any_day : fields[2] == "*",
+            any_weekday : fields[4] == "*"
+        })
+    }
+
+    /* Lists of values, ranges and steps such as "1-5", "0-59/15", "mon,wed,fri" */
+    pub(crate) fn parse_field(field : &str, min : u32, max : u32, names : &[&str], first_name : u32) -> Result<u64, Box<dyn std::error::Error>>
+    {
+        let bad = || NotifydError::new(format!("Bad cron field '{}'", field).as_str());
+
+        let value = |s : &str| -> Option<u32> {
+            match s.parse::<u32>() {
+                Ok(v) => Some(v),
+                Err(_) => names.iter().position(|n| n.eq_ignore_ascii_case(s)).map(|i| i as u32 + first_name)
+            }
+        };
+
+        let mut mask = 0u64;
+
+        for part in field.split(',')
+        {
+            let (range, step) = match part.split_once('/') {
+                Some((r, s)) => (r, s.parse::<usize>().map_err(|_| bad())?),
+                None => (part, 1)
+            };
+
+            if step == 0
+            {
+                return Err(bad());
+            }
+
+            let (lo, hi) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((a, b)) => (value(a).ok_or_else(bad)?, value(b).ok_or_else(bad)?),
+                /* "5/10" runs from 5 to the end */
+                None => {
+                    let v = value(range).ok_or_else(bad)?;
+                    (v, if step > 1 { max } else { v })
+                }
+            };
+
+            if lo < min || hi > max || lo > hi
+            {
+                return Err(NotifydError::new(format!("Cron field '{}' must stay within {}-{}", field, min, max).as_str()));
+            }
+
+            for v in (lo..=hi).step_by(step)
+            {
+                mask |= 1 << v;
+            }
+        }
+
+        Ok(mask)
+    }
+
+    pub(crate) fn matches_day(self : &Self, date : chrono::NaiveDate) -> bool
+    {
+        if self.months & (1 << date.month()) == 0
+        {
+            return false;
+        }
+
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday
+        }
+    }
+
+    pub(crate) fn matches(self : &Self, t : &chrono::DateTime<chrono::Local>) -> bool
+    {
+        self.matches_day(t.date_naive())
+            && self.hours & (1 << t.hour()) != 0
+            && self.minutes & (1 << t.minute()) != 0
+    }
+
+    /* First matching minute after t, looking up to four years ahead for February 29th */
+    pub(crate) fn next_after(self : &Self, t : &chrono::DateTime<chrono::Local>) -> Option<chrono::DateTime<chrono::Local>>
+    {
+        let mut day = t.date_naive();
+
+        for _ in 0..(4 * 366)
+        {
+            if self.matches_day(day)
+            {
+                for h in (0..24).filter(|h| self.hours & (1 << h) != 0)
+                {
+                    for m in (0..60).filter(|m| self.minutes & (1 << m) != 0)
+                    {
+                        let candidate = day.and_hms_opt(h, m, 0)
+                                           .and_then(|d| d.and_local_timezone(chrono::Local).earliest());
+
+                        match candidate {
+                            Some(c) if c > *t => return Some(c),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
+            day = day.succ_opt()?;
+        }
+
+        None
+    }
+}
+
+/***********************
+ * RECURRING SCHEDULES *
+ ***********************/
+
+#[derive(Serialize, Clone)]
+pub struct Schedule
+{
+    pub(crate) id : i64,
+    pub(crate) cron : String,
+    pub(crate) text : String,
+    /* The default target when absent */
+    pub(crate) target : Option<String>,
+    pub(crate) params : SpeechParams,
+    pub(crate) enabled : bool,
+    pub(crate) created : i64,
+    /* Unix time (seconds) of the last announcement */
+    pub(crate) last_run : Option<i64>,
+    /* Next announcement, for listings */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) next : Option<String>
+}
+
+impl Schedule
+{
+    pub(crate) fn from_row(row : &rusqlite::Row) -> rusqlite::Result<Schedule>
+    {
+        let params : String = row.get(4)?;
+
+        Ok(Schedule {
+            id : row.get(0)?,
+            cron : row.get(1)?,
+            text : row.get(2)?,
+            target : row.get(3)?,
+            params : serde_json::from_str(&params).unwrap_or_default(),
+            enabled : row.get(5)?,
+            created : row.get(6)?,
+            last_run : row.get(7)?,
+            next : None
+        })
+    }
+
+    pub(crate) fn with_next(mut self) -> Schedule
+    {
+        self.next = match (self.enabled, CronExpr::parse(&self.cron)) {
+            (true, Ok(c)) => c.next_after(&chrono::Local::now()).map(|n| n.to_rfc3339()),
+            _ => None
+        };
+        self
+    }
+}
+
+/* Instances sharing a database each run their own schedules, None being the main one */
+impl Store
+{
+    pub(crate) fn schedules(self : &Self, instance : &Option<String>) -> Result<Vec<Schedule>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, cron, text, target, params, enabled, created, last_run FROM schedules WHERE instance IS ?1 ORDER BY id")?;
+        let rows = stmt.query_map([instance], Schedule::from_row)?;
+
+        let mut ret = Vec::new();
+        for s in rows
+        {
+            ret.push(s?);
+        }
+
+        Ok(ret)
+    }
+
+    pub(crate) fn schedule(self : &Self, instance : &Option<String>, id : i64) -> Result<Option<Schedule>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, cron, text, target, params, enabled, created, last_run FROM schedules WHERE instance IS ?1 AND id = ?2")?;
+        let mut rows = stmt.query_map(rusqlite::params![instance, id], Schedule::from_row)?;
+
+        match rows.next() {
+            Some(s) => Ok(Some(s?)),
+            None => Ok(None)
+        }
+    }
+
+    pub(crate) fn create_schedule(self : &Self, instance : &Option<String>, cron : &str, text : &str, target : Option<String>, params : SpeechParams, enabled : bool) -> Result<Schedule, Box<dyn std::error::Error>>
+    {
+        let now = (now_in_usecs() / 1_000_000) as i64;
+
+        let id = {
+            let conn = self.conn.lock().unwrap();
+            conn.execute("INSERT INTO schedules (cron, text, target, params, enabled, created, instance) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                         rusqlite::params![cron, text, target, serde_json::to_string(&params)?, enabled, now, instance])?;
+            conn.last_insert_rowid()
+        };
+
+        Ok(Schedule {
+            id : id,
+            cron : cron.to_string(),
+            text : text.to_string(),
+            target : target,
+            params : params,
+            enabled : enabled,
+            created : now,
+            last_run : None,
+            next : None
+        })
+    }
+
+    pub(crate) fn update_schedule(self : &Self, schedule : &Schedule) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.conn.lock().unwrap().execute("UPDATE schedules SET cron = ?1, text = ?2, target = ?3, params = ?4, enabled = ?5 WHERE id = ?6",
+                                          rusqlite::params![schedule.cron, schedule.text, schedule.target,
+                                                            serde_json::to_string(&schedule.params)?, schedule.enabled, schedule.id])?;
+        Ok(())
+    }
+
+    pub(crate) fn mark_schedule_run(self : &Self, id : i64, time : i64) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.conn.lock().unwrap().execute("UPDATE schedules SET last_run = ?1 WHERE id = ?2", rusqlite::params![time, id])?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_schedule(self : &Self, instance : &Option<String>, id : i64) -> Result<bool, Box<dyn std::error::Error>>
+    {
+        Ok(self.conn.lock().unwrap().execute("DELETE FROM schedules WHERE instance IS ?1 AND id = ?2", rusqlite::params![instance, id])? > 0)
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::Once;
+use std::time::Duration;
+
+/************
+ * SHUTDOWN *
+ ************/
+
+/* How often the servers look for a shutdown request */
+pub const SHUTDOWN_POLL : Duration = Duration::from_millis(100);
+
+/* Raised by SIGTERM or SIGINT, daemons drain their queue and exit when they see it */
+static SHUTDOWN_REQUESTED : AtomicBool = AtomicBool::new(false);
+#[cfg(unix)]
+static SHUTDOWN_HANDLER : Once = Once::new();
+
+#[cfg(unix)]
+extern "C" fn on_terminate(_ : libc::c_int)
+{
+    /* A second signal means the drain is not worth waiting for */
+    if SHUTDOWN_REQUESTED.swap(true, Ordering::SeqCst)
+    {
+        unsafe {
+            libc::_exit(1);
+        }
+    }
+}
+
+/* Catch SIGTERM and SIGINT instead of being terminated by them, once for the whole process */
+pub fn watch_shutdown()
+{
+    #[cfg(unix)]
+    SHUTDOWN_HANDLER.call_once(|| unsafe {
+        libc::signal(libc::SIGTERM, on_terminate as *const () as libc::sighandler_t);
+        libc::signal(libc::SIGINT, on_terminate as *const () as libc::sighandler_t);
+    });
+}
+
+pub fn shutdown_requested() -> bool
+{
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,450 @@
+use std::path::PathBuf;
+use which::which;
+use std::process::{Command, Stdio};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use std::io::Write;
+use std::time::Duration;
+use std::sync::atomic::Ordering;
+use tracing::warn;
+use crate::audio::AudioOutput;
+use crate::caster::Caster;
+use crate::error::NotifydError;
+use crate::notifyd::Notifyd;
+use crate::queue::{JobFailure, JobInfo, JobOutcome, JobSuccess};
+use crate::snapcast::SnapcastConfig;
+use crate::sonos::SonosTarget;
+
+/*********
+ * SINKS *
+ *********/
+
+/* Somewhere notifications are delivered, picked from the target of each job */
+pub trait Sink : Send + Sync
+{
+    fn deliver(self : &Self, notifyd : &Notifyd, job : &JobInfo) -> JobOutcome;
+    /* Whether notifications can currently be delivered */
+    fn check(self : &Self, notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>;
+
+    /* Level of the target, from 0.0 to 1.0 */
+    fn volume(self : &Self, _notifyd : &Notifyd) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        Err(NotifydError::new("This target has no volume"))
+    }
+
+    fn set_volume(self : &Self, _notifyd : &Notifyd, _level : f32, _ramp : Duration) -> Result<(), Box<dyn std::error::Error>>
+    {
+        Err(NotifydError::new("This target has no volume"))
+    }
+}
+
+pub struct LocalSpeakerSink;
+
+impl Sink for LocalSpeakerSink
+{
+    fn deliver(self : &Self, notifyd : &Notifyd, job : &JobInfo) -> JobOutcome
+    {
+        notifyd.do_tts(job.text.clone(), &job.params, job.priority)
+    }
+
+    fn check(self : &Self, notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>
+    {
+        if notifyd.sound.lock().unwrap().is_none()
+        {
+            notifyd.reset_audio();
+        }
+
+        match *notifyd.sound.lock().unwrap() {
+            Some(_) => Ok(()),
+            None => Err(NotifydError::new("No audio output"))
+        }
+    }
+
+    fn volume(self : &Self, notifyd : &Notifyd) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        if !AudioOutput::has_volume(&notifyd.player)
+        {
+            return Err(NotifydError::new("The external player has no volume control"));
+        }
+
+        Ok(notifyd.level.get().0)
+    }
+
+    /* Applied by the playback itself, which may be holding the output */
+    fn set_volume(self : &Self, notifyd : &Notifyd, level : f32, ramp : Duration) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.volume(notifyd)?;
+        notifyd.level.set(level, ramp);
+        Ok(())
+    }
+}
+
+pub struct CastSink
+{
+    pub(crate) uid : String,
+    /* Play on the local speaker when the device cannot be cast to */
+    pub(crate) fallback : bool
+}
+
+impl Sink for CastSink
+{
+    fn deliver(self : &Self, notifyd : &Notifyd, job : &JobInfo) -> JobOutcome
+    {
+        let failure = match notifyd.do_bcast(job.text.clone(), &job.params, self.uid.clone()) {
+            Ok(s) => return Ok(s),
+            Err(f) => f
+        };
+
+        /* Stopped or interrupted on purpose, not a failure of the device */
+        if !self.fallback || notifyd.queue.preempted.load(Ordering::SeqCst)
+        {
+            return Err(failure);
+        }
+
+        warn!("Failed to cast to {} ({} : {}), playing on the local speaker", self.uid, failure.reason, failure.err);
+
+        match LocalSpeakerSink.deliver(notifyd, job) {
+            Ok(mut s) => {
+                s.reason = format!("Failed to cast to {} ({}), played on the local speaker instead", self.uid, failure.err);
+                if let Some(info) = s.info.as_mut()
+                {
+                    info.fallback_from = Some(self.uid.clone());
+                }
+                Ok(s)
+            },
+            Err(local) => {
+                Err(JobFailure::new(&failure.reason, format!("{}, and the local speaker failed too : {} ({})", failure.err, local.reason, local.err)).with_status(failure.status))
+            }
+        }
+    }
+
+    fn check(self : &Self, _notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>
+    {
+        match Caster::is_reachable(&self.uid)? {
+            true => Ok(()),
+            false => Err(NotifydError::new("Device not discovered on the network"))
+        }
+    }
+
+    fn volume(self : &Self, _notifyd : &Notifyd) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        Caster::volume_of(&self.uid)
+    }
+
+    fn set_volume(self : &Self, _notifyd : &Notifyd, level : f32, ramp : Duration) -> Result<(), Box<dyn std::error::Error>>
+    {
+        Caster::set_volume_of(&self.uid, level, ramp)
+    }
+}
+
+/* A registry device of the "airplay" kind, streamed to by an external RAOP sender */
+pub struct AirPlaySink
+{
+    pub(crate) name : String,
+    pub(crate) host : Option<String>
+}
+
+impl AirPlaySink
+{
+    pub(crate) fn host(self : &Self) -> Result<&str, Box<dyn std::error::Error>>
+    {
+        match &self.host {
+            Some(h) => Ok(h),
+            None => Err(NotifydError::new(format!("AirPlay device {} has no IP address in the registry", self.name).as_str()))
+        }
+    }
+}
+
+impl Sink for AirPlaySink
+{
+    fn deliver(self : &Self, notifyd : &Notifyd, job : &JobInfo) -> JobOutcome
+    {
+        match self.host() {
+            Ok(h) => notifyd.do_airplay(job.text.clone(), &job.params, &self.name, h),
+            Err(e) => Err(JobFailure::new("Failed to play over AirPlay", e))
+        }
+    }
+
+    fn check(self : &Self, notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.host()?;
+        notifyd.settings().airplay.check()
+    }
+}
+
+/* Every room running snapclient, in sync, through the pipe source of snapserver */
+pub struct SnapcastSink
+{
+    pub(crate) config : Option<SnapcastConfig>
+}
+
+impl SnapcastSink
+{
+    pub(crate) fn config(self : &Self) -> Result<&SnapcastConfig, Box<dyn std::error::Error>>
+    {
+        self.config.as_ref().ok_or(NotifydError::new("Snapcast is not configured, see the [snapcast] section"))
+    }
+}
+
+impl Sink for SnapcastSink
+{
+    fn deliver(self : &Self, notifyd : &Notifyd, job : &JobInfo) -> JobOutcome
+    {
+        match self.config() {
+            Ok(c) => notifyd.do_snapcast(job.text.clone(), &job.params, c),
+            Err(e) => Err(JobFailure::new("Failed to play on the snapcast clients", e))
+        }
+    }
+
+    fn check(self : &Self, _notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let config = self.config()?;
+
+        match TerminalSink::is_pipe(&config.fifo)? {
+            true => Ok(()),
+            false => Err(NotifydError::new(format!("{} is not a named pipe", config.fifo.to_string_lossy()).as_str()))
+        }
+    }
+}
+
+/* A Sonos zone, what it was playing being resumed after the notification */
+pub struct SonosSink
+{
+    pub(crate) zone : String,
+    pub(crate) host : String
+}
+
+impl Sink for SonosSink
+{
+    fn deliver(self : &Self, notifyd : &Notifyd, job : &JobInfo) -> JobOutcome
+    {
+        notifyd.do_sonos(job.text.clone(), &job.params, &self.zone, &self.host)
+    }
+
+    fn check(self : &Self, _notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>
+    {
+        SonosTarget::new(&self.host).transport_state().map(|_| ())
+    }
+
+    fn volume(self : &Self, _notifyd : &Notifyd) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        SonosTarget::new(&self.host).volume()
+    }
+
+    fn set_volume(self : &Self, _notifyd : &Notifyd, level : f32, ramp : Duration) -> Result<(), Box<dyn std::error::Error>>
+    {
+        SonosTarget::new(&self.host).set_volume(level, ramp)?;
+        Ok(())
+    }
+}
+
+/* Every member of a group at once, the group failing only when no member made it */
+pub struct GroupSink
+{
+    pub(crate) name : String,
+    pub(crate) members : Vec<(String, Box<dyn Sink>)>
+}
+
+impl Sink for GroupSink
+{
+    fn deliver(self : &Self, notifyd : &Notifyd, job : &JobInfo) -> JobOutcome
+    {
+        let outcomes : Vec<(String, JobOutcome)> = std::thread::scope(|scope| {
+            let running : Vec<_> = self.members.iter()
+                                       .map(|(name, sink)| (name, scope.spawn(move || sink.deliver(notifyd, job))))
+                                       .collect();
+
+            running.into_iter()
+                   .map(|(name, h)| (name.clone(), h.join().unwrap_or_else(|_| Err(JobFailure::new("Delivery panicked", name)))))
+                   .collect()
+        });
+
+        let failures : Vec<String> = outcomes.iter()
+                                             .filter_map(|(name, o)| o.as_ref().err().map(|f| format!("{} : {} ({})", name, f.reason, f.err)))
+                                             .collect();
+        let total = outcomes.len();
+
+        match outcomes.into_iter().find_map(|(_, o)| o.ok()) {
+            Some(mut success) => {
+                success.reason = if failures.is_empty() {
+                    format!("Delivered to the {} members of {}", total, self.name)
+                } else {
+                    format!("Delivered to {} of the {} members of {}, failed on {}", total - failures.len(), total, self.name, failures.join(", "))
+                };
+                Ok(success)
+            },
+            None => Err(JobFailure::new(format!("Failed to deliver to group {}", self.name).as_str(), failures.join(", ")))
+        }
+    }
+
+    fn check(self : &Self, notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let failures : Vec<String> = self.members.iter()
+                                         .filter_map(|(name, sink)| sink.check(notifyd).err().map(|e| format!("{} : {}", name, e)))
+                                         .collect();
+
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(NotifydError::new(failures.join(", ").as_str()))
+        }
+    }
+
+    fn set_volume(self : &Self, notifyd : &Notifyd, level : f32, ramp : Duration) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let failures : Vec<String> = std::thread::scope(|scope| {
+            let running : Vec<_> = self.members.iter()
+                                       .map(|(name, sink)| (name, scope.spawn(move || sink.set_volume(notifyd, level, ramp).map_err(|e| e.to_string()))))
+                                       .collect();
+
+            running.into_iter()
+                   .filter_map(|(name, h)| match h.join() {
+                       Ok(Ok(())) => None,
+                       Ok(Err(e)) => Some(format!("{} : {}", name, e)),
+                       Err(_) => Some(format!("{} : panicked", name))
+                   })
+                   .collect()
+        });
+
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(NotifydError::new(failures.join(", ").as_str()))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalMode
+{
+    /* Broadcast to the terminals of logged-in users with wall(1) */
+    Wall,
+    /* Write one line per notification to a named pipe */
+    Pipe
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TerminalConfig
+{
+    pub(crate) mode : TerminalMode,
+    pub(crate) pipe : PathBuf
+}
+
+impl Default for TerminalConfig
+{
+    fn default() -> Self
+    {
+        TerminalConfig {
+            mode : TerminalMode::Wall,
+            pipe : PathBuf::from("/run/notifyd/notifications")
+        }
+    }
+}
+
+/* Text only sink for headless hosts, selected with the "terminal" target */
+pub struct TerminalSink
+{
+    pub(crate) config : TerminalConfig
+}
+
+impl Sink for TerminalSink
+{
+    fn deliver(self : &Self, _notifyd : &Notifyd, job : &JobInfo) -> JobOutcome
+    {
+        let line = format!("[notifyd {}] {}\n", chrono::Local::now().format("%H:%M"), job.text);
+
+        let ret = match self.config.mode {
+            TerminalMode::Wall => TerminalSink::wall(&line),
+            TerminalMode::Pipe => TerminalSink::write_pipe(&self.config.pipe, &line)
+        };
+
+        match ret {
+            Ok(()) => Ok(JobSuccess::without_audio("Written to terminals")),
+            Err(e) => Err(JobFailure::new("Failed to write to terminals", e))
+        }
+    }
+
+    fn check(self : &Self, _notifyd : &Notifyd) -> Result<(), Box<dyn std::error::Error>>
+    {
+        match self.config.mode {
+            TerminalMode::Wall => {
+                which("wall")?;
+                Ok(())
+            },
+            TerminalMode::Pipe => {
+                if TerminalSink::is_pipe(&self.config.pipe)?
+                {
+                    Ok(())
+                }
+                else
+                {
+                    Err(NotifydError::new(format!("{} is not a named pipe", self.config.pipe.to_string_lossy()).as_str()))
+                }
+            }
+        }
+    }
+}
+
+impl TerminalSink
+{
+    pub(crate) fn wall(line : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut child = Command::new("wall")
+                            .stdin(Stdio::piped())
+                            .stdout(Stdio::null())
+                            .spawn()?;
+
+        let mut stdin = child.stdin.take().expect("Failed to open stdin");
+        stdin.write_all(line.as_bytes())?;
+        drop(stdin);
+
+        let status = child.wait()?;
+
+        if !status.success()
+        {
+            return Err(NotifydError::new(format!("wall exited with {}", status).as_str()));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn is_pipe(path : &Path) -> Result<bool, Box<dyn std::error::Error>>
+    {
+        use std::os::unix::fs::FileTypeExt;
+        Ok(std::fs::metadata(path)?.file_type().is_fifo())
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn is_pipe(_path : &Path) -> Result<bool, Box<dyn std::error::Error>>
+    {
+        Err(NotifydError::new("Named pipes are only supported on Unix"))
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn write_pipe(_path : &Path, _line : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        Err(NotifydError::new("Named pipes are only supported on Unix"))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn write_pipe(path : &Path, line : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        /* Non blocking so that a pipe nobody reads fails instead of stalling the queue */
+        let mut pipe = match std::fs::OpenOptions::new()
+                                .write(true)
+                                .custom_flags(libc::O_NONBLOCK)
+                                .open(path) {
+            Ok(p) => p,
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                return Err(NotifydError::new(format!("Nobody is reading {}", path.to_string_lossy()).as_str()));
+            },
+            Err(e) => return Err(Box::new(e))
+        };
+
+        pipe.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::audio::PcmAudio;
+use crate::cloud::{curl, curl_option, expect_success};
+use crate::error::{NotifydError, PlaybackInterrupted};
+
+/************
+ * SNAPCAST *
+ ************/
+
+/* Audio written to the pipe at once, small enough to stop right away */
+pub const SNAPCAST_CHUNK_MS : u64 = 20;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SnapcastConfig
+{
+    /* Pipe source of snapserver, e.g. source = pipe:///tmp/snapfifo?name=notifyd */
+    pub(crate) fifo : PathBuf,
+    /* Sample format of that source */
+    pub(crate) sample_rate : u32,
+    pub(crate) channels : u16,
+    /* Time the clients take to play what was written, the "buffer" of snapserver */
+    pub(crate) latency_ms : u64,
+    /* HTTP address of snapserver (e.g. "http://127.0.0.1:1780") to switch every group to the stream while announcing */
+    pub(crate) server : Option<String>,
+    /* Name of the pipe source, the groups being switched to it */
+    pub(crate) stream : String
+}
+
+impl Default for SnapcastConfig
+{
+    fn default() -> Self
+    {
+        SnapcastConfig {
+            fifo : PathBuf::from("/tmp/snapfifo"),
+            sample_rate : 48000,
+            channels : 2,
+            latency_ms : 1000,
+            server : None,
+            stream : String::from("notifyd")
+        }
+    }
+}
+
+impl SnapcastConfig
+{
+    /* Result of a JSON-RPC call to snapserver */
+    pub(crate) fn rpc(self : &Self, server : &str, method : &str, params : serde_json::Value) -> Result<serde_json::Value, Box<dyn std::error::Error>>
+    {
+        let body = serde_json::json!({ "id" : 1, "jsonrpc" : "2.0", "method" : method, "params" : params });
+        let options = [curl_option("header", "Content-Type: application/json")];
+
+        let (code, content) = curl(&format!("{}/jsonrpc", server.trim_end_matches('/')), &options, Some(body.to_string().as_bytes()))?;
+        let answer : serde_json::Value = serde_json::from_slice(&expect_success("Snapserver", code, content)?)?;
+
+        if !answer["error"].is_null()
+        {
+            return Err(NotifydError::new(format!("Snapserver refused {} : {}", method, answer["error"]["message"]).as_str()));
+        }
+
+        Ok(answer["result"].clone())
+    }
+
+    /* Switch every group to the stream, returning the (group, stream) pairs to go back to */
+    pub(crate) fn switch_groups(self : &Self) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>>
+    {
+        let server = match &self.server {
+            Some(s) => s,
+            None => return Ok(Vec::new())
+        };
+
+        let status = self.rpc(server, "Server.GetStatus", serde_json::json!({}))?;
+        let mut previous = Vec::new();
+
+        for g in status["server"]["groups"].as_array().cloned().unwrap_or_default()
+        {
+            let (id, stream) = (g["id"].as_str().unwrap_or_default(), g["stream_id"].as_str().unwrap_or_default());
+
+            if stream != self.stream
+            {
+                self.rpc(server, "Group.SetStream", serde_json::json!({ "id" : id, "stream_id" : self.stream }))?;
+                previous.push((id.to_string(), stream.to_string()));
+            }
+        }
+
+        Ok(previous)
+    }
+
+    pub(crate) fn restore_groups(self : &Self, previous : &[(String, String)])
+    {
+        if let Some(server) = &self.server
+        {
+            for (id, stream) in previous
+            {
+                if let Err(e) = self.rpc(server, "Group.SetStream", serde_json::json!({ "id" : id, "stream_id" : stream }))
+                {
+                    warn!("Failed to switch the snapcast group {} back to {} : {}", id, stream, e);
+                }
+            }
+        }
+    }
+
+    /* Write the audio to the pipe as snapserver reads it, then wait for the clients to play it */
+    pub(crate) fn play(self : &Self, audio : &PcmAudio, interrupt : &AtomicBool, paused : &AtomicBool) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let pcm = audio.convert(self.sample_rate, self.channels).pcm_bytes();
+
+        /* Fails right away rather than blocking when snapserver is not reading */
+        let mut fifo = {
+            let mut options = OpenOptions::new();
+            options.write(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.custom_flags(libc::O_NONBLOCK);
+            }
+            options.open(&self.fifo)
+                   .map_err(|e| NotifydError::new(format!("Failed to open {} (is snapserver running ?) : {}", self.fifo.to_string_lossy(), e).as_str()))?
+        };
+
+        let chunk = (self.sample_rate as u64 * SNAPCAST_CHUNK_MS / 1000) as usize * self.channels as usize * 2;
+
+        for data in pcm.chunks(chunk.max(2))
+        {
+            let mut written = 0;
+
+            while written < data.len()
+            {
+                if interrupt.load(Ordering::SeqCst)
+                {
+                    return Err(Box::new(PlaybackInterrupted));
+                }
+
+                /* The clients play silence meanwhile */
+                if paused.load(Ordering::SeqCst)
+                {
+                    std::thread::sleep(Duration::from_millis(SNAPCAST_CHUNK_MS));
+                    continue;
+                }
+
+                match fifo.write(&data[written..]) {
+                    Ok(n) => written += n,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(Duration::from_millis(5)),
+                    Err(e) => return Err(Box::new(e))
+                }
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(self.latency_ms));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use crate::audio::PauseClock;
+use crate::caster::VOLUME_STEP;
+use crate::cloud::{curl, curl_option};
+use crate::error::NotifydError;
+
+/******************
+ * SONOS SPEAKERS *
+ ******************/
+
+/* UPnP port of the speakers */
+pub const SONOS_PORT : u16 = 1400;
+
+/* Interval between two checks of the transport state */
+pub const SONOS_POLL : Duration = Duration::from_millis(300);
+
+/* Time given to a speaker beyond the length of the announcement */
+pub const SONOS_MARGIN : Duration = Duration::from_secs(30);
+
+pub const AV_TRANSPORT : (&str, &str) = ("AVTransport", "/MediaRenderer/AVTransport/Control");
+pub const RENDERING_CONTROL : (&str, &str) = ("RenderingControl", "/MediaRenderer/RenderingControl/Control");
+
+pub(crate) fn xml_escape(s : &str) -> String
+{
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub(crate) fn xml_unescape(s : &str) -> String
+{
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/* Text of the first <tag> of an answer, unescaped */
+pub(crate) fn xml_value(xml : &str, tag : &str) -> Option<String>
+{
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", tag))?;
+
+    Some(xml_unescape(&xml[start..start + end]))
+}
+
+/* What the speaker was doing before an announcement */
+pub struct SonosSnapshot
+{
+    pub(crate) uri : String,
+    pub(crate) metadata : String,
+    /* Position in the queue, from 1 */
+    pub(crate) track : u32,
+    pub(crate) position : String,
+    pub(crate) playing : bool,
+    pub(crate) volume : f32
+}
+
+/* A zone player, driven over UPnP/SOAP */
+pub struct SonosTarget
+{
+    /* Host, with the port when not the default one */
+    pub(crate) host : String
+}
+
+impl SonosTarget
+{
+    pub(crate) fn new(host : &str) -> SonosTarget
+    {
+        SonosTarget {
+            host : if host.contains(':') { host.to_string() } else { format!("{}:{}", host, SONOS_PORT) }
+        }
+    }
+
+    /* Answer of a SOAP action, errors being reported as such */
+    pub(crate) fn call(self : &Self, service : (&str, &str), action : &str, args : &[(&str, &str)]) -> Result<String, Box<dyn std::error::Error>>
+    {
+        let (name, path) = service;
+        let urn = format!("urn:schemas-upnp-org:service:{}:1", name);
+
+        let args : String = args.iter().map(|(k, v)| format!("<{}>{}</{}>", k, xml_escape(v), k)).collect();
+        let body = format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+                            <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+                            <s:Body><u:{} xmlns:u=\"{}\">{}</u:{}></s:Body></s:Envelope>", action, urn, args, action);
+
+        let options = [
+            curl_option("header", "Content-Type: text/xml; charset=\"utf-8\""),
+            curl_option("header", format!("SOAPACTION: \"{}#{}\"", urn, action).as_str())
+        ];
+
+        let (code, content) = curl(&format!("http://{}{}", self.host, path), &options, Some(body.as_bytes()))?;
+        let answer = String::from_utf8_lossy(&content).to_string();
+
+        if !(200..300).contains(&code)
+        {
+            let upnp = xml_value(&answer, "errorCode").unwrap_or_default();
+            return Err(NotifydError::new(format!("Sonos speaker {} refused {} (HTTP {}, UPnP error {})", self.host, action, code, upnp).as_str()));
+        }
+
+        Ok(answer)
+    }
+
+    /* "PLAYING", "PAUSED_PLAYBACK", "STOPPED" or "TRANSITIONING" */
+    pub(crate) fn transport_state(self : &Self) -> Result<String, Box<dyn std::error::Error>>
+    {
+        let answer = self.call(AV_TRANSPORT, "GetTransportInfo", &[("InstanceID", "0")])?;
+
+        match xml_value(&answer, "CurrentTransportState") {
+            Some(s) => Ok(s),
+            None => Err(NotifydError::new(format!("Sonos speaker {} did not report its state", self.host).as_str()))
+        }
+    }
+
+    /* Level of the speaker, from 0.0 to 1.0 */
+    pub(crate) fn volume(self : &Self) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        let answer = self.call(RENDERING_CONTROL, "GetVolume", &[("InstanceID", "0"), ("Channel", "Master")])?;
+
+        match xml_value(&answer, "CurrentVolume").and_then(|v| v.parse::<f32>().ok()) {
+            Some(v) => Ok(v / 100.0),
+            None => Err(NotifydError::new(format!("Sonos speaker {} did not report its volume", self.host).as_str()))
+        }
+    }
+
+    /* Bring the speaker to level over ramp, returning the level it was at */
+    pub(crate) fn set_volume(self : &Self, level : f32, ramp : Duration) -> Result<f32, Box<dyn std::error::Error>>
+    {
+        let from = self.volume()?;
+
+        if (from - level).abs() < 0.005
+        {
+            return Ok(from);
+        }
+
+        let steps = (ramp.as_millis() / VOLUME_STEP.as_millis()).max(1) as u32;
+
+        for i in 1..=steps
+        {
+            let v = ((from + (level - from) * i as f32 / steps as f32) * 100.0).round().clamp(0.0, 100.0);
+            self.call(RENDERING_CONTROL, "SetVolume", &[("InstanceID", "0"), ("Channel", "Master"), ("DesiredVolume", &v.to_string())])?;
+
+            if i < steps
+            {
+                std::thread::sleep(VOLUME_STEP);
+            }
+        }
+
+        Ok(from)
+    }
+
+    pub(crate) fn command(self : &Self, action : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        match action {
+            "Play" => self.call(AV_TRANSPORT, action, &[("InstanceID", "0"), ("Speed", "1")])?,
+            _ => self.call(AV_TRANSPORT, action, &[("InstanceID", "0")])?
+        };
+
+        Ok(())
+    }
+
+    pub(crate) fn snapshot(self : &Self) -> Result<SonosSnapshot, Box<dyn std::error::Error>>
+    {
+        let media = self.call(AV_TRANSPORT, "GetMediaInfo", &[("InstanceID", "0")])?;
+        let position = self.call(AV_TRANSPORT, "GetPositionInfo", &[("InstanceID", "0")])?;
+
+        Ok(SonosSnapshot {
+            uri : xml_value(&media, "CurrentURI").unwrap_or_default(),
+            metadata : xml_value(&media, "CurrentURIMetaData").unwrap_or_default(),
+            track : xml_value(&position, "Track").and_then(|t| t.parse().ok()).unwrap_or(1),
+            position : xml_value(&position, "RelTime").unwrap_or(String::from("0:00:00")),
+            playing : self.transport_state()? == "PLAYING",
+            volume : self.volume()?
+        })
+    }
+
+    /* Put back what was playing, at the same place of the queue */
+    pub(crate) fn restore(self : &Self, snapshot : &SonosSnapshot, ramp : Duration) -> Result<(), Box<dyn std::error::Error>>
+    {
+        if !snapshot.uri.is_empty()
+        {
+            self.call(AV_TRANSPORT, "SetAVTransportURI", &[("InstanceID", "0"), ("CurrentURI", &snapshot.uri), ("CurrentURIMetaData", &snapshot.metadata)])?;
+
+            if snapshot.uri.starts_with("x-rincon-queue:")
+            {
+                self.call(AV_TRANSPORT, "Seek", &[("InstanceID", "0"), ("Unit", "TRACK_NR"), ("Target", &snapshot.track.to_string())])?;
+
+                if let Err(e) = self.call(AV_TRANSPORT, "Seek", &[("InstanceID", "0"), ("Unit", "REL_TIME"), ("Target", &snapshot.position)])
+                {
+                    warn!("Failed to seek back into the track on {} : {}", self.host, e);
+                }
+            }
+        }
+
+        self.set_volume(snapshot.volume, ramp)?;
+
+        if snapshot.playing
+        {
+            self.command("Play")?;
+        }
+
+        Ok(())
+    }
+
+    /* Play the URL and wait until the speaker is done with it, stopping or pausing it from the flags */
+    pub(crate) fn announce(self : &Self, url : &str, mime : &str, title : &str, length : Duration, preempted : &AtomicBool, paused : &AtomicBool) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let didl = format!("<DIDL-Lite xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\" \
+                            xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\"><item id=\"notifyd\" parentID=\"-1\" restricted=\"1\">\
+                            <dc:title>{}</dc:title><upnp:class>object.item.audioItem</upnp:class>\
+                            <res protocolInfo=\"http-get:*:{}:*\">{}</res></item></DIDL-Lite>", xml_escape(title), mime, xml_escape(url));
+
+        self.call(AV_TRANSPORT, "SetAVTransportURI", &[("InstanceID", "0"), ("CurrentURI", url), ("CurrentURIMetaData", &didl)])?;
+        self.command("Play")?;
+
+        let start = Instant::now();
+        let mut pause = PauseClock::new();
+        let mut started = false;
+
+        loop {
+            std::thread::sleep(SONOS_POLL);
+
+            if preempted.load(Ordering::SeqCst)
+            {
+                return self.command("Stop");
+            }
+
+            let hold = paused.load(Ordering::SeqCst);
+            if pause.update(hold)
+            {
+                self.command(if hold { "Pause" } else { "Play" })?;
+            }
+
+            if hold
+            {
+                continue;
+            }
+
+            match self.transport_state()?.as_str() {
+                "PLAYING" => started = true,
+                /* Short clips may be over before being seen playing */
+                "STOPPED" if started || start.elapsed() >= length + pause.total() => return Ok(()),
+                _ => {}
+            }
+
+            if start.elapsed() > length + pause.total() + SONOS_MARGIN
+            {
+                let _ = self.command("Stop");
+                return Err(NotifydError::new(format!("Sonos speaker {} did not finish playing in time", self.host).as_str()));
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Serialize;
+use crate::now_in_usecs;
+use crate::store::Store;
+
+/*****************
+ * SOUND LIBRARY *
+ *****************/
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundSource
+{
+    /* Listed in the configuration file, under [sounds] or [alarms.sounds] */
+    Config,
+    /* Sent to POST /sounds and kept in the database */
+    Upload
+}
+
+#[derive(Serialize, Clone)]
+pub struct SoundInfo
+{
+    pub(crate) name : String,
+    pub(crate) source : SoundSource,
+    /* "wav", "mp3", "ogg" or "flac" */
+    pub(crate) format : String,
+    pub(crate) bytes : u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) created : Option<i64>
+}
+
+/* Names end up in URLs and file names */
+pub(crate) fn valid_sound_name(name : &str) -> bool
+{
+    !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/* Uploaded sounds are shared by the instances using the database */
+impl Store
+{
+    pub(crate) fn sounds(self : &Self) -> Result<Vec<SoundInfo>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name, format, length(data), created FROM sounds ORDER BY name")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SoundInfo {
+                name : row.get(0)?,
+                source : SoundSource::Upload,
+                format : row.get(1)?,
+                bytes : row.get(2)?,
+                created : row.get(3)?
+            })
+        })?;
+
+        let mut ret = Vec::new();
+        for s in rows
+        {
+            ret.push(s?);
+        }
+
+        Ok(ret)
+    }
+
+    /* Content of an uploaded sound */
+    pub(crate) fn sound(self : &Self, name : &str) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT data FROM sounds WHERE name = ?1")?;
+        let mut rows = stmt.query_map([name], |row| row.get(0))?;
+
+        match rows.next() {
+            Some(s) => Ok(Some(s?)),
+            None => Ok(None)
+        }
+    }
+
+    pub(crate) fn has_sound(self : &Self, name : &str) -> bool
+    {
+        self.conn.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM sounds WHERE name = ?1", [name], |row| row.get::<_, i64>(0))
+            .map(|n| n > 0)
+            .unwrap_or(false)
+    }
+
+    /* Replaces the sound of the same name */
+    pub(crate) fn save_sound(self : &Self, name : &str, format : &str, data : &[u8]) -> Result<SoundInfo, Box<dyn std::error::Error>>
+    {
+        let now = (now_in_usecs() / 1_000_000) as i64;
+
+        self.conn.lock().unwrap().execute("INSERT OR REPLACE INTO sounds (name, format, data, created) VALUES (?1, ?2, ?3, ?4)",
+                                          rusqlite::params![name, format, data, now])?;
+
+        Ok(SoundInfo {
+            name : name.to_string(),
+            source : SoundSource::Upload,
+            format : format.to_string(),
+            bytes : data.len() as u64,
+            created : Some(now)
+        })
+    }
+
+    pub(crate) fn delete_sound(self : &Self, name : &str) -> Result<bool, Box<dyn std::error::Error>>
+    {
+        Ok(self.conn.lock().unwrap().execute("DELETE FROM sounds WHERE name = ?1", [name])? > 0)
+    }
+}
@@ -0,0 +1,272 @@
+use std::path::PathBuf;
+use std::path::Path;
+use std::env;
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+
+/*********
+ * STORE *
+ *********/
+
+pub const STORE_SCHEMA : &str = "
+CREATE TABLE IF NOT EXISTS devices (
+    uuid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    ip TEXT,
+    model TEXT,
+    kind TEXT NOT NULL,
+    capabilities TEXT NOT NULL,
+    alias TEXT UNIQUE,
+    last_seen INTEGER
+);
+CREATE TABLE IF NOT EXISTS tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    label TEXT NOT NULL,
+    hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created INTEGER NOT NULL,
+    last_used INTEGER
+);
+CREATE TABLE IF NOT EXISTS schedules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    cron TEXT NOT NULL,
+    text TEXT NOT NULL,
+    target TEXT,
+    params TEXT NOT NULL,
+    enabled INTEGER NOT NULL,
+    created INTEGER NOT NULL,
+    last_run INTEGER
+);
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    instance TEXT,
+    time TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    job INTEGER,
+    text TEXT NOT NULL,
+    topic TEXT,
+    target TEXT NOT NULL,
+    priority TEXT NOT NULL,
+    status TEXT NOT NULL,
+    items TEXT NOT NULL,
+    err TEXT,
+    latency_ms INTEGER,
+    audio_url TEXT,
+    audio TEXT
+);
+CREATE TABLE IF NOT EXISTS sounds (
+    name TEXT PRIMARY KEY,
+    format TEXT NOT NULL,
+    data BLOB NOT NULL,
+    created INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS queue (
+    instance TEXT,
+    job INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    target TEXT NOT NULL,
+    priority TEXT NOT NULL,
+    params TEXT NOT NULL,
+    audio TEXT,
+    queued INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS timers (
+    instance TEXT,
+    entry INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    label TEXT NOT NULL,
+    due TEXT NOT NULL,
+    target TEXT NOT NULL,
+    sound TEXT NOT NULL,
+    replace_key TEXT,
+    params TEXT,
+    audio TEXT,
+    source TEXT
+);
+CREATE INDEX IF NOT EXISTS history_timestamp ON history (timestamp);
+CREATE INDEX IF NOT EXISTS history_job ON history (job);
+";
+
+/* Columns added to existing tables since they were first created (table, column, type) */
+pub const STORE_MIGRATIONS : &[(&str, &str, &str)] = &[
+    ("devices", "model", "TEXT"),
+    ("schedules", "instance", "TEXT"),
+    ("history", "params", "TEXT"),
+    ("timers", "source", "TEXT")
+];
+
+/* Embedded SQLite database keeping state across restarts */
+pub struct Store
+{
+    pub(crate) conn : Mutex<rusqlite::Connection>
+}
+
+impl Store
+{
+    pub(crate) fn default_path() -> PathBuf
+    {
+        let data = match (env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) {
+            (Some(d), _) => PathBuf::from(d),
+            (None, Some(h)) => PathBuf::from(h).join(".local/share"),
+            (None, None) => return PathBuf::from("notifyd.db")
+        };
+
+        data.join("notifyd").join("notifyd.db")
+    }
+
+    pub(crate) fn open(path : &Path) -> Result<Store, Box<dyn std::error::Error>>
+    {
+        if let Some(parent) = path.parent()
+        {
+            if !parent.as_os_str().is_empty()
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(STORE_SCHEMA)?;
+        Store::migrate(&conn)?;
+
+        Ok(Store {
+            conn : Mutex::new(conn)
+        })
+    }
+
+    pub(crate) fn migrate(conn : &rusqlite::Connection) -> Result<(), Box<dyn std::error::Error>>
+    {
+        for (table, column, kind) in STORE_MIGRATIONS
+        {
+            let present : i64 = conn.query_row("SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+                                               [table, column], |row| row.get(0))?;
+
+            if present == 0
+            {
+                conn.execute_batch(format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, kind).as_str())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*******************
+ * DEVICE REGISTRY *
+ *******************/
+
+pub fn default_device_kind() -> String
+{
+    String::from("chromecast")
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Device
+{
+    pub(crate) uuid : String,
+    pub(crate) name : String,
+    #[serde(default)]
+    pub(crate) ip : Option<String>,
+    /* As advertised, e.g. "Google Home Mini" or "Google Cast Group" */
+    #[serde(default)]
+    pub(crate) model : Option<String>,
+    /* "chromecast", "dlna", "sonos", ... */
+    #[serde(default = "default_device_kind")]
+    pub(crate) kind : String,
+    #[serde(default)]
+    pub(crate) capabilities : Vec<String>,
+    /* User-assigned name, kept across rediscoveries */
+    #[serde(default)]
+    pub(crate) alias : Option<String>,
+    /* Unix time (seconds) the device was last discovered */
+    #[serde(default)]
+    pub(crate) last_seen : Option<i64>
+}
+
+impl Device
+{
+    pub(crate) fn from_row(row : &rusqlite::Row) -> rusqlite::Result<Device>
+    {
+        let capabilities : String = row.get(4)?;
+
+        Ok(Device {
+            uuid : row.get(0)?,
+            name : row.get(1)?,
+            ip : row.get(2)?,
+            kind : row.get(3)?,
+            capabilities : serde_json::from_str(&capabilities).unwrap_or_default(),
+            alias : row.get(5)?,
+            last_seen : row.get(6)?,
+            model : row.get(7)?
+        })
+    }
+}
+
+impl Store
+{
+    pub(crate) fn devices(self : &Self) -> Result<Vec<Device>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT uuid, name, ip, kind, capabilities, alias, last_seen, model FROM devices ORDER BY name")?;
+        let rows = stmt.query_map([], Device::from_row)?;
+
+        let mut ret = Vec::new();
+        for d in rows
+        {
+            ret.push(d?);
+        }
+
+        Ok(ret)
+    }
+
+    pub(crate) fn device(self : &Self, uuid : &str) -> Result<Option<Device>, Box<dyn std::error::Error>>
+    {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT uuid, name, ip, kind, capabilities, alias, last_seen, model FROM devices WHERE uuid = ?1")?;
+        let mut rows = stmt.query_map([uuid], Device::from_row)?;
+
+        match rows.next() {
+            Some(d) => Ok(Some(d?)),
+            None => Ok(None)
+        }
+    }
+
+    pub(crate) fn save_device(self : &Self, device : &Device) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO devices (uuid, name, ip, kind, capabilities, alias, last_seen, model) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(uuid) DO UPDATE SET name = excluded.name, ip = excluded.ip, kind = excluded.kind,
+                                             capabilities = excluded.capabilities, alias = excluded.alias,
+                                             last_seen = excluded.last_seen, model = excluded.model",
+            rusqlite::params![device.uuid, device.name, device.ip, device.kind,
+                              serde_json::to_string(&device.capabilities)?, device.alias, device.last_seen, device.model])?;
+        Ok(())
+    }
+
+    /* Record a discovered device, keeping the alias it may already have */
+    pub(crate) fn record_discovered(self : &Self, device : &Device) -> Result<(), Box<dyn std::error::Error>>
+    {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO devices (uuid, name, ip, kind, capabilities, last_seen, model) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(uuid) DO UPDATE SET name = excluded.name, ip = excluded.ip, kind = excluded.kind,
+                                             capabilities = excluded.capabilities, last_seen = excluded.last_seen,
+                                             model = excluded.model",
+            rusqlite::params![device.uuid, device.name, device.ip, device.kind,
+                              serde_json::to_string(&device.capabilities)?, device.last_seen, device.model])?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_device(self : &Self, uuid : &str) -> Result<bool, Box<dyn std::error::Error>>
+    {
+        Ok(self.conn.lock().unwrap().execute("DELETE FROM devices WHERE uuid = ?1", [uuid])? > 0)
+    }
+
+    /* UUID of the device with this alias, or else this name */
+    pub(crate) fn resolve_device(self : &Self, name : &str) -> Option<String>
+    {
+        let conn = self.conn.lock().unwrap();
+
+        conn.query_row("SELECT uuid FROM devices WHERE alias = ?1 COLLATE NOCASE
+                        UNION ALL SELECT uuid FROM devices WHERE name = ?1 COLLATE NOCASE LIMIT 1",
+                       [name], |row| row.get(0)).ok()
+    }
+}
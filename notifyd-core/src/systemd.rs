@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/***********************
+ * SYSTEMD INTEGRATION *
+ ***********************/
+
+/* First file descriptor passed by socket activation */
+pub const SD_LISTEN_FDS_START : i32 = 3;
+
+/* Listening socket passed by systemd socket activation, if this process was given one */
+pub fn activation_listener() -> Option<TcpListener>
+{
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::FromRawFd;
+
+        let ours = std::env::var("LISTEN_PID").ok().and_then(|p| p.parse::<u32>().ok()) == Some(std::process::id());
+        let fds = std::env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<i32>().ok()).unwrap_or(0);
+
+        /* Not handed down to the engines and players we spawn */
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        if !ours || fds < 1
+        {
+            return None;
+        }
+
+        if fds > 1
+        {
+            warn!("systemd passed {} sockets, only the first one is served", fds);
+        }
+
+        let listener = unsafe {
+            libc::fcntl(SD_LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+            TcpListener::from_raw_fd(SD_LISTEN_FDS_START)
+        };
+
+        if let Err(e) = listener.local_addr()
+        {
+            warn!("The socket passed by systemd is not a TCP socket ({}), binding the port instead", e);
+            return None;
+        }
+
+        return Some(listener);
+    }
+
+    #[cfg(not(unix))]
+    None
+}
+
+/* Tell the service manager about our state, e.g. "READY=1", when it asked for it */
+pub fn sd_notify(state : &str)
+{
+    #[cfg(unix)]
+    {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = match std::env::var("NOTIFY_SOCKET") {
+            Ok(p) if !p.is_empty() => p,
+            _ => return
+        };
+
+        let sent = UnixDatagram::unbound().and_then(|socket| {
+            match path.strip_prefix('@') {
+                #[cfg(target_os = "linux")]
+                Some(name) => {
+                    use std::os::linux::net::SocketAddrExt;
+                    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                    socket.send_to_addr(state.as_bytes(), &addr)
+                },
+                _ => socket.send_to(state.as_bytes(), &path)
+            }
+        });
+
+        if let Err(e) = sent
+        {
+            warn!("Failed to notify systemd of {} : {}", state.trim(), e);
+        }
+    }
+}
+
+/* Interval within which systemd expects a ping, when its watchdog watches this process */
+pub fn watchdog_interval() -> Option<Duration>
+{
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+    {
+        if pid.parse::<u32>().ok() != Some(std::process::id())
+        {
+            return None;
+        }
+    }
+
+    std::env::var("WATCHDOG_USEC").ok()
+                                  .and_then(|u| u.parse::<u64>().ok())
+                                  .filter(|u| *u > 0)
+                                  .map(Duration::from_micros)
+}
+
+/* Connections accepted on the socket passed by systemd are relayed to the
+   server listening on the loopback, which only binds addresses itself. The
+   clients are remembered by the local port of their relay */
+pub struct Relay
+{
+    pub(crate) peers : Mutex<HashMap<u16, SocketAddr>>
+}
+
+impl Relay
+{
+    pub(crate) fn new() -> Arc<Relay>
+    {
+        Arc::new(Relay {
+            peers : Mutex::new(HashMap::new())
+        })
+    }
+
+    /* Client behind a request, which is its source unless it was relayed */
+    pub(crate) fn peer(self : &Self, source : &SocketAddr) -> SocketAddr
+    {
+        if !source.ip().is_loopback()
+        {
+            return *source;
+        }
+
+        self.peers.lock().unwrap().get(&source.port()).copied().unwrap_or(*source)
+    }
+
+    pub(crate) fn serve(self : Arc<Self>, listener : TcpListener, backend : SocketAddr)
+    {
+        std::thread::spawn(move || {
+            for client in listener.incoming()
+            {
+                let client = match client {
+                    Ok(c) => c,
+                    Err(e) => {
+                        warn!("Failed to accept a connection on the socket passed by systemd : {}", e);
+                        continue;
+                    }
+                };
+
+                let relay = Arc::clone(&self);
+                std::thread::spawn(move || {
+                    if let Err(e) = relay.forward(client, backend)
+                    {
+                        debug!("Relayed connection closed : {}", e);
+                    }
+                });
+            }
+        });
+    }
+
+    fn forward(self : &Self, client : TcpStream, backend : SocketAddr) -> std::io::Result<()>
+    {
+        let peer = client.peer_addr()?;
+        let server = TcpStream::connect(backend)?;
+        let port = server.local_addr()?.port();
+
+        self.peers.lock().unwrap().insert(port, peer);
+
+        let (mut from_client, mut to_server) = (client.try_clone()?, server.try_clone()?);
+        let upstream = std::thread::spawn(move || {
+            let _ = std::io::copy(&mut from_client, &mut to_server);
+            let _ = to_server.shutdown(Shutdown::Write);
+        });
+
+        let (mut from_server, mut to_client) = (server, client);
+        let _ = std::io::copy(&mut from_server, &mut to_client);
+        let _ = to_client.shutdown(Shutdown::Both);
+        let _ = upstream.join();
+
+        self.peers.lock().unwrap().remove(&port);
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::error::NotifydError;
+use crate::tts::SpeechParams;
+
+/*************
+ * TEMPLATES *
+ *************/
+
+/* Text of the template in the language of the request, "washer.fr-FR" then "washer.fr" then "washer" */
+pub fn localized_template<'a>(templates : &'a HashMap<String, String>, name : &str, lang : Option<&str>) -> Option<&'a String>
+{
+    if let Some(lang) = lang
+    {
+        let short = lang.split(['-', '_']).next().unwrap_or(lang);
+
+        for l in [lang, short]
+        {
+            if let Some(t) = templates.get(&format!("{}.{}", name, l))
+            {
+                return Some(t);
+            }
+        }
+    }
+
+    templates.get(name)
+}
+
+pub enum TemplatePart
+{
+    Text(String),
+    Variable(String)
+}
+
+pub fn parse_template(template : &str) -> Result<Vec<TemplatePart>, Box<dyn std::error::Error>>
+{
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next()
+    {
+        match c {
+            '{' | '}' if chars.peek() == Some(&c) => {
+                chars.next();
+                text.push(c);
+            },
+            '{' => {
+                let mut name = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some('{') | None => return Err(NotifydError::new(format!("Unclosed variable '{{{}' in template", name).as_str())),
+                        Some(c) => name.push(c)
+                    }
+                }
+
+                let name = name.trim().to_string();
+
+                if name.is_empty()
+                {
+                    return Err(NotifydError::new("Empty variable name in template"));
+                }
+
+                if !text.is_empty()
+                {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                }
+
+                parts.push(TemplatePart::Variable(name));
+            },
+            '}' => return Err(NotifydError::new("Unmatched '}' in template, '}}' stands for a brace")),
+            c => text.push(c)
+        }
+    }
+
+    if !text.is_empty()
+    {
+        parts.push(TemplatePart::Text(text));
+    }
+
+    Ok(parts)
+}
+
+/* Substitute the variables, which must all be given as strings, numbers or booleans */
+pub fn render_template(template : &str, vars : &serde_json::Map<String, serde_json::Value>) -> Result<String, Box<dyn std::error::Error>>
+{
+    let mut ret = String::new();
+
+    for part in parse_template(template)?
+    {
+        match part {
+            TemplatePart::Text(t) => ret.push_str(&t),
+            TemplatePart::Variable(v) => match vars.get(&v) {
+                Some(serde_json::Value::String(s)) => ret.push_str(s),
+                Some(serde_json::Value::Number(n)) => ret.push_str(&n.to_string()),
+                Some(serde_json::Value::Bool(b)) => ret.push_str(&b.to_string()),
+                Some(serde_json::Value::Null) | None => return Err(NotifydError::new(format!("Missing variable '{}'", v).as_str())),
+                Some(_) => return Err(NotifydError::new(format!("Variable '{}' must be a string, a number or a boolean", v).as_str()))
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/***********
+ * PRESETS *
+ ***********/
+
+/* A [presets.<name>] section, played as it is by /notify/preset/<name> */
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PresetConfig
+{
+    pub(crate) text : String,
+    pub(crate) target : Option<String>,
+    /* The speech fields of /notify, e.g. priority = "high" or repeat = 2 */
+    #[serde(flatten)]
+    pub(crate) params : SpeechParams
+}
+
+impl PresetConfig
+{
+    pub(crate) fn check(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        if self.text.trim().is_empty() && self.params.segments.is_empty()
+        {
+            return Err(NotifydError::new("A preset needs a text"));
+        }
+
+        self.params.check_levels()
+    }
+}
@@ -0,0 +1,195 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::error::NotifydError;
+use crate::journal::Journal;
+use crate::tts::SpeechParams;
+
+/*********************
+ * TIMERS AND ALARMS *
+ *********************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AlarmConfig
+{
+    /* Sound played before the label, "beep" for a generated tone pattern */
+    pub(crate) default_sound : String,
+    /* Named sound files requests can pick with "sound" */
+    pub(crate) sounds : HashMap<String, PathBuf>,
+    /* "{label}" is substituted */
+    pub(crate) timer_message : String,
+    pub(crate) alarm_message : String
+}
+
+impl Default for AlarmConfig
+{
+    fn default() -> Self
+    {
+        AlarmConfig {
+            default_sound : String::from("beep"),
+            sounds : HashMap::new(),
+            timer_message : String::from("The {label} timer is done"),
+            alarm_message : String::from("{label}")
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimerKind
+{
+    Timer,
+    Alarm,
+    /* A /notify request sent later, its text being the label */
+    Notification
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScheduledEntry
+{
+    pub(crate) id : u64,
+    pub(crate) kind : TimerKind,
+    pub(crate) label : String,
+    pub(crate) due : String,
+    pub(crate) target : String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub(crate) sound : String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) replace_key : Option<String>,
+    #[serde(skip)]
+    pub(crate) due_at : chrono::DateTime<chrono::Local>,
+    /* Speech settings of a scheduled notification */
+    #[serde(skip)]
+    pub(crate) params : Option<SpeechParams>
+}
+
+impl ScheduledEntry
+{
+    pub(crate) fn new(kind : TimerKind, label : String, due_at : chrono::DateTime<chrono::Local>, target : String) -> ScheduledEntry
+    {
+        ScheduledEntry {
+            id : 0,
+            kind : kind,
+            label : label,
+            due : due_at.to_rfc3339(),
+            target : target,
+            sound : String::new(),
+            replace_key : None,
+            due_at : due_at,
+            params : None
+        }
+    }
+}
+
+pub struct Scheduler
+{
+    pub(crate) entries : Mutex<Vec<ScheduledEntry>>,
+    pub(crate) next_id : Mutex<u64>,
+    /* Kept in the database to survive restarts, when persistence is enabled */
+    pub(crate) journal : Option<Journal>
+}
+
+impl Scheduler
+{
+    pub(crate) fn new(journal : Option<Journal>) -> Scheduler
+    {
+        Scheduler {
+            entries : Mutex::new(Vec::new()),
+            next_id : Mutex::new(1),
+            journal : journal
+        }
+    }
+
+    /* Schedule the entry under a fresh id */
+    pub(crate) fn add(self : &Self, mut entry : ScheduledEntry) -> ScheduledEntry
+    {
+        entry.id = {
+            let mut next = self.next_id.lock().unwrap();
+            let id = *next;
+            *next += 1;
+            id
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entry.replace_key.is_some()
+        {
+            let (replaced, kept) : (Vec<ScheduledEntry>, Vec<ScheduledEntry>) = std::mem::take(&mut *entries).into_iter()
+                                                                                                     .partition(|e| e.replace_key == entry.replace_key);
+            *entries = kept;
+
+            if let Some(j) = &self.journal
+            {
+                replaced.iter().for_each(|e| j.forget_entry(e.id));
+            }
+        }
+
+        if let Some(j) = &self.journal
+        {
+            j.save_entry(&entry);
+        }
+
+        entries.push(entry.clone());
+        entries.sort_by_key(|e| e.due_at);
+
+        entry
+    }
+
+    pub(crate) fn cancel(self : &Self, id : u64) -> Result<ScheduledEntry, Box<dyn std::error::Error>>
+    {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.iter().position(|e| e.id == id) {
+            Some(pos) => {
+                if let Some(j) = &self.journal
+                {
+                    j.forget_entry(id);
+                }
+                Ok(entries.remove(pos))
+            },
+            None => Err(NotifydError::new(format!("No timer or alarm with id {}", id).as_str()))
+        }
+    }
+
+    pub(crate) fn list(self : &Self) -> Vec<ScheduledEntry>
+    {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub(crate) fn take_due(self : &Self, now : chrono::DateTime<chrono::Local>) -> Vec<ScheduledEntry>
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let split = entries.iter().position(|e| e.due_at > now).unwrap_or(entries.len());
+        let due : Vec<ScheduledEntry> = entries.drain(..split).collect();
+
+        if let Some(j) = &self.journal
+        {
+            due.iter().for_each(|e| j.forget_entry(e.id));
+        }
+
+        due
+    }
+}
+
+/* Next occurrence of a wall clock time, tomorrow when already past */
+pub fn next_occurrence(at : chrono::NaiveTime) -> Result<chrono::DateTime<chrono::Local>, Box<dyn std::error::Error>>
+{
+    let now = chrono::Local::now();
+    let mut day = now.date_naive();
+
+    for _ in 0..2
+    {
+        if let Some(t) = day.and_time(at).and_local_timezone(chrono::Local).earliest()
+        {
+            if t > now
+            {
+                return Ok(t);
+            }
+        }
+        day = day.succ_opt().unwrap_or(day);
+    }
+
+    Err(NotifydError::new(format!("{} does not exist in the local time zone", at).as_str()))
+}
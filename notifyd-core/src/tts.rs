@@ -0,0 +1,2004 @@
+use tempdir::TempDir;
+use std::{path::PathBuf, fs::remove_file};
+use which::which;
+use std::process::{Command, Stdio};
+use std::path::Path;
+use std::env;
+use std::fs::{File, read_dir};
+use md5::compute as md5;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::channel;
+use serde::{Serialize, Deserialize};
+use std::time::{Instant, Duration};
+use std::io::{Read, Write};
+use std::collections::{HashMap, VecDeque};
+use base64::Engine;
+use tracing::{debug, error, info, warn};
+use crate::audio::{AudioOutput, PcmAudio, PlaybackControl, sniff_audio};
+use crate::cloud::{AzureConfig, AzureTts, GoogleConfig, GoogleTts, Polly, PollyConfig};
+use crate::config::Config;
+use crate::error::NotifydError;
+use crate::limits::SlotGuard;
+use crate::now_in_usecs;
+use crate::queue::Priority;
+
+/**************
+ * TTS ENGINE *
+ **************/
+
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+pub enum TTSEngine
+{
+    PIPERTTS,
+    /* The piper binary itself, given the model to use */
+    PIPER,
+    PICO2WAV,
+    ESPEAK,
+    ESPEAKNG,
+    /* macOS */
+    SAY,
+    /* Windows SAPI through PowerShell's System.Speech */
+    SAPI,
+    /* Cloud services reached over HTTP */
+    GOOGLE,
+    POLLY,
+    AZURE,
+    AUTO
+}
+
+impl TTSEngine
+{
+    pub(crate) fn is_cloud(self : &Self) -> bool
+    {
+        matches!(self, TTSEngine::GOOGLE | TTSEngine::POLLY | TTSEngine::AZURE)
+    }
+}
+
+/* One part of a message spoken with its own language and voice */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Segment
+{
+    #[serde(default)]
+    pub(crate) text : String,
+    pub(crate) lang : Option<String>,
+    pub(crate) voice : Option<String>,
+    pub(crate) phonemes : Option<String>
+}
+
+/* Named speech rates, mapped to engine specific parameters */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Speed
+{
+    Slow,
+    Normal,
+    Fast,
+    Urgent
+}
+
+impl Speed
+{
+    /* Default speed-up factor relative to the engine's natural rate */
+    pub(crate) fn default_factor(self : &Self) -> f32
+    {
+        match self {
+            Speed::Slow => 0.8,
+            Speed::Normal => 1.0,
+            Speed::Fast => 1.25,
+            Speed::Urgent => 1.5
+        }
+    }
+}
+
+/* Voice models of the piper engine */
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct PiperConfig
+{
+    /* Directory of the .onnx models, $PIPER_VOICES when absent */
+    pub(crate) voices : Option<PathBuf>,
+    /* Model spoken when none is requested, a name in the voices directory or a path */
+    pub(crate) model : Option<String>
+}
+
+/* Settings of the engines needing more than a binary in PATH */
+#[derive(Default, Clone)]
+pub struct Backends
+{
+    pub(crate) piper : PiperConfig,
+    pub(crate) google : Option<GoogleConfig>,
+    pub(crate) polly : Option<PollyConfig>,
+    pub(crate) azure : Option<AzureConfig>
+}
+
+impl Backends
+{
+    pub(crate) fn new(config : &Config) -> Backends
+    {
+        Backends {
+            piper : config.piper.clone(),
+            google : config.google.clone(),
+            polly : config.polly.clone(),
+            azure : config.azure.clone()
+        }
+    }
+}
+
+/* Bounds of the repetitions of a notification, which hold the queue meanwhile */
+pub const MAX_REPEAT : u32 = 10;
+pub const MAX_REPEAT_GAP_MS : u64 = 60_000;
+
+/* Optional per-request synthesis settings */
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct SpeechParams
+{
+    /* Topic whose configured defaults apply to this request */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) topic : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) speed : Option<Speed>,
+    /* Speed-up factor, overriding the speed preset */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rate : Option<f32>,
+    /* Relative to the natural pitch of the voice, 1.0 leaving it alone */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pitch : Option<f32>,
+    /* Volume factor, 0.5 halving the amplitude */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) gain : Option<f32>,
+    /* Level of the speaker or device while this plays, from 0.0 to 1.0 */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) volume : Option<f32>,
+    /* espeak voice variant such as "f3", "m7", "whisper" or "croak" */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) variant : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) lang : Option<String>,
+    /* Either an engine specific voice name or an "engine:voice" spec */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) voice : Option<String>,
+    /* Phoneme mnemonics spoken instead of the text, for engines supporting it */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) phonemes : Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) segments : Vec<Segment>,
+    /* Rank of the request in the playback queue */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) priority : Option<Priority>,
+    /* Said before the text, e.g. "From Grafana:", overriding the configured one */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) prefix : Option<String>,
+    /* Pending notifications with the same key are replaced by this one */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) replace_key : Option<String>,
+    /* Notifications with the same key arriving within the deduplication window are merged into the first one */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) dedup_key : Option<String>,
+    /* Answer once the notification was delivered instead of once queued */
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) wait : bool,
+    /* Times the notification is played in a row, 1 by default */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) repeat : Option<u32>,
+    /* Silence between two repetitions, 1 second by default */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) repeat_gap_ms : Option<u64>,
+    /* Stop the notification being played and go before the pending ones */
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) interrupt : bool,
+    /* Audio supplied by the client, played instead of synthesized speech */
+    #[serde(skip)]
+    pub(crate) audio : Option<PathBuf>,
+    /* Label of the token the notification was sent with, for the routing rules */
+    #[serde(skip)]
+    pub(crate) source : Option<String>
+}
+
+impl SpeechParams
+{
+    /* Settings of a single segment, inheriting what it leaves unset */
+    pub(crate) fn for_segment(self : &Self, seg : &Segment) -> SpeechParams
+    {
+        SpeechParams {
+            topic : self.topic.clone(),
+            speed : self.speed,
+            rate : self.rate,
+            pitch : self.pitch,
+            gain : self.gain,
+            volume : self.volume,
+            variant : self.variant.clone(),
+            lang : seg.lang.clone().or(self.lang.clone()),
+            voice : seg.voice.clone().or(self.voice.clone()),
+            phonemes : seg.phonemes.clone(),
+            segments : Vec::new(),
+            priority : self.priority,
+            prefix : None,
+            replace_key : None,
+            dedup_key : None,
+            wait : false,
+            repeat : None,
+            repeat_gap_ms : None,
+            interrupt : false,
+            audio : None,
+            source : self.source.clone()
+        }
+    }
+
+    /* Engines clamp the levels within these bounds */
+    pub(crate) fn check_levels(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        for (name, value, min, max) in [("rate", self.rate, 0.25, 4.0), ("pitch", self.pitch, 0.5, 2.0), ("gain", self.gain, 0.0, 4.0), ("volume", self.volume, 0.0, 1.0)]
+        {
+            match value {
+                Some(v) if !(min..=max).contains(&v) => {
+                    return Err(NotifydError::new(format!("'{}' must be between {} and {}", name, min, max).as_str()));
+                },
+                _ => {}
+            }
+        }
+
+        if let Some(r) = self.repeat
+        {
+            if !(1..=MAX_REPEAT).contains(&r)
+            {
+                return Err(NotifydError::new(format!("'repeat' must be between 1 and {}", MAX_REPEAT).as_str()));
+            }
+        }
+
+        if self.repeat_gap_ms.unwrap_or(0) > MAX_REPEAT_GAP_MS
+        {
+            return Err(NotifydError::new(format!("'repeat_gap_ms' cannot exceed {}", MAX_REPEAT_GAP_MS).as_str()));
+        }
+
+        Ok(())
+    }
+
+    /* The text to announce, built from the segments when no plain text was given */
+    pub(crate) fn full_text(self : &Self, text : String) -> Result<String, Box<dyn std::error::Error>>
+    {
+        if !text.is_empty()
+        {
+            return Ok(text);
+        }
+
+        if let Some(ph) = &self.phonemes
+        {
+            return Ok(ph.clone());
+        }
+
+        if self.segments.is_empty()
+        {
+            return Err(NotifydError::new("Either 'text', 'phonemes' or 'segments' must be provided"));
+        }
+
+        Ok(self.segments.iter()
+                        .map(|s| if s.text.is_empty() { s.phonemes.clone().unwrap_or_default() } else { s.text.clone() })
+                        .collect::<Vec<String>>()
+                        .join(" "))
+    }
+}
+
+/* An engine with an optional engine-specific voice, written "engine:voice" in the config */
+#[derive(Clone, Debug)]
+pub struct VoiceSpec
+{
+    pub(crate) engine : TTSEngine,
+    pub(crate) voice : Option<String>
+}
+
+impl VoiceSpec
+{
+    pub(crate) fn parse(desc : &str) -> Result<VoiceSpec, Box<dyn std::error::Error>>
+    {
+        let (engine_name, voice) = match desc.split_once(':') {
+            Some((e, v)) if !v.is_empty() => (e, Some(v.to_string())),
+            Some((e, _)) => (e, None),
+            None => (desc, None)
+        };
+
+        match TTS::all_engines().into_iter().find(|e| TTS::tts_to_bin_name(e) == engine_name)
+        {
+            Some(e) => Ok(VoiceSpec{
+                engine : e,
+                voice : voice
+            }),
+            None => Err(NotifydError::new(format!("Unknown TTS engine '{}' in '{}'", engine_name, desc).as_str()))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TtsSentence
+{
+    pub text: String,
+    pub path : String,
+    pub(crate) engine : TTSEngine,
+    pub(crate) voice : String,
+    /* Set when the requested language was not available */
+    pub(crate) fallback : bool,
+    /* The file belongs to the synthesis cache and outlives the sentence */
+    pub(crate) cached : bool
+}
+
+impl TtsSentence
+{
+    pub(crate) fn new(path : &str, text : &str, engine : TTSEngine, voice : &str) -> TtsSentence
+    {
+        TtsSentence{
+            text : String::from(text),
+            path : String::from(path),
+            engine : engine,
+            voice : String::from(voice),
+            fallback : false,
+            cached : false
+        }
+    }
+
+    pub(crate) fn _run_player(self : &Self, player : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let cmd = [player, self.path.as_str()];
+
+        let ret = Command::new(cmd[0])
+        .args(&cmd[1..])
+        .output()?;
+
+        if !ret.status.success()
+        {
+            error!("Failed to run {:?} : {}", cmd, String::from_utf8_lossy(&ret.stderr).trim());
+            return Err(NotifydError::new("Failed to run tts"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn _play_external(self: & Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        #[cfg(target_os = "macos")]
+        let candidate_players = ["afplay", "play" /* sox */];
+        #[cfg(not(target_os = "macos"))]
+        let candidate_players = ["paplay", "mplayer", "play" /* sox */];
+
+        for p in candidate_players
+        {
+            match which(p) {
+                Ok(p) => {
+                    match self._run_player(p.to_str().unwrap())
+                    {
+                        Ok(_) => return Ok(()),
+                        Err(e) => return Err(e)
+                    }
+                },
+                Err(_) => {}
+            }
+        }
+
+        Err(NotifydError::new(format!("Could not find any player in {:?} to play {}", candidate_players, self.path).as_str()))
+    }
+
+    /* Convert the WAV to another container (mp3, opus, ...) next to the original */
+    pub(crate) fn transcode(self : &Self, extension : &str, options : &[&str]) -> Result<TtsSentence, Box<dyn std::error::Error>>
+    {
+        let ffmpeg = match which("ffmpeg") {
+            Ok(p) => p,
+            Err(_) => return Err(NotifydError::new("Cannot locate ffmpeg in path to transcode audio"))
+        };
+
+        let outpath = Path::new(&self.path).with_extension(extension);
+        let outpath = outpath.to_string_lossy();
+
+        let ret = Command::new(ffmpeg)
+        .args(["-y", "-loglevel", "error", "-i", self.path.as_str()])
+        .args(options)
+        .arg(outpath.as_ref())
+        .output()?;
+
+        if !ret.status.success()
+        {
+            let err_desc = String::from_utf8_lossy(&ret.stderr).to_string();
+            error!("Failed to transcode {} to {} : {}", self.path, extension, err_desc.trim());
+            return Err(NotifydError::new(err_desc.as_str()));
+        }
+
+        Ok(TtsSentence{
+            path : outpath.to_string(),
+            cached : false,
+            ..self.clone()
+        })
+    }
+
+    /* Duration computed from the RIFF header (byte rate and data chunk size) */
+    pub(crate) fn duration_ms(self : &Self) -> Result<u64, Box<dyn std::error::Error>>
+    {
+        let mut content = Vec::new();
+        File::open(&self.path)?.read_to_end(&mut content)?;
+
+        let le32 = |off : usize| -> u32 {
+            u32::from_le_bytes([content[off], content[off + 1], content[off + 2], content[off + 3]])
+        };
+
+        if content.len() < 12 || &content[0..4] != b"RIFF" || &content[8..12] != b"WAVE"
+        {
+            return Err(NotifydError::new(format!("{} is not a WAV file", self.path).as_str()));
+        }
+
+        let mut byte_rate : u32 = 0;
+        let mut off = 12;
+
+        while off + 8 <= content.len()
+        {
+            let id = &content[off..off + 4];
+            let size = le32(off + 4) as usize;
+
+            if id == b"fmt " && off + 16 <= content.len()
+            {
+                byte_rate = le32(off + 16);
+            }
+            else if id == b"data"
+            {
+                if byte_rate == 0
+                {
+                    break;
+                }
+                /* Streaming engines may leave the size unset */
+                let avail = content.len() - off - 8;
+                let size = if size == 0 || size > avail { avail } else { size };
+                return Ok(size as u64 * 1000 / byte_rate as u64);
+            }
+
+            off += 8 + size + (size & 1);
+        }
+
+        Err(NotifydError::new(format!("Could not find audio data in {}", self.path).as_str()))
+    }
+
+    pub(crate) fn play(self : &Self, output : &mut AudioOutput, control : &PlaybackControl) -> Result<(), Box<dyn std::error::Error>>
+    {
+        //self.play_external()
+        output.play(self, control)
+    }
+
+    pub(crate) fn delete(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        if self.cached
+        {
+            return Ok(());
+        }
+
+        debug!("Removing data for {} : '{}'", self.path, self.text);
+        remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct VoiceInfo
+{
+    pub(crate) name : String,
+    pub(crate) lang : String
+}
+
+#[derive(Serialize)]
+pub struct EngineInfo
+{
+    pub(crate) engine : String,
+    pub(crate) path : String,
+    pub(crate) active : bool,
+    pub(crate) voices : Vec<VoiceInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) variants : Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) err : Option<String>
+}
+
+/****************
+ * TTS BACKENDS *
+ ****************/
+
+/* One synthesis handed to a backend */
+pub struct Utterance<'a>
+{
+    pub(crate) text : &'a str,
+    /* Spoken instead of the text, for engines supporting it */
+    pub(crate) phonemes : Option<&'a str>,
+    pub(crate) lang : &'a str,
+    /* Engine specific voice, the default one of the language when absent */
+    pub(crate) voice : Option<&'a str>,
+    pub(crate) variant : Option<&'a str>,
+    /* Speed-up factor relative to the natural rate of the voice */
+    pub(crate) factor : f32,
+    /* Pitch factor relative to the natural one */
+    pub(crate) pitch : f32,
+    /* Volume factor, applied to the WAV unless the backend applies it */
+    pub(crate) gain : f32,
+    /* Where the WAV is written */
+    pub(crate) outpath : &'a str
+}
+
+/* What synthesizes speech, a binary in PATH or a cloud service */
+pub trait TtsBackend
+{
+    /* Path of the binary or URL of the service */
+    fn location(self : &Self) -> String;
+
+    fn voices(self : &Self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>>;
+
+    fn variants(self : &Self) -> Vec<String>
+    {
+        Vec::new()
+    }
+
+    /* Whether synthesize applies the gain of the utterance itself */
+    fn applies_gain(self : &Self) -> bool
+    {
+        false
+    }
+
+    /* Writes the WAV of the utterance, returning the voice it was spoken with */
+    fn synthesize(self : &Self, u : &Utterance) -> Result<String, Box<dyn std::error::Error>>;
+}
+
+/* The voice for a language among the ones of an engine, preferring the exact
+   region (e.g. fr-FR over fr-BE) */
+pub(crate) fn voice_for_lang<'a>(voices : &'a [VoiceInfo], lang : &str) -> Option<&'a VoiceInfo>
+{
+    let norm = |l : &str| l.to_lowercase().replace('_', "-");
+    let wanted = norm(lang);
+    let primary = |l : &str| l.split('-').next().unwrap_or("").to_string();
+
+    let mut candidates : Vec<&VoiceInfo> = voices.iter()
+                                                 .filter(|v| primary(&norm(&v.lang)) == primary(&wanted))
+                                                 .collect();
+    candidates.sort_by_key(|v| (norm(&v.lang) != wanted, v.name.clone()));
+
+    candidates.first().copied()
+}
+
+/* Engines run as a local binary, the text being written to their stdin */
+pub struct LocalEngine
+{
+    pub(crate) engine : TTSEngine,
+    pub(crate) path : PathBuf,
+    pub(crate) piper : PiperConfig
+}
+
+impl LocalEngine
+{
+    /* The model piper speaks with: the requested one, the configured one,
+       or else the first installed model of the language */
+    pub(crate) fn piper_model(self : &Self, voice : Option<&str>, lang : &str) -> Result<PathBuf, Box<dyn std::error::Error>>
+    {
+        let model = match voice.or(self.piper.model.as_deref()) {
+            Some(m) => TTS::piper_model_path(&self.piper, m),
+            None => {
+                match voice_for_lang(&self.voices()?, lang) {
+                    Some(v) => TTS::piper_model_path(&self.piper, &v.name),
+                    None => return Err(NotifydError::new(format!("No piper model for {} in {}, set [piper] model",
+                                                                 lang, TTS::piper_voice_dir(&self.piper).to_string_lossy()).as_str()))
+                }
+            }
+        };
+
+        if !model.is_file()
+        {
+            return Err(NotifydError::new(format!("Cannot find piper model {}", model.to_string_lossy()).as_str()));
+        }
+
+        Ok(model)
+    }
+}
+
+impl TtsBackend for LocalEngine
+{
+    fn location(self : &Self) -> String
+    {
+        self.path.to_string_lossy().to_string()
+    }
+
+    fn voices(self : &Self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>>
+    {
+        let mut voices = Vec::new();
+
+        match self.engine {
+            TTSEngine::ESPEAK | TTSEngine::ESPEAKNG => {
+                let ret = Command::new(&self.path)
+                .arg("--voices")
+                .output()?;
+
+                if !ret.status.success()
+                {
+                    return Err(NotifydError::new(String::from_utf8_lossy(&ret.stderr).as_ref()));
+                }
+
+                /* Pty Language Age/Gender VoiceName File Other Languages */
+                for line in String::from_utf8_lossy(&ret.stdout).lines().skip(1)
+                {
+                    let cols : Vec<&str> = line.split_whitespace().collect();
+                    if cols.len() >= 4
+                    {
+                        voices.push(VoiceInfo{
+                            name : cols[3].to_string(),
+                            lang : cols[1].to_string()
+                        });
+                    }
+                }
+            },
+            TTSEngine::PICO2WAV => {
+                /* Pico ships a fixed set of languages */
+                for l in ["en-US", "en-GB", "de-DE", "es-ES", "fr-FR", "it-IT"]
+                {
+                    voices.push(VoiceInfo{
+                        name : l.to_string(),
+                        lang : l.to_string()
+                    });
+                }
+            },
+            TTSEngine::PIPERTTS | TTSEngine::PIPER => {
+                /* Models are named like fr_FR-siwis-medium.onnx */
+                for entry in read_dir(TTS::piper_voice_dir(&self.piper))? {
+                    let path = entry?.path();
+
+                    if path.extension().map(|e| e == "onnx").unwrap_or(false)
+                    {
+                        let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                        let lang = name.split('-').next().unwrap_or("").replace('_', "-");
+                        voices.push(VoiceInfo{
+                            name : name,
+                            lang : lang
+                        });
+                    }
+                }
+            },
+            TTSEngine::SAY => {
+                let ret = Command::new(&self.path)
+                .args(["-v", "?"])
+                .output()?;
+
+                /* "Bad News          en_US    # The light you see ..." */
+                for line in String::from_utf8_lossy(&ret.stdout).lines()
+                {
+                    let desc = line.split('#').next().unwrap_or("").trim();
+                    if let Some((name, lang)) = desc.rsplit_once(char::is_whitespace)
+                    {
+                        voices.push(VoiceInfo{
+                            name : name.trim().to_string(),
+                            lang : lang.replace('_', "-")
+                        });
+                    }
+                }
+            },
+            TTSEngine::SAPI => {
+                let ret = Command::new(&self.path)
+                .args(["-NoProfile", "-NonInteractive", "-Command",
+                       "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).GetInstalledVoices() | ForEach-Object { $_.VoiceInfo.Name + '|' + $_.VoiceInfo.Culture.Name }"])
+                .output()?;
+
+                if !ret.status.success()
+                {
+                    return Err(NotifydError::new(String::from_utf8_lossy(&ret.stderr).as_ref()));
+                }
+
+                for line in String::from_utf8_lossy(&ret.stdout).lines()
+                {
+                    if let Some((name, lang)) = line.trim().split_once('|')
+                    {
+                        voices.push(VoiceInfo{
+                            name : name.to_string(),
+                            lang : lang.to_string()
+                        });
+                    }
+                }
+            },
+            _ => {}
+        }
+
+        Ok(voices)
+    }
+
+    fn variants(self : &Self) -> Vec<String>
+    {
+        if self.engine != TTSEngine::ESPEAK && self.engine != TTSEngine::ESPEAKNG
+        {
+            return Vec::new();
+        }
+
+        let ret = match Command::new(&self.path).arg("--voices=variant").output() {
+            Ok(r) => r,
+            Err(_) => return Vec::new()
+        };
+
+        /* Pty Language Age/Gender VoiceName File, the file being "!v/<variant>" */
+        String::from_utf8_lossy(&ret.stdout)
+                .lines()
+                .skip(1)
+                .filter_map(|l| l.split_whitespace().nth(4))
+                .map(|f| f.trim_start_matches("!v/").to_string())
+                .collect()
+    }
+
+    fn applies_gain(self : &Self) -> bool
+    {
+        self.engine == TTSEngine::ESPEAK || self.engine == TTSEngine::ESPEAKNG
+    }
+
+    fn synthesize(self : &Self, u : &Utterance) -> Result<String, Box<dyn std::error::Error>>
+    {
+        let mut cmd = Command::new(&self.path);
+        let outpath = u.outpath;
+        let factor = u.factor;
+        let mut used = u.voice.unwrap_or(u.lang).to_string();
+
+        match self.engine {
+            TTSEngine::PIPERTTS => {
+                cmd.args(["-f", outpath]);
+                /* Piper stretches phoneme lengths, higher is slower */
+                if factor != 1.0
+                {
+                    cmd.args(["--length_scale", format!("{:.2}", 1.0 / factor).as_str()]);
+                }
+                /* The pipertts wrapper picks its model from the environment */
+                if let Some(v) = u.voice
+                {
+                    cmd.env("TTSMODEL", v);
+                }
+            },
+            TTSEngine::PIPER => {
+                let model = self.piper_model(u.voice, u.lang)?;
+
+                /* The .onnx.json next to the model is picked up by piper */
+                cmd.arg("--model").arg(&model).args(["--output_file", outpath]);
+                if factor != 1.0
+                {
+                    cmd.args(["--length_scale", format!("{:.2}", 1.0 / factor).as_str()]);
+                }
+                used = model.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            },
+            TTSEngine::ESPEAK | TTSEngine::ESPEAKNG => {
+                let mut voice = used.clone();
+
+                /* Variants are appended to the voice name, replacing any given there */
+                if let Some(v) = u.variant
+                {
+                    if v.is_empty() || !v.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        return Err(NotifydError::new(format!("Invalid voice variant '{}'", v).as_str()));
+                    }
+                    voice = format!("{}+{}", voice.split('+').next().unwrap_or(""), v);
+                }
+
+                cmd.args(["-w", outpath, "-v", voice.as_str()]);
+                /* espeak speaks 175 words per minute by default */
+                if factor != 1.0
+                {
+                    cmd.args(["-s", format!("{}", (175.0 * factor) as u32).as_str()]);
+                }
+                /* Pitch goes from 0 to 99 around 50, amplitude from 0 to 200 around 100 */
+                if u.pitch != 1.0
+                {
+                    cmd.args(["-p", format!("{}", (50.0 * u.pitch).round().clamp(0.0, 99.0) as u32).as_str()]);
+                }
+                if u.gain != 1.0
+                {
+                    cmd.args(["-a", format!("{}", (100.0 * u.gain).round().clamp(0.0, 200.0) as u32).as_str()]);
+                }
+            },
+            TTSEngine::SAY => {
+                cmd.args(["-o", outpath, "--data-format=LEI16@22050"]);
+                if let Some(v) = u.voice
+                {
+                    cmd.args(["-v", v]);
+                }
+                if factor != 1.0
+                {
+                    cmd.args(["-r", format!("{}", (175.0 * factor) as u32).as_str()]);
+                }
+                /* say reads the text from stdin when given none */
+            },
+            TTSEngine::SAPI => {
+                let quote = |v : &str| format!("'{}'", v.replace('\'', "''"));
+
+                let select = match u.voice {
+                    Some(v) => format!("$s.SelectVoice({});", quote(v)),
+                    None => format!("$s.SelectVoiceByHints([System.Speech.Synthesis.VoiceGender]::NotSet, [System.Speech.Synthesis.VoiceAge]::NotSet, 0, [System.Globalization.CultureInfo]::GetCultureInfo({}));", quote(u.lang))
+                };
+                /* SAPI rates go from -10 to 10 */
+                let rate = ((factor - 1.0) * 10.0).round().clamp(-10.0, 10.0) as i32;
+
+                let script = format!("Add-Type -AssemblyName System.Speech; \
+                                      $s = New-Object System.Speech.Synthesis.SpeechSynthesizer; \
+                                      {} \
+                                      $s.Rate = {}; \
+                                      $s.SetOutputToWaveFile({}); \
+                                      $s.Speak([Console]::In.ReadToEnd()); \
+                                      $s.Dispose()", select, rate, quote(outpath));
+
+                cmd.args(["-NoProfile", "-NonInteractive", "-Command", script.as_str()]);
+            },
+            _ => {
+                cmd.args(["-w", outpath, "-l", u.lang]);
+            }
+        }
+
+        let text = match self.engine {
+            /* pico2wave reads levels in percent from markup around the text */
+            TTSEngine::PICO2WAV if factor != 1.0 || u.pitch != 1.0 => {
+                format!("<speed level=\"{}\"><pitch level=\"{}\">{}</pitch></speed>",
+                        (100.0 * factor).round().clamp(20.0, 500.0) as u32,
+                        (100.0 * u.pitch).round().clamp(50.0, 200.0) as u32,
+                        u.text)
+            },
+            _ => u.text.to_string()
+        };
+
+        let input = match u.phonemes {
+            Some(ph) => {
+                match self.engine {
+                    /* espeak reads phoneme mnemonics enclosed in double brackets */
+                    TTSEngine::ESPEAK | TTSEngine::ESPEAKNG => format!("[[{}]]", ph),
+                    _ => return Err(NotifydError::new(format!("TTS engine {} does not accept phoneme input", TTS::tts_to_bin_name(&self.engine)).as_str()))
+                }
+            },
+            /* piper synthesizes every line of its input on its own */
+            None if self.engine == TTSEngine::PIPER => text.replace(['\r', '\n'], " "),
+            None => text
+        };
+
+        let mut child = cmd
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+        let mut stdin = child.stdin.take().expect("Failed to open stdin");
+
+        stdin.write_all(input.as_bytes())?;
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to read stdout");
+
+
+        if !output.status.success()
+        {
+            let err_desc = format!("{}", String::from_utf8(output.stderr).unwrap());
+            error!("Failed to run TTS engine {:?} : {}", cmd, err_desc.trim());
+            return Err(NotifydError::new(err_desc.as_str()));
+        }
+
+        Ok(used)
+    }
+}
+
+/* Where synthesized audio is written and served from under /static/ */
+pub struct AudioDir
+{
+    pub(crate) path : PathBuf,
+    /* Removed with its content on exit, unlike a configured directory */
+    pub(crate) _temp : Option<TempDir>
+}
+
+impl AudioDir
+{
+    pub(crate) fn new(dir : &Option<PathBuf>) -> Result<AudioDir, Box<dyn std::error::Error>>
+    {
+        match dir {
+            /* Sweeps and flushes delete whatever they find, they keep to a subdirectory of their own */
+            Some(d) => {
+                let own = d.join("notifyd");
+                std::fs::create_dir_all(&own)?;
+                Ok(AudioDir {
+                    path : own.canonicalize()?,
+                    _temp : None
+                })
+            },
+            None => {
+                let temp = TempDir::new("notifydtts")?;
+                Ok(AudioDir {
+                    path : temp.path().canonicalize()?,
+                    _temp : Some(temp)
+                })
+            }
+        }
+    }
+
+    pub(crate) fn path(self : &Self) -> &Path
+    {
+        &self.path
+    }
+
+    /* Remove the synthesized audio before exiting, the cache of a configured directory being kept along with the given files */
+    pub(crate) fn clean_up(self : &Self, keep : &[PathBuf]) -> std::io::Result<()>
+    {
+        if !self.path.exists()
+        {
+            return Ok(());
+        }
+
+        if self._temp.is_some()
+        {
+            return std::fs::remove_dir_all(&self.path);
+        }
+
+        for entry in read_dir(&self.path)?
+        {
+            let path = entry?.path();
+
+            if path.is_file() && !TtsCache::is_cache_file(&path) && !keep.contains(&path)
+            {
+                remove_file(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/* The engine used when nothing else is requested, switchable at runtime */
+pub struct DefaultEngine
+{
+    pub(crate) engine : TTSEngine,
+    pub(crate) enginepath : String,
+    pub(crate) voice : Option<String>
+}
+
+pub struct TTS
+{
+    pub(crate) default : RwLock<DefaultEngine>,
+    pub(crate) lang : String,
+    pub(crate) voices : HashMap<String, VoiceSpec>,
+    pub(crate) speeds : HashMap<Speed, f32>,
+    /* Languages each installed engine reported when last probed */
+    pub(crate) languages : RwLock<HashMap<TTSEngine, Vec<String>>>,
+    pub(crate) fallback_voice : Option<VoiceSpec>,
+    pub(crate) fallback_note : Option<String>,
+    pub(crate) backends : Backends,
+    /* Starts empty on reloads, the voices and models behind a spec may have changed */
+    pub(crate) cache : TtsCache,
+    /* Shared with the engines reloaded from this one, files keep being served */
+    pub(crate) tmpdir : Arc<AudioDir>
+}
+
+impl TTS
+{
+    pub(crate) fn tts_to_bin_name( engine : & TTSEngine) -> &'static str
+    {
+        match engine {
+            // This is a script calling piper
+            TTSEngine::PIPERTTS => "pipertts",
+            TTSEngine::PIPER => "piper",
+            TTSEngine::PICO2WAV => "pico2wave",
+            TTSEngine::ESPEAK => "espeak",
+            TTSEngine::ESPEAKNG =>  "espeak-ng",
+            TTSEngine::SAY => "say",
+            TTSEngine::SAPI => "powershell",
+            TTSEngine::GOOGLE => "google",
+            TTSEngine::POLLY => "polly",
+            TTSEngine::AZURE => "azure",
+            TTSEngine::AUTO => panic!("AUTO engine cannot be instanciated")
+        }
+    }
+
+    /* All concrete engines, in order of preference, local ones first */
+    pub(crate) fn all_engines() -> Vec<TTSEngine>
+    {
+        vec![TTSEngine::PIPERTTS, TTSEngine::PIPER, TTSEngine::PICO2WAV, TTSEngine::ESPEAK, TTSEngine::ESPEAKNG, TTSEngine::SAY, TTSEngine::SAPI,
+             TTSEngine::GOOGLE, TTSEngine::POLLY, TTSEngine::AZURE]
+    }
+
+    /* The backend implementing an engine, failing when it cannot be used here */
+    pub(crate) fn backend(engine : &TTSEngine, backends : &Backends) -> Result<Box<dyn TtsBackend>, Box<dyn std::error::Error>>
+    {
+        let not_configured = |section : &str| NotifydError::new(format!("TTS engine {} is not configured, add a [{}] section", TTS::tts_to_bin_name(engine), section).as_str());
+
+        match engine {
+            TTSEngine::GOOGLE => match &backends.google {
+                Some(c) => Ok(Box::new(GoogleTts::new(c)?)),
+                None => Err(not_configured("google"))
+            },
+            TTSEngine::POLLY => match &backends.polly {
+                Some(c) => Ok(Box::new(Polly::new(c)?)),
+                None => Err(not_configured("polly"))
+            },
+            TTSEngine::AZURE => match &backends.azure {
+                Some(c) => Ok(Box::new(AzureTts::new(c)?)),
+                None => Err(not_configured("azure"))
+            },
+            _ => {
+                let name = TTS::tts_to_bin_name(engine);
+
+                match which(name) {
+                    Ok(p) => Ok(Box::new(LocalEngine {
+                        engine : *engine,
+                        path : p,
+                        piper : backends.piper.clone()
+                    })),
+                    Err(_) => Err(NotifydError::new(format!("Cannot find TTS engine {} in PATH", name).as_str()))
+                }
+            }
+        }
+    }
+
+    /* Where piper voice models (.onnx) are looked up */
+    pub(crate) fn piper_voice_dir(piper : &PiperConfig) -> PathBuf
+    {
+        if let Some(d) = &piper.voices
+        {
+            return d.clone();
+        }
+
+        match env::var("PIPER_VOICES")
+        {
+            Ok(d) => PathBuf::from(d),
+            Err(_) => PathBuf::from("/opt/venvs/piper_tts/voices/")
+        }
+    }
+
+    /* A model is either a path or the name of a model of the voices directory */
+    pub(crate) fn piper_model_path(piper : &PiperConfig, model : &str) -> PathBuf
+    {
+        if model.contains('/') || model.ends_with(".onnx")
+        {
+            return PathBuf::from(model);
+        }
+
+        TTS::piper_voice_dir(piper).join(format!("{}.onnx", model))
+    }
+
+    pub fn list_engines(self : & Self) -> Vec<EngineInfo>
+    {
+        let mut ret = Vec::new();
+
+        for e in TTS::all_engines()
+        {
+            let name = TTS::tts_to_bin_name(&e);
+
+            if let Ok(backend) = TTS::backend(&e, &self.backends)
+            {
+                let (voices, err) = match backend.voices() {
+                    Ok(v) => (v, None),
+                    Err(e) => (Vec::new(), Some(e.to_string()))
+                };
+
+                ret.push(EngineInfo{
+                    engine : name.to_string(),
+                    path : backend.location(),
+                    active : e == self.engine(),
+                    voices : voices,
+                    variants : backend.variants(),
+                    err : err
+                });
+            }
+        }
+
+        ret
+    }
+
+    pub(crate) fn look_for_candidate_engine(engine : TTSEngine, backends : &Backends) -> Result<TTSEngine, Box<dyn std::error::Error>>
+    {
+        if engine != TTSEngine::AUTO
+        {
+            return Ok(engine)
+        }
+
+        for e in TTS::all_engines(){
+            match TTS::backend(&e, backends)
+            {
+                Ok(_) => return Ok(e),
+                Err(_) => {},
+            }
+        }
+
+        Err(NotifydError::new("Cannot find any binary for implementing TTS in PATH, nor any configured cloud engine"))
+    }
+
+
+    /* Remove the files untouched for max_age, but the ones to keep */
+    pub(crate) fn clean_older_files(self : & Self, max_age : Duration, keep : &[PathBuf]) -> Result<(), Box<dyn std::error::Error>>
+    {
+        for entry in read_dir(self.tmpdir.path())? {
+            let entry = entry?;
+
+            /* The cache has its own bounds */
+            if entry.file_type()?.is_file() && !TtsCache::is_cache_file(&entry.path()) && !keep.contains(&entry.path()) {
+                let metadata = entry.metadata()?;
+                let modified_time = metadata.modified()?;
+
+                if modified_time <= (std::time::SystemTime::now() - max_age)
+                {
+                    let file_path = entry.path();
+                    remove_file(&file_path)?;
+                    debug!("Deleted: {:?}", file_path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+
+    pub(crate) fn engine(self : & Self) -> TTSEngine
+    {
+        self.default.read().unwrap().engine
+    }
+
+    pub(crate) fn default_spec(self : & Self) -> VoiceSpec
+    {
+        let d = self.default.read().unwrap();
+
+        VoiceSpec {
+            engine : d.engine,
+            voice : d.voice.clone()
+        }
+    }
+
+    /* Make another installed engine (and optionally voice) the default one */
+    pub fn switch_engine(self : & Self, spec : VoiceSpec) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let name = TTS::tts_to_bin_name(&spec.engine);
+        let backend = TTS::backend(&spec.engine, &self.backends)?;
+
+        /* Make sure it actually runs before relying on it */
+        let voices = backend.voices()?;
+        self.languages.write().unwrap().insert(spec.engine, voices.into_iter().map(|v| v.lang).collect());
+
+        let mut d = self.default.write().unwrap();
+        d.engine = spec.engine;
+        d.enginepath = backend.location();
+        d.voice = spec.voice;
+
+        info!("Switched TTS engine to {}", name);
+        Ok(())
+    }
+
+    /* Languages are compared loosely: "fr-FR" is spoken by a "fr" voice and conversely */
+    pub(crate) fn supports(self : & Self, engine : &TTSEngine, lang : &str) -> bool
+    {
+        let norm = |l : &str| l.to_lowercase().replace('_', "-");
+        let wanted = norm(lang);
+        let wanted_primary = wanted.split('-').next().unwrap_or("").to_string();
+
+        match self.languages.read().unwrap().get(engine)
+        {
+            /* Nothing was probed, we cannot tell */
+            None => true,
+            Some(l) if l.is_empty() => true,
+            Some(l) => l.iter().map(|x| norm(x)).any(|x| {
+                x == wanted || x == wanted_primary || x.split('-').next() == Some(wanted_primary.as_str())
+            })
+        }
+    }
+
+    /* Pick the engine and voice for a language, honouring the configured mapping
+       and the voice explicitly requested, if any */
+    pub(crate) fn resolve_voice(self : & Self, lang : &str, voice : Option<&String>) -> Result<VoiceSpec, Box<dyn std::error::Error>>
+    {
+        let mut spec = match self.voices.get(lang)
+        {
+            Some(v) => v.clone(),
+            None => self.default_spec()
+        };
+
+        match voice {
+            Some(v) if v.contains(':') => {
+                spec = VoiceSpec::parse(v)?;
+            },
+            Some(v) => {
+                spec.voice = Some(v.clone());
+            },
+            None => {}
+        }
+
+        Ok(spec)
+    }
+
+    /* Synthesize every segment and stitch them into a single WAV */
+    pub(crate) fn speak_segments_to_file(self : & Self, params : &SpeechParams) -> Result<TtsSentence, Box<dyn std::error::Error>>
+    {
+        let mut parts : Vec<TtsSentence> = Vec::new();
+
+        for seg in &params.segments
+        {
+            parts.push(self.speak_to_file(seg.text.clone(), &params.for_segment(seg))?);
+        }
+
+        let mut audio = PcmAudio::load(&parts[0].path)?;
+
+        for p in &parts[1..]
+        {
+            audio.append(&PcmAudio::load(&p.path)?);
+        }
+
+        let text = params.full_text(String::new())?;
+        let digest = md5(format!("{}{}", text, now_in_usecs()));
+        let outfile = self.tmpdir.path().join(format!("{:x}.wav", digest));
+        let outpath = outfile.to_string_lossy().to_string();
+
+        audio.save(&outpath)?;
+
+        for p in &parts
+        {
+            let _ = p.delete();
+        }
+
+        let voices : Vec<&str> = parts.iter().map(|p| p.voice.as_str()).collect();
+
+        Ok(TtsSentence::new(&outpath, &text, parts[0].engine, voices.join(", ").as_str()))
+    }
+
+    /* Decode and store a client supplied audio clip */
+    pub(crate) fn store_attachment(self : & Self, b64 : &str, max_bytes : usize) -> Result<PathBuf, Box<dyn std::error::Error>>
+    {
+        let (content, ext) = TTS::decode_attachment(b64, max_bytes)?;
+        self.store_clip(&content, ext)
+    }
+
+    /* Keep audio obtained elsewhere in the tmpdir, where cast devices can fetch it */
+    pub(crate) fn store_clip(self : & Self, content : &[u8], ext : &str) -> Result<PathBuf, Box<dyn std::error::Error>>
+    {
+        let digest = md5(format!("{}{}", content.len(), now_in_usecs()));
+        let path = self.tmpdir.path().join(format!("{:x}.{}", digest, ext));
+
+        File::create(&path)?.write_all(content)?;
+
+        Ok(path)
+    }
+
+    /* Content and format of a base64 encoded audio clip */
+    pub(crate) fn decode_attachment(b64 : &str, max_bytes : usize) -> Result<(Vec<u8>, &'static str), Box<dyn std::error::Error>>
+    {
+        let b64 : String = b64.chars().filter(|c| !c.is_whitespace()).collect();
+
+        /* Every 4 base64 characters carry 3 bytes */
+        if b64.len() / 4 * 3 > max_bytes + 2
+        {
+            return Err(NotifydError::new(format!("Audio attachment exceeds {} bytes", max_bytes).as_str()));
+        }
+
+        let content = base64::engine::general_purpose::STANDARD.decode(b64.as_bytes())?;
+
+        if content.len() > max_bytes
+        {
+            return Err(NotifydError::new(format!("Audio attachment exceeds {} bytes", max_bytes).as_str()));
+        }
+
+        let ext = match sniff_audio(&content) {
+            Some(e) => e,
+            None => return Err(NotifydError::new("Audio attachment is not WAV, MP3, OGG or FLAC"))
+        };
+
+        Ok((content, ext))
+    }
+
+    pub(crate) fn tmpdir_usage(self : & Self) -> u64
+    {
+        let entries = match read_dir(self.tmpdir.path()) {
+            Ok(e) => e,
+            Err(_) => return 0
+        };
+
+        entries.filter_map(|e| e.ok())
+               .filter_map(|e| e.metadata().ok())
+               .filter(|m| m.is_file())
+               .map(|m| m.len())
+               .sum()
+    }
+
+    /* Remove every file of the tmpdir but the ones to keep, returns (files, bytes) removed */
+    pub(crate) fn flush(self : & Self, keep : &[PathBuf]) -> Result<(u64, u64), Box<dyn std::error::Error>>
+    {
+        let mut files = 0;
+        let mut bytes = 0;
+
+        for entry in read_dir(self.tmpdir.path())? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if !entry.file_type()?.is_file() || keep.contains(&path)
+            {
+                continue;
+            }
+
+            let len = entry.metadata()?.len();
+
+            if remove_file(&path).is_ok()
+            {
+                files += 1;
+                bytes += len;
+            }
+        }
+
+        info!("Flushed {} files ({} bytes)", files, bytes);
+        Ok((files, bytes))
+    }
+
+    pub fn speak_to_file(self :& Self, text : String, params : &SpeechParams) -> Result<TtsSentence, Box<dyn std::error::Error>>
+    {
+        if !params.segments.is_empty()
+        {
+            return self.speak_segments_to_file(params);
+        }
+
+        let to_hash = format!("{}{}", text, now_in_usecs());
+        let digest = md5(to_hash);
+        let outfile = self.tmpdir.path().join(format!("{}.wav", format!("{:x}", digest)));
+        let outpath: &str = outfile.to_str().expect("Failed to convert path to str");
+
+        let mut lang = params.lang.clone().unwrap_or(self.lang.clone());
+        let mut spec = self.resolve_voice(&lang, params.voice.as_ref())?;
+        let mut text = text;
+        let mut fallback = false;
+
+        /* Nobody can speak the requested language, use the fallback voice instead of failing */
+        if params.lang.is_some() && params.voice.is_none() && !self.voices.contains_key(&lang) && !self.supports(&spec.engine, &lang)
+        {
+            warn!("No engine supports language {}, using fallback voice", lang);
+
+            if let Some(note) = &self.fallback_note
+            {
+                text = format!("{} {}", note.replace("{lang}", &lang), text);
+            }
+
+            spec = self.fallback_voice.clone().unwrap_or(self.default_spec());
+            lang = self.lang.clone();
+            fallback = true;
+        }
+
+        let factor = match (params.rate, params.speed) {
+            (Some(r), _) => r,
+            (None, Some(sp)) => *self.speeds.get(&sp).unwrap_or(&sp.default_factor()),
+            (None, None) => 1.0
+        };
+
+        params.check_levels()?;
+        let (pitch, gain) = (params.pitch.unwrap_or(1.0), params.gain.unwrap_or(1.0));
+
+        /* Everything the audio depends on */
+        let key = format!("{:x}", md5(format!("{:?}|{:?}|{}|{:?}|{:?}|{}|{}|{}|{}",
+                                               spec.engine, spec.voice, lang, params.phonemes, params.variant,
+                                               factor, pitch, gain, text)));
+
+        if let Some((path, voice)) = self.cache.lookup(&key)
+        {
+            let mut sentence = TtsSentence::new(path.to_string_lossy().as_ref(), text.as_str(), spec.engine, voice.as_str());
+            sentence.fallback = fallback;
+            sentence.cached = true;
+            return Ok(sentence);
+        }
+
+        let backend = TTS::backend(&spec.engine, &self.backends)?;
+
+        let voice = backend.synthesize(&Utterance {
+            text : &text,
+            phonemes : params.phonemes.as_deref(),
+            lang : &lang,
+            voice : spec.voice.as_deref(),
+            variant : params.variant.as_deref(),
+            factor : factor,
+            pitch : pitch,
+            gain : gain,
+            outpath : outpath
+        })?;
+
+        if gain != 1.0 && !backend.applies_gain()
+        {
+            let mut audio = PcmAudio::load(outpath)?;
+            audio.scale(gain);
+            audio.save(outpath)?;
+        }
+
+        let mut sentence = TtsSentence::new(outpath, text.as_str(), spec.engine, voice.as_str());
+        sentence.fallback = fallback;
+
+        if let Some(path) = self.cache.store(&key, &outfile, &voice)
+        {
+            sentence.path = path.to_string_lossy().to_string();
+            sentence.cached = true;
+        }
+
+        Ok(sentence)
+    }
+
+    pub(crate) fn get_locale_from_env() -> String
+    {
+        match env::var("LANG")
+        {
+            Ok(l) => {
+                if l.find(".").is_some()
+                {
+                    match l.split(".").next()
+                    {
+                        Some(e) => return e.to_string().replace("_", "-"),
+                        None => panic!("Failed to split on '.'")
+                    }
+                }
+                return l.replace("_", "-")
+            },
+            Err(_) => return String::from("en-US")
+        }
+    }
+
+    pub fn new(engine : TTSEngine, lang : Option<String>, config : &Config) -> Result<TTS, Box<dyn std::error::Error>>
+    {
+        TTS::with_tmpdir(engine, lang, config, Arc::new(AudioDir::new(&config.static_dir)?))
+    }
+
+    /* A fresh engine for a new configuration, keeping the language and the files of this one */
+    pub(crate) fn reload(self : & Self, config : &Config) -> Result<TTS, Box<dyn std::error::Error>>
+    {
+        TTS::with_tmpdir(TTSEngine::AUTO, Some(self.lang.clone()), config, Arc::clone(&self.tmpdir))
+    }
+
+    pub(crate) fn with_tmpdir(engine : TTSEngine, lang : Option<String>, config : &Config, tmp_dir : Arc<AudioDir>) -> Result<TTS, Box<dyn std::error::Error>>
+    {
+        let backends = Backends::new(config);
+
+        /* The configured engine is used unless another one is asked for */
+        let (engine, default_voice) = match (&config.engine, engine) {
+            (Some(desc), TTSEngine::AUTO) => {
+                let spec = VoiceSpec::parse(desc)?;
+                (spec.engine, spec.voice)
+            },
+            _ => (engine, None)
+        };
+
+        /* Without any engine we can still beep, synthesis simply fails */
+        let engine_to_use = match TTS::look_for_candidate_engine(engine, &backends) {
+            Ok(e) => e,
+            Err(e) => {
+                if !config.beep.enabled
+                {
+                    return Err(e);
+                }
+                TTSEngine::ESPEAK
+            }
+        };
+        let engine_binary_name = String::from(TTS::tts_to_bin_name(&engine_to_use));
+
+        let enginepath : String;
+
+        match TTS::backend(&engine_to_use, &backends)
+        {
+            Ok(b) => enginepath = b.location(),
+            Err(e) => {
+                if !config.beep.enabled
+                {
+                    panic!("{}", e);
+                }
+                warn!("{}, notifications will beep", e);
+                enginepath = engine_binary_name.clone();
+            }
+        }
+
+        let mut locale;
+
+        match lang
+        {
+            Some (a) => {
+                locale = a;
+            }
+            None => {
+                locale = TTS::get_locale_from_env();
+
+                if locale == "C"
+                {
+                    locale = String::from("us-US");
+                }
+            }
+        }
+
+        info!("Using TTS engine {}", engine_binary_name);
+
+        let mut voice_map = HashMap::new();
+
+        for (l, desc) in &config.voices
+        {
+            let spec = VoiceSpec::parse(desc)?;
+
+            if let Err(e) = TTS::backend(&spec.engine, &backends)
+            {
+                return Err(NotifydError::new(format!("Voice for {} cannot be used : {}", l, e).as_str()));
+            }
+
+            info!("Voice for {} : {}", l, desc);
+            voice_map.insert(l.clone(), spec);
+        }
+
+        let fallback_voice = match &config.fallback_voice {
+            Some(v) => Some(VoiceSpec::parse(v)?),
+            None => None
+        };
+
+        let mut languages = HashMap::new();
+
+        /* Spawning every engine is slow and memory hungry on small boards,
+           without probing every language is assumed to be supported */
+        for e in TTS::all_engines().into_iter().filter(|_| !config.low_footprint)
+        {
+            if let Ok(backend) = TTS::backend(&e, &backends)
+            {
+                if let Ok(v) = backend.voices()
+                {
+                    languages.insert(e, v.into_iter().map(|v| v.lang).collect::<Vec<String>>());
+                }
+            }
+        }
+
+        return Ok(TTS { default : RwLock::new(DefaultEngine {
+                            engine : engine_to_use,
+                            enginepath: enginepath,
+                            voice : default_voice
+                        }),
+                        lang : locale,
+                        voices : voice_map,
+                        speeds : config.speeds.clone(),
+                        languages : RwLock::new(languages),
+                        fallback_voice : fallback_voice,
+                        fallback_note : config.fallback_note.clone(),
+                        backends : backends,
+                        cache : TtsCache::new(&config.cache, tmp_dir.path()),
+                        tmpdir: tmp_dir
+                     })
+    }
+
+}
+
+/*************
+ * TTS CACHE *
+ *************/
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CacheConfig
+{
+    pub(crate) enabled : bool,
+    /* Least recently used entries are evicted beyond this size */
+    pub(crate) max_bytes : u64,
+    /* Seconds an entry is kept after its last use */
+    pub(crate) max_age : u64
+}
+
+impl Default for CacheConfig
+{
+    fn default() -> Self
+    {
+        CacheConfig {
+            enabled : true,
+            max_bytes : 64 * 1024 * 1024,
+            max_age : 7 * 24 * 3600
+        }
+    }
+}
+
+/* Synthesized audio reused for identical requests, stored as cache-<hash>.wav
+   in the tmpdir, the modification time telling when an entry was last used */
+pub struct TtsCache
+{
+    pub(crate) config : CacheConfig,
+    pub(crate) dir : PathBuf,
+    /* Voice each entry was spoken with */
+    pub(crate) voices : Mutex<HashMap<String, String>>
+}
+
+impl TtsCache
+{
+    pub(crate) fn new(config : &CacheConfig, dir : &Path) -> TtsCache
+    {
+        TtsCache {
+            config : config.clone(),
+            dir : dir.to_path_buf(),
+            voices : Mutex::new(HashMap::new())
+        }
+    }
+
+    pub(crate) fn is_cache_file(path : &Path) -> bool
+    {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        name.starts_with("cache-") && name.ends_with(".wav")
+    }
+
+    pub(crate) fn path_of(self : &Self, key : &str) -> PathBuf
+    {
+        self.dir.join(format!("cache-{}.wav", key))
+    }
+
+    /* The file and voice of an entry, which becomes the most recently used */
+    pub(crate) fn lookup(self : &Self, key : &str) -> Option<(PathBuf, String)>
+    {
+        if !self.config.enabled
+        {
+            return None;
+        }
+
+        let mut voices = self.voices.lock().unwrap();
+        let voice = voices.get(key)?.clone();
+        let path = self.path_of(key);
+
+        /* Flushed or evicted behind our back */
+        match File::options().write(true).open(&path) {
+            Ok(f) => {
+                let _ = f.set_modified(std::time::SystemTime::now());
+                Some((path, voice))
+            },
+            Err(_) => {
+                voices.remove(key);
+                None
+            }
+        }
+    }
+
+    /* Move a freshly synthesized file into the cache, returning its new path */
+    pub(crate) fn store(self : &Self, key : &str, file : &Path, voice : &str) -> Option<PathBuf>
+    {
+        if !self.config.enabled
+        {
+            return None;
+        }
+
+        let path = self.path_of(key);
+
+        if let Err(e) = std::fs::rename(file, &path)
+        {
+            warn!("Failed to cache {} : {}", file.to_string_lossy(), e);
+            return None;
+        }
+
+        self.voices.lock().unwrap().insert(key.to_string(), voice.to_string());
+        self.evict(std::slice::from_ref(&path));
+
+        Some(path)
+    }
+
+    /* Drop the entries unused for too long, then the least recently used ones
+       until the cache fits, never the ones to keep */
+    pub(crate) fn evict(self : &Self, keep : &[PathBuf])
+    {
+        let entries = match read_dir(&self.dir) {
+            Ok(e) => e,
+            Err(_) => return
+        };
+
+        let mut files : Vec<(std::time::SystemTime, u64, PathBuf)> = entries.filter_map(|e| e.ok())
+                                                                           .map(|e| e.path())
+                                                                           .filter(|p| TtsCache::is_cache_file(p) && !keep.contains(p))
+                                                                           .filter_map(|p| {
+                                                                               let m = p.metadata().ok()?;
+                                                                               Some((m.modified().ok()?, m.len(), p))
+                                                                           })
+                                                                           .collect();
+        files.sort();
+
+        let max_age = Duration::from_secs(self.config.max_age);
+        let mut total : u64 = files.iter().map(|f| f.1).sum::<u64>()
+                                 + keep.iter().filter(|p| TtsCache::is_cache_file(p)).filter_map(|p| p.metadata().ok()).map(|m| m.len()).sum::<u64>();
+
+        for (modified, len, path) in files
+        {
+            let expired = modified.elapsed().map(|e| e > max_age).unwrap_or(false);
+
+            if !expired && total <= self.config.max_bytes
+            {
+                break;
+            }
+
+            if remove_file(&path).is_ok()
+            {
+                total -= len;
+                let key = path.file_stem().unwrap_or_default().to_string_lossy().trim_start_matches("cache-").to_string();
+                self.voices.lock().unwrap().remove(&key);
+            }
+        }
+    }
+}
+
+/*******************
+ * ENGINE DISPATCH *
+ *******************/
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DispatchStrategy
+{
+    /* Use the voice configured for the language */
+    #[default]
+    Default,
+    /* Use the engine with the fewest syntheses in flight */
+    LeastBusy,
+    /* Run the best and the fastest engines together, keeping the best one
+       when it is done within the latency budget */
+    Race
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DispatchConfig
+{
+    /* Engines to spread syntheses over, best quality first ("engine" or "engine:voice") */
+    pub(crate) engines : Vec<String>,
+    pub(crate) strategies : HashMap<Priority, DispatchStrategy>,
+    pub(crate) latency_budget_ms : u64
+}
+
+impl Default for DispatchConfig
+{
+    fn default() -> Self
+    {
+        DispatchConfig {
+            engines : Vec::new(),
+            strategies : HashMap::new(),
+            latency_budget_ms : 1500
+        }
+    }
+}
+
+pub type RaceResult = (usize, Result<TtsSentence, String>);
+
+pub struct Dispatcher
+{
+    pub(crate) specs : Vec<VoiceSpec>,
+    pub(crate) strategies : HashMap<Priority, DispatchStrategy>,
+    pub(crate) budget : Duration,
+    pub(crate) busy : Arc<Mutex<HashMap<TTSEngine, usize>>>
+}
+
+impl Dispatcher
+{
+    pub(crate) fn new(config : &DispatchConfig) -> Result<Dispatcher, Box<dyn std::error::Error>>
+    {
+        let mut specs = Vec::new();
+
+        for e in &config.engines
+        {
+            specs.push(VoiceSpec::parse(e)?);
+        }
+
+        Ok(Dispatcher {
+            specs : specs,
+            strategies : config.strategies.clone(),
+            budget : Duration::from_millis(config.latency_budget_ms),
+            busy : Arc::new(Mutex::new(HashMap::new()))
+        })
+    }
+
+    pub(crate) fn params_for(params : &SpeechParams, spec : &VoiceSpec) -> SpeechParams
+    {
+        let mut p = params.clone();
+        p.voice = Some(format!("{}:{}", TTS::tts_to_bin_name(&spec.engine), spec.voice.clone().unwrap_or_default()));
+        p
+    }
+
+    pub(crate) fn speak_counted(busy : &Mutex<HashMap<TTSEngine, usize>>, tts : &TTS, text : String, params : &SpeechParams, engine : TTSEngine) -> Result<TtsSentence, Box<dyn std::error::Error>>
+    {
+        *busy.lock().unwrap().entry(engine).or_insert(0) += 1;
+        let ret = tts.speak_to_file(text, params);
+        *busy.lock().unwrap().entry(engine).or_insert(1) -= 1;
+        ret
+    }
+
+    pub(crate) fn synthesize(self : &Self, tts : &Arc<TTS>, text : String, params : &SpeechParams) -> Result<TtsSentence, Box<dyn std::error::Error>>
+    {
+        let strategy = self.strategies.get(&params.priority.unwrap_or(Priority::Normal)).copied().unwrap_or_default();
+
+        /* Explicit voices and segments are left alone */
+        if strategy == DispatchStrategy::Default || params.voice.is_some() || !params.segments.is_empty()
+        {
+            return tts.speak_to_file(text, params);
+        }
+
+        let lang = params.lang.clone().unwrap_or(tts.lang.clone());
+        let candidates : Vec<&VoiceSpec> = self.specs.iter()
+                                               .filter(|s| which(TTS::tts_to_bin_name(&s.engine)).is_ok())
+                                               .filter(|s| s.voice.is_some() || tts.supports(&s.engine, &lang))
+                                               .collect();
+
+        if candidates.is_empty()
+        {
+            return tts.speak_to_file(text, params);
+        }
+
+        match strategy {
+            DispatchStrategy::LeastBusy => {
+                let spec = {
+                    let busy = self.busy.lock().unwrap();
+                    *candidates.iter().min_by_key(|s| busy.get(&s.engine).copied().unwrap_or(0)).unwrap()
+                };
+                Dispatcher::speak_counted(&self.busy, tts, text, &Dispatcher::params_for(params, spec), spec.engine)
+            },
+            DispatchStrategy::Race if candidates.len() > 1 => {
+                self.race(tts, text, params, candidates[0], candidates[candidates.len() - 1])
+            },
+            _ => {
+                Dispatcher::speak_counted(&self.busy, tts, text, &Dispatcher::params_for(params, candidates[0]), candidates[0].engine)
+            }
+        }
+    }
+
+    pub(crate) fn race(self : &Self, tts : &Arc<TTS>, text : String, params : &SpeechParams, best : &VoiceSpec, fast : &VoiceSpec) -> Result<TtsSentence, Box<dyn std::error::Error>>
+    {
+        let (tx, rx) = channel::<RaceResult>();
+
+        for (rank, spec) in [(0, best), (1, fast)]
+        {
+            let tx = tx.clone();
+            let tts = Arc::clone(tts);
+            let busy = Arc::clone(&self.busy);
+            let text = text.clone();
+            let params = Dispatcher::params_for(params, spec);
+            let engine = spec.engine;
+
+            std::thread::spawn(move || {
+                let ret = Dispatcher::speak_counted(&busy, &tts, text, &params, engine);
+                let _ = tx.send((rank, ret.map_err(|e| e.to_string())));
+            });
+        }
+        drop(tx);
+
+        let deadline = Instant::now() + self.budget;
+        let mut results : [Option<Result<TtsSentence, String>>; 2] = [None, None];
+
+        let winner = loop {
+            let over_budget = Instant::now() >= deadline;
+
+            match (&results[0], &results[1]) {
+                (Some(Ok(_)), _) => break 0,
+                (Some(Err(_)), Some(Ok(_))) => break 1,
+                (None, Some(Ok(_))) if over_budget => break 1,
+                (Some(Err(e)), Some(Err(_))) => return Err(NotifydError::new(e)),
+                _ => {}
+            }
+
+            let wait = if over_budget { Duration::from_secs(3600) } else { deadline - Instant::now() };
+
+            match rx.recv_timeout(wait) {
+                Ok((rank, r)) => {
+                    results[rank] = Some(r);
+                },
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {},
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(NotifydError::new("Synthesis threads are gone"));
+                }
+            }
+        };
+
+        /* Do not leave the audio of the loser behind */
+        match results[1 - winner].take() {
+            Some(Ok(s)) => {
+                let _ = s.delete();
+            },
+            Some(Err(_)) => {},
+            None => {
+                std::thread::spawn(move || {
+                    for (_, r) in rx
+                    {
+                        if let Ok(s) = r
+                        {
+                            let _ = s.delete();
+                        }
+                    }
+                });
+            }
+        }
+
+        match results[winner].take() {
+            Some(Ok(s)) => Ok(s),
+            _ => Err(NotifydError::new("Race ended without audio"))
+        }
+    }
+}
+
+/********************
+ * SPEECH STREAMING *
+ ********************/
+
+/* Split a long text at sentence boundaries so that it can be synthesized piecewise */
+pub fn split_sentences(text : &str) -> Vec<String>
+{
+    let mut ret = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next()
+    {
+        current.push(c);
+
+        let boundary = matches!(c, '.' | '!' | '?' | ';' | '\n')
+                        && chars.peek().map(|n| n.is_whitespace()).unwrap_or(true);
+
+        if boundary && !current.trim().is_empty()
+        {
+            ret.push(current.trim().to_string());
+            current.clear();
+        }
+    }
+
+    if !current.trim().is_empty()
+    {
+        ret.push(current.trim().to_string());
+    }
+
+    ret
+}
+
+/* WAV stream of unknown length, each piece being synthesized when the client reads past the previous one */
+pub struct SpeechStream
+{
+    pub(crate) tts : Arc<TTS>,
+    pub(crate) pieces : VecDeque<(String, SpeechParams)>,
+    pub(crate) format : Option<(u32, u16)>,
+    pub(crate) buffer : Vec<u8>,
+    pub(crate) pos : usize,
+    pub(crate) _slot : SlotGuard
+}
+
+impl SpeechStream
+{
+    pub(crate) fn new(tts : Arc<TTS>, text : String, params : &SpeechParams, slot : SlotGuard) -> SpeechStream
+    {
+        let pieces : VecDeque<(String, SpeechParams)> = if !params.segments.is_empty()
+        {
+            params.segments.iter().map(|s| (s.text.clone(), params.for_segment(s))).collect()
+        }
+        else if params.phonemes.is_some()
+        {
+            /* Phonemes cannot be matched with sentences of the text */
+            VecDeque::from(vec![(text, params.clone())])
+        }
+        else
+        {
+            split_sentences(&text).into_iter().map(|t| (t, params.clone())).collect()
+        };
+
+        SpeechStream {
+            tts : tts,
+            pieces : pieces,
+            format : None,
+            buffer : Vec::new(),
+            pos : 0,
+            _slot : slot
+        }
+    }
+
+    pub(crate) fn next_piece(self : &mut Self, text : String, params : &SpeechParams) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let sentence = self.tts.speak_to_file(text, params)?;
+        let pcm = PcmAudio::load(&sentence.path);
+        let _ = sentence.delete();
+        let pcm = pcm?;
+
+        self.buffer.clear();
+        self.pos = 0;
+
+        let (rate, channels) = match self.format {
+            Some(f) => f,
+            None => {
+                /* The first piece decides the format of the whole stream */
+                self.buffer = PcmAudio::wav_header(pcm.rate, pcm.channels, u32::MAX);
+                self.format = Some((pcm.rate, pcm.channels));
+                (pcm.rate, pcm.channels)
+            }
+        };
+
+        self.buffer.extend(pcm.convert(rate, channels).pcm_bytes());
+        Ok(())
+    }
+}
+
+impl Read for SpeechStream
+{
+    fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize>
+    {
+        while self.pos >= self.buffer.len()
+        {
+            let (text, params) = match self.pieces.pop_front() {
+                Some(p) => p,
+                None => return Ok(0)
+            };
+
+            if let Err(e) = self.next_piece(text, &params)
+            {
+                /* Abort the transfer so that the client does not mistake it for a complete one */
+                error!("Streaming synthesis failed : {}", e);
+                return Err(std::io::Error::other(e.to_string()));
+            }
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
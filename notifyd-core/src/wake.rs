@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::UdpSocket;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::cast::CastClient;
+use crate::caster::Caster;
+use crate::error::NotifydError;
+
+/******************
+ * WAKING DEVICES *
+ ******************/
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum WakeMethod
+{
+    /* Launch the media receiver ahead of time, which powers TVs on through their own CEC */
+    #[default]
+    Cast,
+    /* Power the TV on with cec-client from the machine plugged to it */
+    Cec,
+    /* Send a Wake-on-LAN magic packet */
+    Wol
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WakeConfig
+{
+    pub(crate) method : WakeMethod,
+    /* Logical address cec-client powers on, "0" being the TV */
+    pub(crate) cec_address : String,
+    /* MAC address of the device for Wake-on-LAN */
+    pub(crate) mac : Option<String>,
+    /* Where the magic packet is sent */
+    pub(crate) broadcast : String,
+    /* Time given to the device to come up before casting */
+    pub(crate) delay_ms : u64,
+    /* A device woken or cast to this recently is not woken again */
+    pub(crate) awake_secs : u64
+}
+
+impl Default for WakeConfig
+{
+    fn default() -> Self
+    {
+        WakeConfig {
+            method : WakeMethod::Cast,
+            cec_address : String::from("0"),
+            mac : None,
+            broadcast : String::from("255.255.255.255:9"),
+            delay_ms : 2000,
+            awake_secs : 300
+        }
+    }
+}
+
+/* 6 bytes of 0xFF followed by the MAC address 16 times */
+pub(crate) fn magic_packet(mac : &str) -> Result<Vec<u8>, Box<dyn std::error::Error>>
+{
+    let bytes : Vec<u8> = mac.split([':', '-'])
+                             .map(|b| u8::from_str_radix(b, 16))
+                             .collect::<Result<_, _>>()
+                             .map_err(|_| NotifydError::new(format!("Invalid MAC address {}", mac).as_str()))?;
+
+    if bytes.len() != 6
+    {
+        return Err(NotifydError::new(format!("Invalid MAC address {}", mac).as_str()));
+    }
+
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16
+    {
+        packet.extend_from_slice(&bytes);
+    }
+
+    Ok(packet)
+}
+
+impl WakeConfig
+{
+    pub(crate) fn check(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        match (self.method, &self.mac) {
+            (WakeMethod::Wol, None) => Err(NotifydError::new("Wake-on-LAN needs the 'mac' of the device")),
+            (WakeMethod::Wol, Some(mac)) => magic_packet(mac).map(|_| ()),
+            _ => Ok(())
+        }
+    }
+
+    /* Wake the device and give it time to come up */
+    pub(crate) fn wake(self : &Self, uid : &str) -> Result<(), Box<dyn std::error::Error>>
+    {
+        match self.method {
+            WakeMethod::Cast => {
+                CastClient::connect(&Caster::find(uid)?.addr)?.launch_media_receiver()?;
+            },
+            WakeMethod::Cec => {
+                let mut child = Command::new("cec-client")
+                                    .args(["-s", "-d", "1"])
+                                    .stdin(Stdio::piped())
+                                    .stdout(Stdio::null())
+                                    .spawn()?;
+
+                let mut stdin = child.stdin.take().expect("Failed to open stdin");
+                stdin.write_all(format!("on {}\n", self.cec_address).as_bytes())?;
+                drop(stdin);
+
+                let status = child.wait()?;
+
+                if !status.success()
+                {
+                    return Err(NotifydError::new(format!("cec-client exited with {}", status).as_str()));
+                }
+            },
+            WakeMethod::Wol => {
+                let packet = magic_packet(self.mac.as_deref().unwrap_or_default())?;
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.set_broadcast(true)?;
+                socket.send_to(&packet, &self.broadcast)?;
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(self.delay_ms));
+
+        Ok(())
+    }
+}
+
+/* When each device was last known awake */
+pub struct Waker
+{
+    pub(crate) awake : Mutex<HashMap<String, Instant>>
+}
+
+impl Waker
+{
+    pub(crate) fn new() -> Waker
+    {
+        Waker {
+            awake : Mutex::new(HashMap::new())
+        }
+    }
+
+    pub(crate) fn is_awake(self : &Self, uid : &str, within : Duration) -> bool
+    {
+        self.awake.lock().unwrap().get(uid).is_some_and(|t| t.elapsed() < within)
+    }
+
+    pub(crate) fn mark_awake(self : &Self, uid : &str)
+    {
+        self.awake.lock().unwrap().insert(uid.to_string(), Instant::now());
+    }
+}
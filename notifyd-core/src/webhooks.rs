@@ -0,0 +1,257 @@
+use serde::{Serialize, Deserialize};
+use crate::error::NotifydError;
+use crate::queue::Priority;
+
+/********************
+ * GENERIC WEBHOOKS *
+ ********************/
+
+/* Largest body accepted from a webhook */
+pub const WEBHOOK_MAX_PAYLOAD : u64 = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct WebhookConfig
+{
+    /* JSONPath of the text, e.g. "$.alerts[*].annotations.summary" */
+    pub(crate) path : Option<String>,
+    /* Handlebars template rendered from the body, e.g. "{{#each alerts}}{{labels.alertname}} is {{status}}. {{/each}}" */
+    pub(crate) template : Option<String>,
+    pub(crate) target : Option<String>,
+    pub(crate) priority : Option<Priority>,
+    /* Topic whose defaults apply to the notifications */
+    pub(crate) topic : Option<String>
+}
+
+pub enum TemplateNode
+{
+    Text(String),
+    Value(String),
+    Each(String, Vec<TemplateNode>),
+    If(String, Vec<TemplateNode>, Vec<TemplateNode>)
+}
+
+/* Keys and indices of a path, "$.a[0]['b c']" and "a.0.b c" alike */
+pub(crate) fn path_segments(path : &str) -> Vec<String>
+{
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut chars = path.trim().trim_start_matches('$').chars();
+
+    while let Some(c) = chars.next()
+    {
+        match c {
+            '.' | '[' => {
+                if !current.is_empty()
+                {
+                    segments.push(std::mem::take(&mut current));
+                }
+
+                if c == '['
+                {
+                    let inner : String = chars.by_ref().take_while(|c| *c != ']').collect();
+                    segments.push(inner.trim_matches(['\'', '"']).to_string());
+                }
+            },
+            c => current.push(c)
+        }
+    }
+
+    if !current.is_empty()
+    {
+        segments.push(current);
+    }
+
+    segments
+}
+
+/* Values at the path, "*" standing for every element */
+pub(crate) fn select<'a>(value : &'a serde_json::Value, path : &str) -> Vec<&'a serde_json::Value>
+{
+    let mut found = vec![value];
+
+    for segment in path_segments(path)
+    {
+        found = found.into_iter().flat_map(|v| -> Vec<&serde_json::Value> {
+            match (v, segment.as_str()) {
+                (serde_json::Value::Array(a), "*") => a.iter().collect(),
+                (serde_json::Value::Object(m), "*") => m.values().collect(),
+                (serde_json::Value::Array(a), s) => s.parse::<usize>().ok().and_then(|i| a.get(i)).into_iter().collect(),
+                (serde_json::Value::Object(m), s) => m.get(s).into_iter().collect(),
+                _ => Vec::new()
+            }
+        }).collect();
+    }
+
+    found
+}
+
+/* Spoken form of a value, arrays being listed */
+pub(crate) fn spoken(value : &serde_json::Value) -> String
+{
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(a) => a.iter().map(spoken).filter(|s| !s.is_empty()).collect::<Vec<String>>().join(", "),
+        v => v.to_string()
+    }
+}
+
+pub(crate) fn truthy(value : &serde_json::Value) -> bool
+{
+    match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::Bool(b) => *b,
+        serde_json::Value::String(s) => !s.is_empty(),
+        serde_json::Value::Number(n) => n.as_f64() != Some(0.0),
+        serde_json::Value::Array(a) => !a.is_empty(),
+        serde_json::Value::Object(_) => true
+    }
+}
+
+/* Nodes up to the next "else" or closing tag, which is returned along */
+fn parse_nodes(tags : &[(bool, String)], pos : &mut usize) -> Result<(Vec<TemplateNode>, Option<String>), Box<dyn std::error::Error>>
+{
+    let mut nodes = Vec::new();
+
+    while *pos < tags.len()
+    {
+        let (is_tag, content) = &tags[*pos];
+        *pos += 1;
+
+        if !is_tag
+        {
+            nodes.push(TemplateNode::Text(content.clone()));
+            continue;
+        }
+
+        if content == "else" || content.starts_with('/')
+        {
+            return Ok((nodes, Some(content.clone())));
+        }
+
+        if let Some(path) = content.strip_prefix("#each ")
+        {
+            match parse_nodes(tags, pos)? {
+                (body, Some(end)) if end == "/each" => nodes.push(TemplateNode::Each(path.trim().to_string(), body)),
+                _ => return Err(NotifydError::new(format!("{{{{#each {}}}}} is not closed by {{{{/each}}}}", path.trim()).as_str()))
+            }
+        }
+        else if let Some(path) = content.strip_prefix("#if ")
+        {
+            let unclosed = || NotifydError::new(format!("{{{{#if {}}}}} is not closed by {{{{/if}}}}", path.trim()).as_str());
+
+            let (then, otherwise) = match parse_nodes(tags, pos)? {
+                (then, Some(end)) if end == "/if" => (then, Vec::new()),
+                (then, Some(end)) if end == "else" => match parse_nodes(tags, pos)? {
+                    (otherwise, Some(end)) if end == "/if" => (then, otherwise),
+                    _ => return Err(unclosed())
+                },
+                _ => return Err(unclosed())
+            };
+
+            nodes.push(TemplateNode::If(path.trim().to_string(), then, otherwise));
+        }
+        else
+        {
+            nodes.push(TemplateNode::Value(content.clone()));
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+/* The subset of Handlebars spoken text needs: {{path}}, {{#each path}} and {{#if path}} with {{else}} */
+pub(crate) fn parse_template(template : &str) -> Result<Vec<TemplateNode>, Box<dyn std::error::Error>>
+{
+    let mut tags = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{")
+    {
+        let end = match rest[start..].find("}}") {
+            Some(e) => start + e,
+            None => return Err(NotifydError::new("A {{ of the template is not closed"))
+        };
+
+        tags.push((false, rest[..start].to_string()));
+        tags.push((true, rest[start + 2..end].trim().to_string()));
+        rest = &rest[end + 2..];
+    }
+    tags.push((false, rest.to_string()));
+
+    let mut pos = 0;
+    match parse_nodes(&tags, &mut pos)? {
+        (nodes, None) => Ok(nodes),
+        (_, Some(tag)) => Err(NotifydError::new(format!("Unexpected {{{{{}}}}} in the template", tag).as_str()))
+    }
+}
+
+/* Paths are relative to the current element, "this" being the element itself and "$" the body */
+fn lookup<'a>(path : &str, scope : &'a serde_json::Value, root : &'a serde_json::Value) -> Vec<&'a serde_json::Value>
+{
+    if path.starts_with('$')
+    {
+        return select(root, path);
+    }
+
+    match path {
+        "this" => vec![scope],
+        p => select(scope, p.strip_prefix("this.").unwrap_or(p))
+    }
+}
+
+pub(crate) fn render(nodes : &[TemplateNode], scope : &serde_json::Value, root : &serde_json::Value, out : &mut String)
+{
+    for node in nodes
+    {
+        match node {
+            TemplateNode::Text(t) => out.push_str(t),
+            TemplateNode::Value(path) => {
+                out.push_str(&lookup(path, scope, root).into_iter().map(spoken).collect::<Vec<String>>().join(", "));
+            },
+            TemplateNode::Each(path, body) => {
+                for v in lookup(path, scope, root)
+                {
+                    match v {
+                        serde_json::Value::Array(a) => a.iter().for_each(|e| render(body, e, root, out)),
+                        serde_json::Value::Object(m) => m.values().for_each(|e| render(body, e, root, out)),
+                        _ => {}
+                    }
+                }
+            },
+            TemplateNode::If(path, then, otherwise) => {
+                let branch = if lookup(path, scope, root).into_iter().any(truthy) { then } else { otherwise };
+                render(branch, scope, root, out);
+            }
+        }
+    }
+}
+
+impl WebhookConfig
+{
+    pub(crate) fn check(self : &Self) -> Result<(), Box<dyn std::error::Error>>
+    {
+        match (&self.path, &self.template) {
+            (Some(_), None) => Ok(()),
+            (None, Some(t)) => parse_template(t).map(|_| ()),
+            _ => Err(NotifydError::new("Either 'path' or 'template' must be given"))
+        }
+    }
+
+    /* Text to speak for the body, empty when there is nothing to say */
+    pub(crate) fn text(self : &Self, body : &serde_json::Value) -> Result<String, Box<dyn std::error::Error>>
+    {
+        let text = match (&self.path, &self.template) {
+            (Some(path), _) => select(body, path).into_iter().map(spoken).filter(|s| !s.is_empty()).collect::<Vec<String>>().join(". "),
+            (None, Some(template)) => {
+                let mut out = String::new();
+                render(&parse_template(template)?, body, body, &mut out);
+                out
+            },
+            (None, None) => return Err(NotifydError::new("Either 'path' or 'template' must be given"))
+        };
+
+        Ok(text.split_whitespace().collect::<Vec<&str>>().join(" "))
+    }
+}
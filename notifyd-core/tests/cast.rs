@@ -0,0 +1,32 @@
+use notifyd_core::cast::CastMessage;
+
+#[test]
+fn messages_survive_the_wire_format()
+{
+    let msg = CastMessage {
+        source : String::from("sender-0"),
+        destination : String::from("receiver-0"),
+        namespace : String::from("urn:x-cast:com.google.cast.tp.connection"),
+        payload : String::from("{\"type\":\"CONNECT\"}")
+    };
+
+    let bytes = msg.encode();
+
+    /* protocol_version 0, then the source id as a length-delimited field */
+    assert_eq!(&bytes[..4], &[0x08, 0x00, 0x12, 0x08]);
+    assert_eq!(CastMessage::decode(&bytes).unwrap(), msg);
+}
+
+#[test]
+fn long_payloads_use_multi_byte_lengths()
+{
+    let msg = CastMessage {
+        source : String::from("sender-0"),
+        destination : String::from("web-1"),
+        namespace : String::from("urn:x-cast:com.google.cast.media"),
+        payload : "x".repeat(300)
+    };
+
+    assert_eq!(CastMessage::decode(&msg.encode()).unwrap(), msg);
+    assert!(CastMessage::decode(&msg.encode()[..40]).is_err());
+}
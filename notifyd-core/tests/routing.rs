@@ -0,0 +1,908 @@
+use std::io::Read;
+use std::io::Write;
+use rouille::{Request, Response};
+use notifyd_core::audio::ByteRange;
+use notifyd_core::config::Config;
+use notifyd_core::notifyd::Notifyd;
+
+fn daemon(config : &str) -> Notifyd
+{
+    let dir = tempdir::TempDir::new("notifyd-test").unwrap();
+    let path = dir.path().join("notifyd.toml");
+
+    std::fs::File::create(&path).unwrap()
+        .write_all(format!("database = \":memory:\"\n{}", config).as_bytes()).unwrap();
+
+    let config = Config::load(&path).unwrap();
+    Notifyd::new(8090, String::from("Use Local Speaker"), None, config).unwrap()
+}
+
+/* A daemon keeping its database in dir, to be started again on it */
+fn daemon_on(dir : &std::path::Path, config : &str) -> Notifyd
+{
+    let path = dir.join("notifyd.toml");
+
+    std::fs::File::create(&path).unwrap()
+        .write_all(format!("database = \"{}\"\n{}", dir.join("notifyd.db").display(), config).as_bytes()).unwrap();
+
+    let config = Config::load(&path).unwrap();
+    Notifyd::new(8090, String::from("Use Local Speaker"), None, config).unwrap()
+}
+
+fn get(url : &str, token : Option<&str>) -> Request
+{
+    let headers = match token {
+        Some(t) => vec![(String::from("Authorization"), format!("Bearer {}", t))],
+        None => Vec::new()
+    };
+
+    Request::fake_http("GET", url, headers, Vec::new())
+}
+
+fn post(url : &str, token : Option<&str>, body : serde_json::Value) -> Request
+{
+    let mut headers = vec![(String::from("Content-Type"), String::from("application/json"))];
+
+    if let Some(t) = token
+    {
+        headers.push((String::from("Authorization"), format!("Bearer {}", t)));
+    }
+
+    Request::fake_http("POST", url, headers, body.to_string().into_bytes())
+}
+
+fn body(response : Response) -> serde_json::Value
+{
+    let (mut reader, _) = response.data.into_reader_and_size();
+    let mut content = String::new();
+    reader.read_to_string(&mut content).unwrap();
+    serde_json::from_str(&content).unwrap()
+}
+
+#[test]
+fn unknown_endpoint_is_an_error()
+{
+    let notifyd = daemon("");
+    let response = notifyd.dispatch(&get("/nope", None));
+
+    assert_eq!(response.status_code, 400);
+    assert_eq!(body(response)["success"], false);
+}
+
+#[test]
+fn queue_starts_empty()
+{
+    let notifyd = daemon("");
+    let response = notifyd.dispatch(&get("/queue", None));
+
+    assert_eq!(response.status_code, 200);
+    let listing = body(response);
+    assert!(listing["running"].is_null());
+    assert_eq!(listing["pending"].as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn status_reports_the_daemon_state()
+{
+    let notifyd = daemon("");
+    let response = notifyd.dispatch(&get("/status", None));
+
+    assert_eq!(response.status_code, 200);
+    let status = body(response);
+    assert!(status["playing"].is_null());
+    assert_eq!(status["pending"].as_array().unwrap().len(), 0);
+    assert_eq!(status["target"], "Use Local Speaker");
+    assert!(status["version"].is_string());
+    assert!(status["uptime_secs"].is_u64());
+}
+
+#[test]
+fn playback_controls_need_something_playing()
+{
+    let notifyd = daemon("");
+
+    for url in ["/action/stop", "/action/pause", "/action/resume"]
+    {
+        let response = notifyd.dispatch(&post(url, None, serde_json::json!({})));
+        assert_eq!(response.status_code, 409);
+        assert_eq!(body(response)["success"], false);
+    }
+}
+
+#[test]
+fn unknown_job_is_not_found()
+{
+    let notifyd = daemon("");
+
+    assert_eq!(notifyd.dispatch(&get("/jobs/42", None)).status_code, 404);
+    assert_eq!(notifyd.dispatch(&get("/jobs/abc", None)).status_code, 400);
+}
+
+#[test]
+fn levels_out_of_range_are_rejected()
+{
+    let notifyd = daemon("");
+
+    let response = notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Good night", "gain" : 12 })));
+    assert_eq!(response.status_code, 400);
+    assert_eq!(body(response)["err"], "'gain' must be between 0 and 4");
+
+    let response = notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Good night", "volume" : 1.5 })));
+    assert_eq!(response.status_code, 400);
+    assert_eq!(body(response)["err"], "'volume' must be between 0 and 1");
+
+    let response = notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Smoke", "repeat" : 0 })));
+    assert_eq!(response.status_code, 400);
+    assert_eq!(body(response)["err"], "'repeat' must be between 1 and 10");
+}
+
+#[test]
+fn volume_is_set_on_the_local_speaker()
+{
+    let notifyd = daemon("");
+
+    let response = notifyd.dispatch(&post("/volume", None, serde_json::json!({ "level" : 0.25, "ramp_ms" : 0 })));
+    assert_eq!(response.status_code, 200);
+
+    let state = body(notifyd.dispatch(&get("/volume", None)));
+    assert_eq!(state["target"], "Use Local Speaker");
+    assert_eq!(state["level"], 0.25);
+
+    assert_eq!(notifyd.dispatch(&post("/volume", None, serde_json::json!({ "level" : 2 }))).status_code, 400);
+    assert_eq!(notifyd.dispatch(&post("/volume", None, serde_json::json!({ "level" : 0.5, "uid" : "terminal" }))).status_code, 400);
+}
+
+#[test]
+fn uploaded_sounds_are_played_without_speech()
+{
+    let notifyd = daemon("");
+
+    let wav = "UklGRgQAAABXQVZF";
+    assert_eq!(notifyd.dispatch(&post("/sounds", None, serde_json::json!({ "name" : "doorbell", "audio_b64" : wav }))).status_code, 201);
+    assert_eq!(notifyd.dispatch(&post("/sounds", None, serde_json::json!({ "name" : "../up", "audio_b64" : wav }))).status_code, 400);
+    assert_eq!(notifyd.dispatch(&post("/sounds", None, serde_json::json!({ "name" : "text", "audio_b64" : "aGVsbG8=" }))).status_code, 400);
+
+    let sounds = body(notifyd.dispatch(&get("/sounds", None)));
+    assert_eq!(sounds[0]["name"], "doorbell");
+    assert_eq!(sounds[0]["format"], "wav");
+
+    let queued = body(notifyd.dispatch(&post("/action/play", None, serde_json::json!({ "sound" : "doorbell" }))));
+    assert_eq!(queued["id"], 1);
+    assert_eq!(notifyd.dispatch(&post("/action/play", None, serde_json::json!({ "sound" : "applause" }))).status_code, 404);
+
+    let delete = Request::fake_http("DELETE", "/sounds/doorbell", Vec::new(), Vec::new());
+    assert_eq!(notifyd.dispatch(&delete).status_code, 200);
+    assert_eq!(notifyd.dispatch(&get("/sounds/doorbell", None)).status_code, 404);
+}
+
+#[test]
+fn play_url_only_fetches_http()
+{
+    let notifyd = daemon("");
+
+    let response = notifyd.dispatch(&post("/action/play_url", None, serde_json::json!({ "url" : "file:///etc/passwd" })));
+    assert_eq!(response.status_code, 400);
+    assert_eq!(body(response)["err"], "Only http and https URLs can be played");
+}
+
+#[test]
+fn synthesized_urls_cannot_be_streamed()
+{
+    let notifyd = daemon("");
+
+    let response = notifyd.dispatch(&post("/synthesize", None, serde_json::json!({ "text" : "Build is green", "as_url" : true, "stream" : true })));
+    assert_eq!(response.status_code, 400);
+    assert_eq!(body(response)["err"], "'stream' and 'as_url' cannot be combined");
+}
+
+#[test]
+fn interrupting_notifications_go_first()
+{
+    let notifyd = daemon("");
+
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Dryer is done" })));
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Smoke", "priority" : "urgent" })));
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Doorbell", "priority" : "low", "interrupt" : true })));
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["text"], "Doorbell");
+    assert_eq!(pending[1]["text"], "Smoke");
+    assert_eq!(pending[2]["text"], "Dryer is done");
+}
+
+#[test]
+fn bursts_are_merged_by_dedup_key()
+{
+    let notifyd = daemon("");
+
+    for _ in 0..3
+    {
+        notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Disk is full", "dedup_key" : "disk" })));
+    }
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Disk is full" })));
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending.as_array().unwrap().len(), 2);
+    assert_eq!(pending[0]["text"], "Disk is full, 3 times");
+    assert_eq!(pending[1]["text"], "Disk is full");
+}
+
+#[test]
+fn retries_with_an_idempotency_key_are_not_queued_again()
+{
+    let notifyd = daemon("");
+
+    let headers = vec![(String::from("Content-Type"), String::from("application/json")), (String::from("Idempotency-Key"), String::from("garage-1"))];
+    let first = body(notifyd.dispatch(&Request::fake_http("POST", "/notify", headers.clone(), br#"{ "text" : "Garage door is open" }"#.to_vec())));
+
+    let retry = notifyd.dispatch(&Request::fake_http("POST", "/notify", headers, br#"{ "text" : "Garage door is open" }"#.to_vec()));
+    assert_eq!(retry.status_code, 202);
+    assert!(retry.headers.iter().any(|(h, v)| h == "Idempotent-Replayed" && v == "true"));
+
+    let retry = body(retry);
+    assert_eq!(retry["id"], first["id"]);
+    assert_eq!(retry["status"], "pending");
+
+    let keyed = body(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Garage door is open", "id" : "garage-2" }))));
+    assert_ne!(keyed["id"], first["id"]);
+
+    let retry = body(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Garage door is open", "id" : "garage-2" }))));
+    assert_eq!(retry["id"], keyed["id"]);
+
+    assert_eq!(body(notifyd.dispatch(&get("/queue", None)))["pending"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn full_queue_refuses_or_drops_the_least_urgent()
+{
+    let notifyd = daemon("[limits]\nmax_queue_depth = 2\n");
+
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Dryer is done", "priority" : "low" })));
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Mail is here" })));
+
+    let refused = notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Smoke", "priority" : "urgent" })));
+    assert_eq!(refused.status_code, 429);
+    assert!(refused.headers.iter().any(|(h, _)| h == "Retry-After"));
+
+    let notifyd = daemon("[limits]\nmax_queue_depth = 2\noverflow = \"drop_lowest\"\n");
+
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Dryer is done", "priority" : "low" })));
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Mail is here" })));
+
+    assert_eq!(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Smoke", "priority" : "urgent" }))).status_code, 202);
+    assert_eq!(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Rain", "priority" : "low" }))).status_code, 429);
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["text"], "Smoke");
+    assert_eq!(pending[1]["text"], "Mail is here");
+}
+
+#[test]
+fn routing_rules_pick_the_target_or_suppress()
+{
+    let notifyd = daemon("[auth]\nadmin_token = \"s3cret\"\n\
+                          [[rules]]\nname = \"quiet rain\"\ntext = \"(?i)rain\"\nsuppress = true\n\
+                          [[rules]]\nsource = \"admin\"\npriority = \"high\"\ntarget = \"office\"\nvolume = 0.5\n");
+
+    let suppressed = body(notifyd.dispatch(&post("/notify", Some("s3cret"), serde_json::json!({ "text" : "Rain is coming", "priority" : "urgent" }))));
+    assert_eq!(suppressed["reason"], "Suppressed by rule quiet rain");
+
+    notifyd.dispatch(&post("/notify", Some("s3cret"), serde_json::json!({ "text" : "Mail is here" })));
+    notifyd.dispatch(&post("/notify", Some("s3cret"), serde_json::json!({ "text" : "Disk is full", "priority" : "high" })));
+
+    let pending = &body(notifyd.dispatch(&get("/queue", Some("s3cret"))))["pending"];
+    assert_eq!(pending[0]["text"], "Disk is full");
+    assert_eq!(pending[0]["target"], "office");
+    assert_eq!(pending[1]["text"], "Mail is here");
+    assert_ne!(pending[1]["target"], "office");
+
+    let history = body(notifyd.dispatch(&get("/history?limit=10", Some("s3cret"))));
+    assert!(history.to_string().contains("suppressed"));
+}
+
+#[test]
+fn topics_have_their_own_endpoint_target_and_tokens()
+{
+    let notifyd = daemon("[topics.doorbell]\ntarget = \"downstairs\"\ntokens = [\"d00r\"]\n[topics.ci]\ntarget = \"office\"\n");
+
+    assert_eq!(notifyd.dispatch(&post("/notify/t/garage", None, serde_json::json!({ "text" : "Door open" }))).status_code, 404);
+    assert_eq!(notifyd.dispatch(&post("/notify/t/doorbell", None, serde_json::json!({ "text" : "Ding dong" }))).status_code, 401);
+    assert_eq!(notifyd.dispatch(&post("/notify/t/doorbell?token=d00r", None, serde_json::json!({ "text" : "Ding dong" }))).status_code, 202);
+    assert_eq!(notifyd.dispatch(&post("/notify/t/ci", None, serde_json::json!({ "text" : "Build failed", "target" : "local" }))).status_code, 202);
+    assert_eq!(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Hi", "topic" : "ci" }))).status_code, 202);
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["target"], "downstairs");
+    assert_eq!(pending[1]["target"], "local");
+    assert_eq!(pending[2]["target"], "office");
+}
+
+#[test]
+fn templates_are_filled_from_the_body()
+{
+    let notifyd = daemon("[templates]\nwasher = \"The washing machine finished after {minutes} minutes\"\n\"washer.fr\" = \"La machine a fini en {minutes} minutes\"\n");
+
+    assert_eq!(notifyd.dispatch(&post("/notify/template/dryer", None, serde_json::json!({ "minutes" : 42 }))).status_code, 404);
+    assert_eq!(notifyd.dispatch(&post("/notify/template/washer", None, serde_json::json!({ "mins" : 42 }))).status_code, 400);
+
+    notifyd.dispatch(&post("/notify/template/washer", None, serde_json::json!({ "minutes" : 42, "lang" : "en-US", "target" : "local" })));
+    notifyd.dispatch(&post("/notify/template/washer", None, serde_json::json!({ "minutes" : "quarante", "lang" : "fr-FR" })));
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["text"], "The washing machine finished after 42 minutes");
+    assert_eq!(pending[0]["target"], "local");
+    assert_eq!(pending[1]["text"], "La machine a fini en quarante minutes");
+}
+
+#[test]
+fn presets_are_played_without_a_body()
+{
+    let notifyd = daemon("[presets.dinner]\ntext = \"Dinner is ready\"\ntarget = \"local\"\npriority = \"high\"\n");
+
+    assert_eq!(notifyd.dispatch(&Request::fake_http("POST", "/notify/preset/lunch", vec![], vec![])).status_code, 404);
+    assert_eq!(notifyd.dispatch(&Request::fake_http("POST", "/notify/preset/dinner", vec![], vec![])).status_code, 202);
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["text"], "Dinner is ready");
+    assert_eq!(pending[0]["target"], "local");
+    assert_eq!(pending[0]["priority"], "high");
+}
+
+#[test]
+fn job_ids_go_on_after_a_restart()
+{
+    let dir = tempdir::TempDir::new("notifyd-test").unwrap();
+    let config = "[persistence]\nenabled = false\n[limits]\nmax_queue_depth = 1\noverflow = \"drop_lowest\"\n";
+
+    {
+        let notifyd = daemon_on(dir.path(), config);
+        notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Dryer is done", "priority" : "low" })));
+        notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Smoke", "priority" : "urgent" })));
+        assert_eq!(body(notifyd.dispatch(&get("/jobs/1", None)))["status"], "dropped");
+    }
+
+    let notifyd = daemon_on(dir.path(), config);
+
+    let queued = body(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Mail is here" }))));
+    assert_eq!(queued["id"], 2);
+    assert_eq!(body(notifyd.dispatch(&get("/jobs/2", None)))["status"], "pending");
+    assert_eq!(body(notifyd.dispatch(&get("/jobs/1", None)))["status"], "dropped");
+}
+
+#[test]
+fn home_assistant_messages_are_queued()
+{
+    let notifyd = daemon("");
+
+    let missing = notifyd.dispatch(&post("/notify/homeassistant", None, serde_json::json!({ "title" : "Laundry" })));
+    assert_eq!(missing.status_code, 400);
+
+    let queued = notifyd.dispatch(&post("/notify/homeassistant", None, serde_json::json!({
+        "message" : "The dryer is done", "title" : "Laundry", "target" : ["local"], "data" : { "priority" : "high" }
+    })));
+    assert_eq!(queued.status_code, 202);
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["text"], "Laundry. The dryer is done");
+    assert_eq!(pending[0]["target"], "local");
+    assert_eq!(pending[0]["priority"], "high");
+}
+
+#[test]
+fn gotify_messages_take_the_app_token()
+{
+    let notifyd = daemon("[auth]\nadmin_token = \"s3cret\"\n");
+    let message = serde_json::json!({ "title" : "Backup", "message" : "The backup is done", "priority" : 8 });
+
+    assert_eq!(notifyd.dispatch(&post("/message", None, message.clone())).status_code, 401);
+
+    let sent = body(notifyd.dispatch(&post("/message?token=s3cret", None, message)));
+    assert_eq!(sent["message"], "The backup is done");
+
+    let pending = &body(notifyd.dispatch(&get("/queue", Some("s3cret"))))["pending"];
+    assert_eq!(pending[0]["id"], sent["id"]);
+    assert_eq!(pending[0]["text"], "Backup. The backup is done");
+    assert_eq!(pending[0]["priority"], "high");
+}
+
+#[test]
+fn github_failures_are_announced_when_signed()
+{
+    let notifyd = daemon("[github]\nsecret = \"hook\"\n");
+
+    let delivery = |conclusion : &str, signature : Option<&str>| -> Request {
+        let payload = serde_json::json!({
+            "action" : "completed",
+            "workflow_run" : { "name" : "Build", "head_branch" : "main", "conclusion" : conclusion },
+            "repository" : { "name" : "notifyd", "full_name" : "besnardjb/notifyd" }
+        }).to_string();
+
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"hook");
+        let tag : String = ring::hmac::sign(&key, payload.as_bytes()).as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+
+        Request::fake_http("POST", "/integrations/github", vec![
+            (String::from("X-GitHub-Event"), String::from("workflow_run")),
+            (String::from("X-Hub-Signature-256"), format!("sha256={}", signature.unwrap_or(&tag)))
+        ], payload.into_bytes())
+    };
+
+    assert_eq!(notifyd.dispatch(&delivery("failure", Some("00"))).status_code, 401);
+    assert_eq!(notifyd.dispatch(&delivery("success", None)).status_code, 200);
+    assert_eq!(notifyd.dispatch(&delivery("failure", None)).status_code, 202);
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["text"], "CI failed on main for notifyd, in workflow Build");
+}
+
+#[test]
+fn webhooks_render_their_template()
+{
+    let notifyd = daemon(concat!(
+        "[webhooks.alerts]\n",
+        "template = \"{{#each alerts}}{{#if labels.severity}}{{labels.alertname}} is {{status}}. {{/if}}{{/each}}\"\n",
+        "priority = \"high\"\n",
+        "[webhooks.deploy]\n",
+        "path = \"$.deployment.message\"\n"
+    ));
+
+    let alerts = serde_json::json!({ "alerts" : [
+        { "status" : "firing", "labels" : { "alertname" : "DiskFull", "severity" : "critical" } },
+        { "status" : "firing", "labels" : { "alertname" : "Watchdog" } },
+        { "status" : "resolved", "labels" : { "alertname" : "HighLoad", "severity" : "warning" } }
+    ]});
+
+    assert_eq!(notifyd.dispatch(&post("/webhooks/alerts", None, alerts)).status_code, 202);
+    assert_eq!(notifyd.dispatch(&post("/webhooks/deploy", None, serde_json::json!({ "deployment" : {} }))).status_code, 200);
+    assert_eq!(notifyd.dispatch(&post("/webhooks/nope", None, serde_json::json!({}))).status_code, 404);
+
+    let pending = &body(notifyd.dispatch(&get("/queue", None)))["pending"];
+    assert_eq!(pending[0]["text"], "DiskFull is firing. HighLoad is resolved.");
+    assert_eq!(pending[0]["priority"], "high");
+}
+
+#[test]
+fn dashboard_is_served_without_a_token()
+{
+    let notifyd = daemon("[auth]\nadmin_token = \"s3cret\"\n");
+    let page = notifyd.dispatch(&get("/", None));
+
+    assert_eq!(page.status_code, 200);
+    assert!(page.headers.iter().any(|(h, v)| h == "Content-Type" && v.starts_with("text/html")));
+    assert_eq!(notifyd.dispatch(&get("/status", None)).status_code, 401);
+}
+
+#[test]
+fn dashboard_is_disabled_in_low_footprint_mode()
+{
+    let notifyd = daemon("low_footprint = true\n");
+
+    assert_eq!(notifyd.dispatch(&get("/", None)).status_code, 404);
+    assert_eq!(notifyd.dispatch(&get("/index.html", None)).status_code, 404);
+    assert_eq!(notifyd.dispatch(&get("/queue", None)).status_code, 200);
+}
+
+#[test]
+fn openapi_references_are_defined()
+{
+    let notifyd = daemon("[auth]\nadmin_token = \"s3cret\"\n");
+    let spec = body(notifyd.dispatch(&get("/openapi.json", None)));
+
+    assert!(spec["paths"]["/notify"]["post"]["requestBody"].is_object());
+
+    let text = spec.to_string();
+    for reference in text.split("\"#/components/schemas/").skip(1)
+    {
+        let name = &reference[..reference.find('"').unwrap()];
+        assert!(spec["components"]["schemas"][name].is_object(), "{} is not defined", name);
+    }
+}
+
+#[test]
+fn do_not_disturb_defers_until_switched_off()
+{
+    let notifyd = daemon("");
+
+    let state = body(notifyd.dispatch(&post("/admin/dnd", None, serde_json::json!({ "mode" : "on" }))));
+    assert_eq!(state["active"], true);
+
+    let deferred = body(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Laundry is done" }))));
+    assert_eq!(deferred["reason"], "Deferred until do-not-disturb ends");
+    assert_eq!(body(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Smoke", "priority" : "urgent" }))))["id"], 1);
+
+    assert_eq!(body(notifyd.dispatch(&get("/admin/dnd", None)))["deferred"], 1);
+
+    let state = body(notifyd.dispatch(&post("/admin/dnd", None, serde_json::json!({ "mode" : "off" }))));
+    assert_eq!(state["deferred"], 0);
+    assert_eq!(body(notifyd.dispatch(&get("/queue", None)))["pending"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn history_is_queried_by_limit_and_time()
+{
+    let notifyd = daemon("[quiet]\naction = \"suppress\"\n");
+
+    notifyd.dispatch(&post("/admin/dnd", None, serde_json::json!({ "mode" : "on" })));
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Mail is here" })));
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Dryer is done" })));
+
+    let all = body(notifyd.dispatch(&get("/history", None)));
+    assert_eq!(all.as_array().unwrap().len(), 2);
+    assert_eq!(all[0]["text"], "Mail is here");
+    assert_eq!(all[0]["status"], "suppressed");
+
+    let last = body(notifyd.dispatch(&get("/history?limit=1", None)));
+    assert_eq!(last.as_array().unwrap().len(), 1);
+    assert_eq!(last[0]["text"], "Dryer is done");
+
+    assert_eq!(body(notifyd.dispatch(&get("/history?since=2999-01-01T00:00:00Z", None))).as_array().unwrap().len(), 0);
+    assert_eq!(body(notifyd.dispatch(&get("/history?since=0", None))).as_array().unwrap().len(), 2);
+    assert_eq!(notifyd.dispatch(&get("/history?since=yesterday", None)).status_code, 400);
+
+    /* Suppressed notifications were never played */
+    assert_eq!(notifyd.dispatch(&post("/action/repeat", None, serde_json::json!({}))).status_code, 404);
+}
+
+#[test]
+fn metrics_are_exposed_for_prometheus()
+{
+    let notifyd = daemon("");
+
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Bread is ready", "priority" : "high" })));
+
+    let response = notifyd.dispatch(&get("/metrics", None));
+    assert_eq!(response.status_code, 200);
+
+    let (mut reader, _) = response.data.into_reader_and_size();
+    let mut text = String::new();
+    reader.read_to_string(&mut text).unwrap();
+
+    assert!(text.contains("notifyd_notifications_received_total{priority=\"high\"} 1\n"));
+    assert!(text.contains("notifyd_queue_pending 1\n"));
+    assert!(text.contains("# TYPE notifyd_synthesis_duration_seconds histogram\n"));
+    assert!(text.contains("# TYPE notifyd_target_up gauge\n"));
+}
+
+#[test]
+fn delayed_notifications_can_be_cancelled()
+{
+    let notifyd = daemon("");
+
+    let scheduled = body(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Take the cake out", "delay_seconds" : 600 }))));
+    let id = scheduled["schedule_id"].as_u64().unwrap();
+
+    let timers = body(notifyd.dispatch(&get("/timers", None)));
+    assert_eq!(timers[0]["kind"], "notification");
+    assert_eq!(timers[0]["label"], "Take the cake out");
+    assert_eq!(body(notifyd.dispatch(&get("/queue", None)))["pending"].as_array().unwrap().len(), 0);
+
+    assert_eq!(notifyd.dispatch(&post(&format!("/timers/{}/cancel", id), None, serde_json::json!({}))).status_code, 200);
+    assert_eq!(body(notifyd.dispatch(&get("/timers", None))).as_array().unwrap().len(), 0);
+
+    let both = notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Hi", "delay_seconds" : 5, "at" : "2030-01-01T08:00:00Z" })));
+    assert_eq!(both.status_code, 400);
+}
+
+#[test]
+fn recurring_schedules_are_managed_under_schedules()
+{
+    let notifyd = daemon("");
+
+    let created = body(notifyd.dispatch(&post("/schedules", None, serde_json::json!({ "cron" : "30 7 * * mon-fri", "text" : "Time to leave" }))));
+    let id = created["id"].as_i64().unwrap();
+    assert_eq!(created["enabled"], true);
+    assert!(created["next"].as_str().unwrap().contains("T07:30:00"));
+
+    assert_eq!(notifyd.dispatch(&post("/schedules", None, serde_json::json!({ "cron" : "61 * * * *", "text" : "Never" }))).status_code, 400);
+
+    let disabled = body(notifyd.dispatch(&post(&format!("/schedules/{}", id), None, serde_json::json!({ "enabled" : false }))));
+    assert_eq!(disabled["text"], "Time to leave");
+    assert!(disabled["next"].is_null());
+
+    let removal = Request::fake_http("DELETE", format!("/schedules/{}", id), Vec::new(), Vec::new());
+    assert_eq!(notifyd.dispatch(&removal).status_code, 200);
+    assert_eq!(body(notifyd.dispatch(&get("/schedules", None))).as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn tokens_gate_requests_by_scope()
+{
+    let notifyd = daemon("[auth]\nadmin_token = \"s3cret\"\n");
+
+    assert_eq!(notifyd.dispatch(&get("/queue", None)).status_code, 401);
+    assert_eq!(notifyd.dispatch(&get("/queue", Some("wrong"))).status_code, 401);
+    assert_eq!(notifyd.dispatch(&get("/queue", Some("s3cret"))).status_code, 200);
+
+    /* Cast devices fetch audio without credentials, through signed URLs */
+    assert_eq!(notifyd.dispatch(&get("/static/missing.wav", None)).status_code, 403);
+    assert_eq!(notifyd.dispatch(&get("/static/missing.wav?expires=4102444800&sig=forged", None)).status_code, 403);
+
+    let created = body(notifyd.dispatch(&post("/admin/tokens", Some("s3cret"),
+                                              serde_json::json!({ "label" : "phone", "scopes" : ["read"] }))));
+    let token = created["token"].as_str().unwrap().to_string();
+
+    let response = notifyd.dispatch(&get("/history", Some(&token)));
+    assert_eq!(response.headers.iter().find(|(h, _)| h == "X-Auth-Identity").map(|(_, v)| v.to_string()),
+               Some(String::from("phone")));
+    assert_eq!(response.status_code, 200);
+
+    assert_eq!(notifyd.dispatch(&get("/admin/tokens", Some(&token))).status_code, 403);
+
+    let revoke = Request::fake_http("DELETE", format!("/admin/tokens/{}", created["id"]),
+                                    vec![(String::from("Authorization"), String::from("Bearer s3cret"))], Vec::new());
+    assert_eq!(notifyd.dispatch(&revoke).status_code, 200);
+    assert_eq!(notifyd.dispatch(&get("/history", Some(&token))).status_code, 401);
+}
+
+#[test]
+fn static_paths_cannot_escape_the_root()
+{
+    let root = tempdir::TempDir::new("notifyd-static").unwrap();
+    std::fs::create_dir_all(root.path().join("notifyd")).unwrap();
+    std::fs::write(root.path().join("notifyd").join("stale.wav"), b"RIFF").unwrap();
+    std::fs::write(root.path().join("index.html"), b"<html></html>").unwrap();
+
+    let notifyd = daemon(&format!("static_dir = \"{}\"\n", root.path().display()));
+
+    for url in ["/static/../../etc/passwd",
+                "/static/%2e%2e/%2e%2e/etc/passwd",
+                "/static/..%2f..%2fetc%2fpasswd",
+                "/static/%2E%2E%5Cwin.ini",
+                "/static/%2fetc%2fpasswd"]
+    {
+        let response = notifyd.dispatch(&get(url, None));
+        assert_eq!(response.status_code, 403);
+        assert_eq!(body(response)["reason"], "Forbidden path", "{}", url);
+    }
+
+    assert_ne!(notifyd.dispatch(&get("/static/index.html", None)).status_code, 200);
+
+    /* The subdirectory of notifyd is the one swept, other files of the configured one are left alone */
+    let flush = Request::fake_http("POST", "/admin/flush", Vec::new(), Vec::new());
+    assert_eq!(body(notifyd.dispatch(&flush))["files_removed"], 1);
+    assert!(!root.path().join("notifyd").join("stale.wav").exists());
+    assert!(root.path().join("index.html").exists());
+}
+
+#[test]
+fn audio_is_returned_when_accepted()
+{
+    let accept = |value : &str| Request::fake_http("POST", "/action/speak", vec![(String::from("Accept"), value.to_string())], Vec::new());
+
+    assert_eq!(Notifyd::negotiated_audio(&accept("audio/wav")), Some("audio/wav"));
+    assert_eq!(Notifyd::negotiated_audio(&accept("audio/*")), Some("audio/wav"));
+    assert_eq!(Notifyd::negotiated_audio(&accept("audio/mpeg, audio/*;q=0.5")), Some("audio/mpeg"));
+    assert_eq!(Notifyd::negotiated_audio(&accept("*/*")), None);
+    assert_eq!(Notifyd::negotiated_audio(&accept("application/json")), None);
+}
+
+#[test]
+fn static_audio_honours_byte_ranges()
+{
+    assert_eq!(ByteRange::parse(None, 1000), ByteRange::Full);
+    assert_eq!(ByteRange::parse(Some("bytes=0-99"), 1000), ByteRange::Partial(0, 99));
+    assert_eq!(ByteRange::parse(Some("bytes=900-"), 1000), ByteRange::Partial(900, 999));
+    assert_eq!(ByteRange::parse(Some("bytes=900-5000"), 1000), ByteRange::Partial(900, 999));
+    assert_eq!(ByteRange::parse(Some("bytes=-100"), 1000), ByteRange::Partial(900, 999));
+    assert_eq!(ByteRange::parse(Some("bytes=1000-"), 1000), ByteRange::Unsatisfiable);
+    assert_eq!(ByteRange::parse(Some("bytes=0-1,5-9"), 1000), ByteRange::Full);
+    assert_eq!(ByteRange::parse(Some("lines=1-2"), 1000), ByteRange::Full);
+}
+
+#[test]
+fn instances_are_served_under_their_name()
+{
+    let mut notifyd = daemon("");
+    let mut office = daemon("[auth]\nadmin_token = \"office\"\n");
+    office.mount = String::from("/office");
+    notifyd.instances.push(std::sync::Arc::new(office));
+
+    assert_eq!(notifyd.dispatch(&get("/queue", None)).status_code, 200);
+    assert_eq!(notifyd.dispatch(&get("/office/queue", None)).status_code, 401);
+    assert_eq!(notifyd.dispatch(&get("/office/queue", Some("office"))).status_code, 200);
+}
+
+#[test]
+fn reload_applies_the_edited_file()
+{
+    let dir = tempdir::TempDir::new("notifyd-test").unwrap();
+    let path = dir.path().join("notifyd.toml");
+
+    std::fs::write(&path, "database = \":memory:\"\nprefix = \"Kitchen\"\n").unwrap();
+    let notifyd = Notifyd::new(8090, String::from("Use Local Speaker"), None, Config::load(&path).unwrap()).unwrap();
+
+    std::fs::write(&path, "database = \":memory:\"\nprefix = \"Garage\"\n").unwrap();
+    let reload = Request::fake_http("POST", "/admin/reload", Vec::new(), Vec::new());
+    let reloaded = body(notifyd.dispatch(&reload));
+    assert_eq!(reloaded["success"], true);
+    assert_eq!(reloaded["restart_required"].as_array().unwrap().len(), 0);
+
+    assert_eq!(body(notifyd.dispatch(&get("/admin/config", None)))["config"]["prefix"], "Garage");
+
+    /* A broken file leaves the running configuration alone */
+    std::fs::write(&path, "prefix = [").unwrap();
+    assert_eq!(notifyd.dispatch(&reload).status_code, 400);
+    assert_eq!(body(notifyd.dispatch(&get("/admin/config", None)))["config"]["prefix"], "Garage");
+}
+
+/* Connection handed to the websocket, keeping what the daemon writes */
+struct FakeSocket(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl Read for FakeSocket
+{
+    fn read(&mut self, _ : &mut [u8]) -> std::io::Result<usize>
+    {
+        Ok(0)
+    }
+}
+
+impl Write for FakeSocket
+{
+    fn write(&mut self, buf : &[u8]) -> std::io::Result<usize>
+    {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+#[test]
+fn websocket_clients_get_the_events_as_text_frames()
+{
+    let notifyd = daemon("");
+
+    let request = Request::fake_http("GET", "/ws?events=received", vec![
+        (String::from("Connection"), String::from("Upgrade")),
+        (String::from("Upgrade"), String::from("websocket")),
+        (String::from("Sec-WebSocket-Version"), String::from("13")),
+        (String::from("Sec-WebSocket-Key"), String::from("dGhlIHNhbXBsZSBub25jZQ=="))
+    ], Vec::new());
+
+    let mut response = notifyd.dispatch(&request);
+    assert_eq!(response.status_code, 101);
+
+    let written = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    response.upgrade.as_mut().unwrap().build(Box::new(FakeSocket(written.clone())));
+
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Dryer is done" })));
+
+    for _ in 0..50
+    {
+        if !written.lock().unwrap().is_empty()
+        {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let written = written.lock().unwrap();
+    assert_eq!(written[0], 0x81);
+    let event : serde_json::Value = serde_json::from_slice(&written[2..2 + written[1] as usize]).unwrap();
+    assert_eq!(event["event"], "received");
+    assert_eq!(event["text"], "Dryer is done");
+}
+
+#[test]
+fn event_streams_send_each_event_at_once_up_to_a_number_of_clients()
+{
+    let notifyd = daemon("");
+
+    let mut response = notifyd.dispatch(&get("/events?events=received", None));
+    assert_eq!(response.status_code, 200);
+
+    let written = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    response.upgrade.as_mut().unwrap().build(Box::new(FakeSocket(written.clone())));
+
+    notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Dryer is done" })));
+
+    for _ in 0..50
+    {
+        if String::from_utf8_lossy(&written.lock().unwrap()).contains("data:")
+        {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    /* No padding after the event */
+    let stream = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+    assert!(stream.starts_with(": connected\n\nevent: received\ndata: {"));
+    assert!(stream.ends_with("}\n\n"));
+
+    /* Open streams, waiting for their connection, count until dropped */
+    let mut streams = Vec::new();
+    while streams.len() < notifyd_core::events::MAX_SUBSCRIBERS - 1
+    {
+        streams.push(notifyd.dispatch(&get("/events", None)));
+    }
+
+    assert_eq!(notifyd.dispatch(&get("/events", None)).status_code, 503);
+
+    /* Its thread lets the subscription go shortly after */
+    streams.pop();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(notifyd.dispatch(&get("/events", None)).status_code, 200);
+}
+
+#[test]
+fn history_retention_only_prunes_its_own_instance()
+{
+    let dir = tempdir::TempDir::new("notifyd-test").unwrap();
+    let db = dir.path().join("notifyd.db");
+    let path = dir.path().join("notifyd.toml");
+
+    std::fs::write(&path, format!("database = \"{}\"\n[history]\nretention_days = 0\n[instances.office.history]\nretention_days = 1\n", db.display())).unwrap();
+
+    let config = Config::load(&path).unwrap();
+    let (_, office_config) = config.instance_configs().unwrap().remove(0);
+    let main = Notifyd::new(8090, String::from("Use Local Speaker"), None, config).unwrap();
+    let office = Notifyd::new(8091, String::from("Use Local Speaker"), None, office_config).unwrap();
+
+    let old = chrono::Local::now() - chrono::Duration::days(10);
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    for instance in [None, Some("office")]
+    {
+        conn.execute("INSERT INTO history (instance, time, timestamp, text, target, priority, status, items) VALUES (?1, ?2, ?3, 'Dryer is done', 'local', 'normal', 'done', '[]')",
+                     rusqlite::params![instance, old.to_rfc3339(), old.timestamp()]).unwrap();
+    }
+
+    let count = |instance : Option<&str>| -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM history WHERE instance IS ?1", [instance], |row| row.get(0)).unwrap()
+    };
+
+    office.prune_history();
+    assert_eq!(count(Some("office")), 0);
+    assert_eq!(count(None), 1);
+
+    /* 0 keeps the history forever */
+    main.prune_history();
+    assert_eq!(count(None), 1);
+}
+
+#[test]
+fn metrics_label_unknown_paths_and_names_with_fixed_routes()
+{
+    use notifyd_core::metrics::endpoint_label;
+
+    assert_eq!(endpoint_label("/jobs/12"), "/jobs/:id");
+    assert_eq!(endpoint_label("/jobs/12/bump"), "/jobs/:id/bump");
+    assert_eq!(endpoint_label("/static/notifyd/a1b2.wav"), "/static");
+    assert_eq!(endpoint_label("/sounds/doorbell"), "/sounds/:id");
+    assert_eq!(endpoint_label("/webhooks/grafana"), "/webhooks/:id");
+    assert_eq!(endpoint_label("/notify/t/laundry"), "/notify/t/:id");
+    assert_eq!(endpoint_label("/notify/template/washer"), "/notify/template/:id");
+    assert_eq!(endpoint_label("/notify/preset/dinner"), "/notify/preset/:id");
+    assert_eq!(endpoint_label("/admin/tokens/phone"), "/admin/tokens/:id");
+    assert_eq!(endpoint_label("/"), "/");
+
+    /* Paths nothing answers to would otherwise each be a series */
+    assert_eq!(endpoint_label("/aaaa"), "other");
+    assert_eq!(endpoint_label("/bbbb"), "other");
+    assert_eq!(endpoint_label("/jobs/abc/def"), "other");
+}
+
+#[test]
+fn flush_keeps_the_attachments_still_to_be_played()
+{
+    let root = tempdir::TempDir::new("notifyd-static").unwrap();
+    let notifyd = daemon(&format!("static_dir = \"{}\"\n", root.path().display()));
+    let audio = root.path().join("notifyd");
+
+    let wav = "UklGRgQAAABXQVZF";
+    assert_eq!(notifyd.dispatch(&post("/sounds", None, serde_json::json!({ "name" : "doorbell", "audio_b64" : wav }))).status_code, 201);
+    assert_eq!(notifyd.dispatch(&post("/action/play", None, serde_json::json!({ "sound" : "doorbell" }))).status_code, 202);
+    assert_eq!(notifyd.dispatch(&post("/notify", None, serde_json::json!({ "text" : "Chime", "audio_b64" : wav, "delay_seconds" : 600 }))).status_code, 200);
+
+    let kept : Vec<_> = std::fs::read_dir(&audio).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(kept.len(), 2);
+
+    std::fs::write(audio.join("orphan.wav"), b"RIFF").unwrap();
+
+    let flush = Request::fake_http("POST", "/admin/flush", Vec::new(), Vec::new());
+    assert_eq!(body(notifyd.dispatch(&flush))["files_removed"], 1);
+    assert!(!audio.join("orphan.wav").exists());
+    assert!(kept.iter().all(|p| p.exists()));
+}
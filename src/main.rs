@@ -1,702 +1,198 @@
-use clap::Parser;
-use tempdir::TempDir;
-use std::{path::PathBuf, fs::remove_file};
-use which::which;
-use std::process::{Command, Stdio};
-use std::path::Path;
-use std::env;
-use std::fs::{File, read_dir};
-use std::error::Error;
-use std::fmt::{self};
-use md5::compute as md5;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::IsTerminal;
+use std::path::PathBuf;
 use std::sync::Arc;
-use rouille::{Response, Request};
-use serde::{Serialize, Deserialize};
-use soloud::*;
-use std::time::SystemTime;
-use std::io::Write;
+use notifyd_core::client;
+use notifyd_core::config::Config;
+use notifyd_core::doctor::Doctor;
+use notifyd_core::notifyd::Notifyd;
+use tracing_subscriber::EnvFilter;
 
-/*******************
- * HELPER FOR TIME *
- *******************/
-
-fn now_in_usecs() -> u128 {
-    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(n) => n.as_micros(),
-        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
-    }
-}
-
-
-/****************
- * DEFINE ERROR *
- ****************/
-
-#[derive(Debug)]
-struct NotifydError(String);
-
-impl fmt::Display for NotifydError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-
-impl Error for NotifydError {}
-
-impl NotifydError {
-    fn new(desc : &str) -> Box<dyn Error>
-    {
-        Box::new(NotifydError(String::from(desc)))
-    }
-}
-
-/**************
- * TTS ENGINE *
- **************/
-
-#[derive(Debug,PartialEq)]
-enum TTSEngine
-{
-    PIPERTTS,
-    PICO2WAV,
-    ESPEAK,
-    ESPEAKNG,
-    AUTO
-}
-
-struct TtsSentence
-{
-    text: String,
-    path : String
-}
-
-impl TtsSentence
-{
-    fn new(path : &str, text : &str) -> TtsSentence
-    {
-        TtsSentence{
-            text : String::from(text),
-            path : String::from(path)
-        }
-    }
-
-    fn _run_player(self : &Self, player : &str) -> Result<(), Box<dyn std::error::Error>>
-    {
-        let cmd = [player, self.path.as_str()];
-
-        let ret = Command::new(cmd[0])
-        .args(&cmd[1..])
-        .output()?;
-
-        if !ret.status.success()
-        {
-            println!("Failed to run {:?}", cmd);
-            println!("{}", String::from_utf8(ret.stderr).unwrap());
-            return Err(NotifydError::new("Failed to run tts"));
-        }
-        Ok(())
-    }
-
-    fn _play_external(self: & Self) -> Result<(), Box<dyn std::error::Error>>
-    {
-        let candidate_players = ["paplay", "mplayer", "play" /* sox */];
-
-        for p in candidate_players
-        {
-            match which(p) {
-                Ok(p) => {
-                    match self._run_player(p.to_str().unwrap())
-                    {
-                        Ok(_) => return Ok(()),
-                        Err(e) => return Err(e)
-                    }
-                },
-                Err(_) => {}
-            }
-        }
-
-        Err(NotifydError::new(format!("Could not find any player in {:?} to play {}", candidate_players, self.path).as_str()))
-    }
-
-    fn play(self : &Self, sl : & Soloud) -> Result<(), Box<dyn std::error::Error>>
-    {
-        //self.play_external()
-        let mut wav = audio::Wav::default();
-        wav.load(&std::path::Path::new(&self.path))?;
-
-        sl.play(&wav);
-        while sl.voice_count() > 0 {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-        Ok(())
+/*****************
+ * CLI ARGUMENTS *
+ *****************/
 
-    }
+ #[derive(Parser,Debug)]
+ struct Cli {
+     /// The UID of the chromecast to target
+     #[clap(short, long, default_value = "Use Local Speaker")]
+     chromecast_uuid : String,
+     /// The port of the webserver
+     #[clap(short, long, default_value_t = 8090)]
+     port : u32,
+     /// Language to use for TTS
+     #[clap(short, long)]
+     lang : Option<String>,
+     /// Path to a TOML configuration file
+     #[clap(long)]
+     config : Option<PathBuf>,
+     /// PEM certificate chain, serving HTTPS along with --tls-key
+     #[clap(long, requires = "tls-key")]
+     tls_cert : Option<PathBuf>,
+     /// PEM private key (PKCS#8 or RSA) of the certificate
+     #[clap(long, requires = "tls-cert")]
+     tls_key : Option<PathBuf>,
+     /// Log filter such as "debug" or "notifyd_core=debug", overriding RUST_LOG
+     #[clap(long)]
+     log_level : Option<String>,
+     /// Format of the log lines
+     #[clap(long, value_enum, default_value = "text")]
+     log_format : LogFormat,
+     #[clap(subcommand)]
+     command : Option<Commands>,
+ }
 
-    fn delete(self : &Self) -> Result<(), Box<dyn std::error::Error>>
-    {
-        println!("Removing data for {} : '{}'", self.path, self.text);
-        remove_file(&self.path)?;
-        Ok(())
-    }
-}
+ #[derive(ValueEnum,Clone,Debug)]
+ enum LogFormat {
+     Text,
+     /// One JSON object per line, for log collectors
+     Json,
+ }
 
+ #[derive(Subcommand,Debug)]
+ enum Commands {
+     /// Check the environment (TTS engines, audio, network, casting, configuration)
+     Doctor,
+     /// Send a notification to a running daemon
+     Send {
+         /// The text to speak
+         text : String,
+         /// Daemon to send to, as a host, host:port or URL (the port defaults to --port)
+         #[clap(long, default_value = "localhost")]
+         host : String,
+         /// Device, group or alias to play on, the default target of the daemon otherwise
+         #[clap(short, long)]
+         target : Option<String>,
+         /// Rank in the queue of the daemon
+         #[clap(long, possible_values = ["low", "normal", "high", "urgent"])]
+         priority : Option<String>,
+         /// Topic whose configured defaults apply
+         #[clap(long)]
+         topic : Option<String>,
+         /// Voice name or "engine:voice"
+         #[clap(long)]
+         voice : Option<String>,
+         /// Level of the target while it plays, from 0.0 to 1.0
+         #[clap(long)]
+         volume : Option<f32>,
+         /// Return once the notification was played rather than once queued
+         #[clap(long)]
+         wait : bool,
+         /// Token of the daemon, NOTIFYD_TOKEN otherwise
+         #[clap(long)]
+         token : Option<String>,
+     },
+ }
 
-struct TTS
-{
-    engine : TTSEngine,
-    enginepath : String,
-    lang : String,
-    tmpdir : TempDir
-}
+/***********
+ * LOGGING *
+ ***********/
 
-impl TTS
+fn init_logging(level : &Option<String>, format : &LogFormat) -> Result<(), Box<dyn std::error::Error>>
 {
-    fn tts_to_bin_name( engine : & TTSEngine) -> &'static str
-    {
-        match engine {
-            // This is a script calling piper
-            TTSEngine::PIPERTTS => "pipertts",
-            TTSEngine::PICO2WAV => "pico2wave",
-            TTSEngine::ESPEAK => "espeak",
-            TTSEngine::ESPEAKNG =>  "espeak-ng",
-            TTSEngine::AUTO => panic!("AUTO engine cannot be instanciated")
-        }
-    }
-
-    fn look_for_candidate_engine(engine : TTSEngine) -> Result<TTSEngine, Box<dyn std::error::Error>>
-    {
-        if engine != TTSEngine::AUTO
-        {
-            return Ok(engine)
-        }
-
-        let engines = vec![TTSEngine::PIPERTTS, TTSEngine::PICO2WAV, TTSEngine::ESPEAK, TTSEngine::ESPEAKNG];
-
-        for e in engines{
-            match which(TTS::tts_to_bin_name(&e))
-            {
-                Ok(_) => return Ok(e),
-                Err(_) => {},
-            }
-        }
-
-        panic!("Cannot find any binary for implementing TTS in PATH");
-    }
-
-
-    fn clean_older_files(self : & Self) -> Result<(), Box<dyn std::error::Error>>
-    {
-        for entry in read_dir(&self.tmpdir)? {
-            let entry = entry?;
-
-            if entry.file_type()?.is_file() {
-                let metadata = entry.metadata()?;
-                let modified_time = metadata.modified()?;
-
-                if modified_time <= (std::time::SystemTime::now() - std::time::Duration::from_secs(3600 * 12))
-                {
-                    let file_path = entry.path();
-                    remove_file(&file_path)?;
-                    println!("Deleted: {:?}", file_path);
-                }
-            }
-        }
-        Ok(())
-    }
-
-
-    fn speak_to_file(self :& Self, text : String) -> Result<TtsSentence, Box<dyn std::error::Error>>
-    {
-        let _ = self.clean_older_files();
-
-        let to_hash = format!("{}{}", text, now_in_usecs());
-        let digest = md5(to_hash);
-        let outfile = self.tmpdir.path().join(format!("{}.wav", format!("{:x}", digest)));
-        let outpath: &str = outfile.to_str().expect("Failed to convert path to str");
-
-
-        let cmd;
-
-        match self.engine {
-            TTSEngine::PIPERTTS => {
-                cmd = vec![self.enginepath.as_str(), "-f", outpath];
-            },
-            _ => {
-                cmd = vec![self.enginepath.as_str(), "-w", outpath, "-l", self.lang.as_str()];
-            }
-        }
-
-        let mut child = Command::new(cmd[0])
-        .args(&cmd[1..])
-        .stdin(Stdio::piped())
-        .spawn()?;
-
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-
-        stdin.write_all(text.as_bytes())?;
-        drop(stdin);
-
-        let output = child.wait_with_output().expect("Failed to read stdout");
-
+    let filter = match level {
+        Some(l) => EnvFilter::try_new(l)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+    };
 
-        if !output.status.success()
-        {
-            let err_desc = format!("{}", String::from_utf8(output.stderr).unwrap());
-            println!("{:?}", cmd);
-            println!("~~~ Failed to run TSS engine ~~~");
-            println!("{}", err_desc);
-            println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
-            return Err(NotifydError::new(err_desc.as_str()));
-        }
+    /* No colors when redirected to a file or a journal */
+    let builder = tracing_subscriber::fmt().with_env_filter(filter)
+                                           .with_ansi(std::io::stdout().is_terminal());
 
-        Ok(TtsSentence::new(outpath, text.as_str()))
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init()
     }
 
-    fn get_locale_from_env() -> String
-    {
-        match env::var("LANG")
-        {
-            Ok(l) => {
-                if l.find(".").is_some()
-                {
-                    match l.split(".").next()
-                    {
-                        Some(e) => return e.to_string().replace("_", "-"),
-                        None => panic!("Failed to split on '.'")
-                    }
-                }
-                return l.replace("_", "-")
-            },
-            Err(_) => return String::from("en-US")
-        }
-    }
-
-    fn new(engine : TTSEngine, lang : Option<String>) -> Result<TTS, Box<dyn std::error::Error>>
-    {
-        let tmp_dir: TempDir = TempDir::new("notifydtts")?;
-
-        let engine_to_use = TTS::look_for_candidate_engine(engine)?;
-        let engine_binary_name = String::from(TTS::tts_to_bin_name(&engine_to_use));
-
-        let enginepath : PathBuf;
-
-        match which(&engine_binary_name)
-        {
-            Ok(path) => enginepath = path,
-            Err(_) => panic!("Cannot find TTS engine {} in PATH", engine_binary_name)
-        }
-
-        let mut locale;
-
-        match lang
-        {
-            Some (a) => {
-                locale = a;
-            }
-            None => {
-                locale = TTS::get_locale_from_env();
-
-                if locale == "C"
-                {
-                    locale = String::from("us-US");
-                }
-            }
-        }
-
-        println!("Using TTS engine {}", engine_binary_name);
-
-        return Ok(TTS { engine : engine_to_use,
-                        tmpdir: tmp_dir,
-                        lang : locale,
-                        enginepath: String::from(enginepath.to_string_lossy())
-                     })
-    }
-
-}
-
-/********************
- * WAV FILE CASTING *
- ********************/
-
-struct Caster
-{
-    target_uid : String,
-    url : String,
-}
-
-impl Drop for Caster
-{
-    fn drop(&mut self)
-    {
-        let _ = self.stop();
-    }
+    Ok(())
 }
 
+/*******************
+ * DEFINE THE MAIN *
+ *******************/
 
-impl Caster
-{
-
-    fn has_go_chromecast() -> Result<(), Box<dyn std::error::Error>>
-    {
-        match which("go-chromecast")
-        {
-            Ok(_) => Ok(()),
-            Err(_) => Err(NotifydError::new("Cannot locate go-chromecast in path"))
-
-        }
-    }
-
-    fn do_run(self : & Self, args : Vec<&str>)  ->  Result<(), Box<dyn std::error::Error>>
-    {
-        let cmd: [&str; 4] = ["go-chromecast", "-u", self.target_uid.as_str(), self.url.as_str()];
-
-        let ret = Command::new("go-chromecast")
-        .args(&args)
-        .output()?;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
 
-        if !ret.status.success()
-        {
-            let err_desc = format!("{}", String::from_utf8(ret.stderr).unwrap());
-            println!("{:?} {:?}", cmd, args);
-            println!("~~~ Failed to run go-chromecast ~~~");
-            println!("{}", err_desc);
-            println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
-            return Err(NotifydError::new(err_desc.as_str()));
-        }
+    let args = Cli::parse();
 
-        Ok(())
-    }
+    init_logging(&args.log_level, &args.log_format)?;
 
-    fn load(self : &Self) ->  Result<(), Box<dyn std::error::Error>>
+    if let Some(Commands::Doctor) = args.command
     {
-        self.do_run(vec!["load", "-u", self.target_uid.as_str(), self.url.as_str()])?;
-        Ok(())
+        std::process::exit(if Doctor::run(args.port, &args.chromecast_uuid, &args.config) { 0 } else { 1 });
     }
 
-    fn stop(self : &Self) ->  Result<(), Box<dyn std::error::Error>>
+    if let Some(Commands::Send { text, host, target, priority, topic, voice, volume, wait, token }) = &args.command
     {
-        self.do_run(vec!["stop", "-u", self.target_uid.as_str()])?;
-        Ok(())
-    }
-
-    fn new(uid:String, url : String) ->  Result<Caster, Box<dyn std::error::Error>>
-    {
-        Caster::has_go_chromecast()?;
-        Ok(Caster{
-            target_uid : uid,
-            url : url
-        })
-    }
-
-}
-
-
-
-/**********************************
- * DEFINE THE NOTIFICATION DAEMON *
- **********************************/
-
-struct Notifyd
-{
-    port : u32,
-    target_uuid : String,
-    tts : TTS,
-    sound : Option<Soloud>
-}
-#[derive(Serialize)]
-struct ProtoResponse
-{
-    success: bool,
-    reason : String,
-    err : String
-}
+        let body = serde_json::json!({
+            "text" : text, "target" : target, "priority" : priority, "topic" : topic, "voice" : voice, "volume" : volume, "wait" : wait
+        });
 
-impl Notifyd
-{
-    fn new( port : u32, target_uuid : String, lang : Option<String>) ->  Result<Notifyd, Box<dyn std::error::Error>>
-    {
-        let sl;
+        let token = token.clone().or(std::env::var("NOTIFYD_TOKEN").ok());
 
-        match Soloud::default() {
-            Ok(a) => {sl = Some(a);},
+        match client::send(&client::base_url(host, args.port), token.as_deref(), &body) {
+            Ok(reason) => println!("{}", reason),
             Err(e) => {
-                println!("Failed to start audio playback : {}", e);
-                sl = None;
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
 
-        Ok(
-            Notifyd{
-                port : port,
-                tts : TTS::new(TTSEngine::AUTO, lang)?,
-                target_uuid : target_uuid,
-                sound: sl
-            }
-        )
+        return Ok(());
     }
 
-    fn error_response(reason : &str, err : Box<dyn std::error::Error>) -> Response
-    {
-        Response::json(&ProtoResponse{
-            success : false,
-            reason : reason.to_string(),
-            err : err.to_string()
-        }).with_status_code(400)
-    }
+    let config = match args.config {
+        Some(p) => Config::load(&p)?,
+        None => Config::default()
+    };
 
-    fn success_response(reason : &str) -> Response
-    {
-        Response::json(&ProtoResponse{
-            success : true,
-            reason : reason.to_string(),
-            err : "".to_string()
-        })
-    }
+    let mut mounted = Vec::new();
+    let mut own_ports = Vec::new();
 
-    fn do_tts(self : & Self, text : String)  -> Response
+    for (settings, inst_config) in config.instance_configs()?
     {
-        let sentence: Result<TtsSentence, Box<dyn Error>> = self.tts.speak_to_file(text);
-
-        match self.sound {
-            Some(ref e) => {
-                match sentence {
-                    Ok(a) => {
-                        match a.play(&e)
-                        {
-                            Ok(()) => {
-                                return Notifyd::success_response("Done emitting requested text");
-                            },
-                            Err(e) => {
-                                return Notifyd::error_response("Failed playing text", e);
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        Notifyd::error_response("Failed to generate TTS from text", err)
-                    }
-                }
-            },
-            None => {
-                return Notifyd::error_response("Failed to load sound device", NotifydError::new("No sound"));
-            }
-        }
-
-    }
+        let port = settings.port.unwrap_or(args.port);
+        tracing::info!("Starting instance {}", settings.name);
 
-    fn handle_tts_request(self : & Self, request : &Request) -> Response
-    {
-        #[derive(Deserialize)]
-        struct Json {
-            text: String,
-        }
+        let mut inst = Notifyd::new(port,
+                                    settings.target.unwrap_or(args.chromecast_uuid.clone()),
+                                    settings.lang.or(args.lang.clone()),
+                                    inst_config)?;
 
-        let json : Json;
-        match rouille::input::json_input(request)
+        if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key)
         {
-            Ok(a) => {
-                json = a;
-            }
-            Err(e) =>{
-                return Notifyd::error_response("Bad arguments", Box::new(e));
-            }
+            inst.load_tls(cert, key)?;
         }
 
-        self.do_tts(json.text)
-    }
-
-    fn handle_static_req(self : & Self, request : &Request) -> Response
-    {
-        let raw_url = request.url();
-
-        if !raw_url.starts_with("/static/")
+        if port == args.port
         {
-            panic!("handle_static_req to be called only on static requests");
+            inst.mount = format!("/{}", settings.name);
+            mounted.push(Arc::new(inst));
         }
-
-        let target_path: PathBuf = self.tts.tmpdir.path().join(&raw_url["/static/".len()..]);
-
-        if !target_path.is_file()
+        else
         {
-            return Response::empty_404();
-        }
-
-        match File::open(&target_path){
-            Ok(f) => {
-                Response::from_file("audio/wav", f)
-            }
-            Err(e) => {
-                Notifyd::error_response(format!("Sending static file {}",
-                                                  target_path.as_path().to_string_lossy()).as_str(),
-                                   Box::new(e))
-            }
+            let inst = Arc::new(inst);
+            own_ports.push(std::thread::spawn(move || {
+                Notifyd::run(inst);
+            }));
         }
     }
 
-    fn sentence_static_url(self : & Self, sentence : TtsSentence) -> String
-    {
-        use local_ip_address::local_ip;
-        let fpath;
-        match  Path::new(&sentence.path).file_name() {
-            Some(p) => {
-                fpath = String::from(p.to_string_lossy());
-            }
-            None => {
-                fpath = String::from("");
-            }
-        }
-
-        let my_local_ip = local_ip().unwrap();
-        format!("http://{}:{}/static/{}", my_local_ip, self.port, fpath)
-    }
+    let mut server = Notifyd::new(args.port, args.chromecast_uuid, args.lang, config)?;
+    server.instances = mounted;
 
-    fn do_bcast(self : & Self, text : String, uid : String) -> Response
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key)
     {
-        let sentence : TtsSentence;
-
-        match self.tts.speak_to_file(text) {
-            Ok(s) => {
-                sentence = s;
-            },
-            Err(e) => {
-                return Notifyd::error_response("Failed to generate TTS", e);
-            }
-        }
-
-        let url = self.sentence_static_url(sentence);
-
-        match Caster::new(uid, url) {
-            Ok(c) => {
-                match c.load() {
-                    Ok(()) => {
-                        return Notifyd::success_response("Content casted");
-                    }
-                    Err(e) => {
-                        return Notifyd::error_response("Failed to cast content", e);
-                    }
-                }
-            },
-            Err(e) => {
-                return Notifyd::error_response("Failed start cast", e);
-            }
-        }
-    }
-
-    fn handle_bcast_req(self : & Self, request : &Request) -> Response
-    {
-        #[derive(Deserialize)]
-        struct Json {
-            text: String,
-            uid : String
-        }
-
-        let json : Json;
-        match rouille::input::json_input(request)
-        {
-            Ok(a) => {
-                json = a;
-            }
-            Err(e) =>{
-                return Notifyd::error_response("Bad arguments", Box::new(e));
-            }
-        }
-
-        self.do_bcast(json.text, json.uid)
+        server.load_tls(cert, key)?;
     }
 
-    fn handle_notify_req(self : &Self, request : &Request)  -> Response
-    {
-        #[derive(Deserialize)]
-        struct Json {
-            text: String,
-        }
-
-        let json : Json;
-        match rouille::input::json_input(request)
-        {
-            Ok(a) => {
-                json = a;
-            }
-            Err(e) =>{
-                return Notifyd::error_response("Bad arguments", Box::new(e));
-            }
-        }
-
-        if self.target_uuid == "Use Local Speaker"
-        {
-            self.do_tts(json.text)
-        }
-        else
-        {
-            self.do_bcast(json.text, self.target_uuid.to_string())
-        }
-    }
-
-    fn route_request(self : &Self, request : &Request) -> Response
-    {
-        let url = request.url();
-        //println!("Request to {}", url);
-        match url.as_str()
-        {
-            "/action/speak" => {
-                self.handle_tts_request(request)
-            },
-            "/action/cast" => {
-                self.handle_bcast_req(request)
-            },
-            "/notify" => {
-                self.handle_notify_req(request)
-            }
-            v => {
-                // The case of static files
-                if v.starts_with("/static/")
-                {
-                    return self.handle_static_req(request)
-                }
-
-                return Notifyd::error_response("No such endpoint",
-                                     NotifydError::new(format!("No endpoint {}", v).as_str()));
-            }
-        }
-
-    }
+    Notifyd::run(Arc::new(server));
 
-    fn run(self : Arc<Self>)
+    /* Instances on their own port drain their queue too before the process exits */
+    for inst in own_ports
     {
-        let me = Arc::clone(&self);
-        rouille::start_server(format!("0.0.0.0:{}",me.port), move |request| {
-            me.route_request(request)
-        });
+        let _ = inst.join();
     }
-}
-
-/*****************
- * CLI ARGUMENTS *
- *****************/
-
- #[derive(Parser,Debug)]
- struct Cli {
-     /// The UID of the chromecast to target
-     #[clap(short, long, default_value = "Use Local Speaker")]
-     chromecast_uuid : String,
-     /// The port of the webserver
-     #[clap(short, long, default_value_t = 8090)]
-     port : u32,
-     /// Language to use for TTS
-     #[clap(short, long)]
-     lang : Option<String>,
- }
-
-/*******************
- * DEFINE THE MAIN *
- *******************/
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-
-    let args = Cli::parse();
-
-    let server = Notifyd::new(args.port, args.chromecast_uuid, args.lang)?;
-
-    Notifyd::run(Arc::new(server));
 
     Ok(())
 }